@@ -1,10 +1,17 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 
-use arp_scan::{vendor, vendor::Vendor};
+use arp_scan::{args, network, vendor, vendor::Vendor};
+use arp_scan::network::{FrameTemplate, ScanLane};
+use pnet::datalink::{DataLinkSender, NetworkInterface};
 use pnet::util::MacAddr;
+use std::io;
+use std::net::Ipv4Addr;
 use std::str::FromStr;
+use std::sync::Arc;
 use regex::{Captures, Regex};
 
+const BENCH_REQUEST_COUNT: u32 = 4096;
+
 fn benchmark_load_bin(c: &mut Criterion) {
     c.bench_function("Load and parse ieee-oui BIN", |b| b.iter(|| {
         Vendor::new()
@@ -54,5 +61,74 @@ fn benchmark_find_mac(c: &mut Criterion) {
     }));
 }
 
-criterion_group!(benches, benchmark_load_bin, benchmark_load_csv, benchmark_find_mac);
+// A datalink sender discarding all frames, this allows to measure the frame
+// building cost without any network (or root permissions)
+struct NullSender {
+    buffer: Vec<u8>,
+    sent_bytes: usize
+}
+
+impl DataLinkSender for NullSender {
+
+    fn build_and_send(&mut self, num_packets: usize, packet_size: usize, func: &mut dyn FnMut(&mut [u8])) -> Option<io::Result<()>> {
+        for _ in 0..num_packets {
+            func(&mut self.buffer[..packet_size]);
+            self.sent_bytes += packet_size;
+        }
+        Some(Ok(()))
+    }
+
+    fn send_to(&mut self, packet: &[u8], _dst: Option<NetworkInterface>) -> Option<io::Result<()>> {
+        self.sent_bytes += packet.len();
+        Some(Ok(()))
+    }
+}
+
+fn benchmark_send_requests(c: &mut Criterion) {
+    let matches = args::build_args().get_matches_from(vec!["arp-scan"]);
+    let options = args::ScanOptions::new(&matches);
+
+    let interface = NetworkInterface {
+        name: "bench0".to_string(),
+        description: "".to_string(),
+        index: 1,
+        mac: Some(MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01)),
+        ips: vec![],
+        flags: 0
+    };
+    let lane = ScanLane {
+        vlan_id: None,
+        vlan_tags: vec![],
+        ip_networks: vec![],
+        target_ranges: vec![],
+        source_ip: Ipv4Addr::new(10, 0, 0, 1),
+        source_mac: interface.mac.unwrap()
+    };
+    let targets: Vec<Ipv4Addr> = (0..BENCH_REQUEST_COUNT).map(|index| Ipv4Addr::from(0x0a00_0000 + index)).collect();
+
+    let mut tx: Box<dyn DataLinkSender> = Box::new(NullSender { buffer: vec![0u8; network::DATALINK_SEND_BUFFER], sent_bytes: 0 });
+
+    let mut group = c.benchmark_group("Send ARP requests");
+    group.throughput(Throughput::Elements(targets.len() as u64));
+
+    group.bench_function("Build each frame", |b| b.iter(|| {
+        for target_ip in &targets {
+            network::send_arp_request(&mut tx, &interface, &lane, *target_ip, MacAddr::broadcast(), Arc::clone(&options));
+        }
+    }));
+
+    // Batches are sized as in a scan with the default options
+    let template = FrameTemplate::new(&interface, &lane, MacAddr::broadcast(), &options);
+    let batch_size = network::compute_batch_capacity(&options);
+    group.bench_function("Frame template with batches", |b| b.iter(|| {
+        let batch: Vec<(&FrameTemplate, Ipv4Addr)> = targets.iter().map(|target_ip| (&template, *target_ip)).collect();
+        for request_batch in batch.chunks(batch_size) {
+            network::send_arp_batch(&mut tx, request_batch);
+        }
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_load_bin, benchmark_load_csv, benchmark_find_mac, benchmark_send_requests);
 criterion_main!(benches);
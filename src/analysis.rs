@@ -0,0 +1,233 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use pnet_datalink::MacAddr;
+
+use crate::args::ScanOptions;
+use crate::network::{TargetDetails, TargetFlag, PROMISC_PROBES};
+
+/**
+ * Flag all MAC addresses answering for many IPv4 addresses in the same VLAN
+ * (and on the same interface).
+ * This usually happens with proxy-ARP routers, load-balancers or firewalls
+ * answering on behalf of other hosts. When requested, all hosts sharing a
+ * flagged MAC are collapsed into a single row (the lowest IPv4 is kept).
+ */
+pub fn flag_shared_macs(target_details: Vec<TargetDetails>, options: &ScanOptions) -> Vec<TargetDetails> {
+
+    if options.proxy_threshold == 0 {
+        return target_details;
+    }
+
+    let mut mac_counts: HashMap<(Option<String>, Option<u16>, MacAddr), usize> = HashMap::new();
+    for detail in target_details.iter() {
+        *mac_counts.entry((detail.interface_name.clone(), detail.vlan_id, detail.mac)).or_insert(0) += 1;
+    }
+
+    let mut collapsed_macs: HashMap<(Option<String>, Option<u16>, MacAddr), TargetDetails> = HashMap::new();
+    let mut flagged_details: Vec<TargetDetails> = vec![];
+
+    for mut detail in target_details.into_iter() {
+
+        let mac_key = (detail.interface_name.clone(), detail.vlan_id, detail.mac);
+        let ip_count = mac_counts.get(&mac_key).copied().unwrap_or(1);
+
+        if ip_count < options.proxy_threshold {
+            flagged_details.push(detail);
+            continue;
+        }
+
+        detail.flags.push(TargetFlag::SharedMac(ip_count));
+
+        if !options.collapse_proxy {
+            flagged_details.push(detail);
+            continue;
+        }
+
+        let is_lowest_ipv4 = collapsed_macs.get(&mac_key).map(|kept_detail| detail.ipv4 < kept_detail.ipv4).unwrap_or(true);
+        if is_lowest_ipv4 {
+            collapsed_macs.insert(mac_key, detail);
+        }
+    }
+
+    flagged_details.extend(collapsed_macs.into_values());
+    flagged_details
+}
+
+/**
+ * List all shared MAC addresses found in the scan results, with the number
+ * of IPv4 addresses each MAC is answering for. A MAC is counted separately on
+ * each interface and VLAN, as done when flagging shared MACs.
+ */
+pub fn list_shared_macs(target_details: &[TargetDetails]) -> Vec<(Option<String>, Option<u16>, MacAddr, usize)> {
+
+    let mut shared_macs: Vec<(Option<String>, Option<u16>, MacAddr, usize)> = vec![];
+    for detail in target_details.iter() {
+
+        let shared_ip_count = detail.flags.iter().find_map(|flag| match flag {
+            TargetFlag::SharedMac(ip_count) => Some(*ip_count),
+            _ => None
+        });
+
+        if let Some(ip_count) = shared_ip_count {
+
+            let is_known = shared_macs.iter().any(|(interface_name, vlan_id, mac, _)| {
+                *interface_name == detail.interface_name && *vlan_id == detail.vlan_id && *mac == detail.mac
+            });
+            if !is_known {
+                shared_macs.push((detail.interface_name.clone(), detail.vlan_id, detail.mac, ip_count));
+            }
+        }
+    }
+
+    shared_macs.sort_by_key(|(_, _, _, ip_count)| Reverse(*ip_count));
+    shared_macs
+}
+
+/**
+ * List all hosts that answered at least one promiscuous probe (ARP requests
+ * sent to bogus MAC addresses), with the names of all answered probes.
+ */
+pub fn list_promiscuous_hosts(target_details: &[TargetDetails]) -> Vec<(Ipv4Addr, MacAddr, Vec<&'static str>)> {
+
+    target_details.iter().filter_map(|detail| {
+
+        let probe_names: Vec<&'static str> = detail.flags.iter().filter_map(|flag| match flag {
+            TargetFlag::PromiscuousProbe(probe_index) => Some(PROMISC_PROBES[*probe_index].0),
+            _ => None
+        }).collect();
+
+        match probe_names.is_empty() {
+            true => None,
+            false => Some((detail.ipv4, detail.mac, probe_names))
+        }
+
+    }).collect()
+}
+
+/**
+ * List all hosts with different Ethernet source and ARP sender addresses in
+ * at least one response, as (IPv4, ARP sender MAC, Ethernet source MAC).
+ */
+pub fn list_mac_mismatches(target_details: &[TargetDetails]) -> Vec<(Ipv4Addr, MacAddr, MacAddr)> {
+
+    target_details.iter()
+        .filter(|detail| detail.flags.contains(&TargetFlag::MacMismatch))
+        .map(|detail| (detail.ipv4, detail.mac, detail.ethernet_mac))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::args::build_args;
+
+    fn build_target(last_byte: u8, mac: MacAddr) -> TargetDetails {
+
+        TargetDetails::for_test(Ipv4Addr::new(192, 168, 1, last_byte), mac)
+    }
+
+    fn build_targets() -> Vec<TargetDetails> {
+
+        let router_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+        let host_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x02);
+
+        vec![
+            build_target(30, router_mac),
+            build_target(10, router_mac),
+            build_target(20, router_mac),
+            build_target(40, host_mac)
+        ]
+    }
+
+    #[test]
+    fn should_flag_shared_macs() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--proxy-threshold", "3"]);
+        let options = ScanOptions::new(&matches);
+
+        let target_details = flag_shared_macs(build_targets(), &options);
+
+        let flagged_count = target_details.iter().filter(|detail| detail.flags.contains(&TargetFlag::SharedMac(3))).count();
+        assert_eq!(target_details.len(), 4);
+        assert_eq!(flagged_count, 3);
+        assert_eq!(list_shared_macs(&target_details).len(), 1);
+    }
+
+    #[test]
+    fn should_list_shared_macs_per_interface() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--proxy-threshold", "2"]);
+        let options = ScanOptions::new(&matches);
+
+        let mut target_details = build_targets();
+        target_details[0].interface_name = Some("eth1".to_string());
+        target_details.push(TargetDetails { interface_name: Some("eth1".to_string()), ..build_target(50, target_details[0].mac) });
+
+        let shared_macs = list_shared_macs(&flag_shared_macs(target_details, &options));
+
+        let router_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+        assert_eq!(shared_macs.len(), 2);
+        assert!(shared_macs.contains(&(None, None, router_mac, 2)));
+        assert!(shared_macs.contains(&(Some("eth1".to_string()), None, router_mac, 2)));
+    }
+
+    #[test]
+    fn should_ignore_macs_below_threshold() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--proxy-threshold", "4"]);
+        let options = ScanOptions::new(&matches);
+
+        let target_details = flag_shared_macs(build_targets(), &options);
+
+        assert!(target_details.iter().all(|detail| detail.flags.is_empty()));
+    }
+
+    #[test]
+    fn should_collapse_shared_macs() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--proxy-threshold", "3", "--collapse-proxy"]);
+        let options = ScanOptions::new(&matches);
+
+        let mut target_details = flag_shared_macs(build_targets(), &options);
+        target_details.sort_by_key(|detail| detail.ipv4);
+
+        assert_eq!(target_details.len(), 2);
+        assert_eq!(target_details[0].ipv4, Ipv4Addr::new(192, 168, 1, 10));
+        assert_eq!(target_details[0].flags, vec![TargetFlag::SharedMac(3)]);
+    }
+
+    #[test]
+    fn should_list_promiscuous_hosts() {
+
+        let mut target_details = build_targets();
+        target_details[3].flags.push(TargetFlag::PromiscuousProbe(0));
+        target_details[3].flags.push(TargetFlag::PromiscuousProbe(3));
+
+        let promiscuous_hosts = list_promiscuous_hosts(&target_details);
+
+        assert_eq!(promiscuous_hosts.len(), 1);
+        assert_eq!(promiscuous_hosts[0].0, Ipv4Addr::new(192, 168, 1, 40));
+        assert_eq!(promiscuous_hosts[0].2, vec!["B47", "Gr"]);
+    }
+
+    #[test]
+    fn should_list_mac_mismatches() {
+
+        let mut target_details = build_targets();
+        target_details[0].ethernet_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x09);
+        target_details[0].flags.push(TargetFlag::MacMismatch);
+
+        let mac_mismatches = list_mac_mismatches(&target_details);
+
+        assert_eq!(mac_mismatches, vec![(
+            Ipv4Addr::new(192, 168, 1, 30),
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01),
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x09)
+        )]);
+    }
+
+}
@@ -1,23 +1,41 @@
-use std::str::FromStr;
 use std::net::Ipv4Addr;
 use std::process;
 use std::sync::Arc;
 use std::path::Path;
 use std::fs;
+use std::io::{self, Read};
+use std::env;
 
 use clap::{Arg, ArgMatches, Command, ArgAction};
 use ipnetwork::IpNetwork;
 use pnet_datalink::MacAddr;
 use pnet::packet::arp::{ArpHardwareType, ArpOperation};
-use pnet::packet::ethernet::EtherType;
+use pnet::packet::ethernet::{EtherType, EtherTypes};
 
-use crate::time::parse_to_milliseconds;
+use crate::checkpoint::ScanCheckpoint;
+use crate::targets;
+use crate::template::{OutputTemplate, Template, TemplateEscape};
+use crate::time::{parse_to_milliseconds, parse_to_microseconds};
 
 const TIMEOUT_MS_FAST: u64 = 800;
 const TIMEOUT_MS_DEFAULT: u64 = 2000;
 
 const HOST_RETRY_DEFAULT: usize = 1;
-const REQUEST_MS_INTERVAL: u64 = 10;
+const REQUEST_US_INTERVAL: u64 = 10_000;
+const BURST_SIZE_DEFAULT: u32 = 1;
+
+const PROXY_THRESHOLD_DEFAULT: usize = 5;
+
+const HISTORY_SCAN_LIMIT: usize = 20;
+
+// Subnet discovery probes gateway-style addresses in each /24 by default,
+// with a few random hosts for subnets without a usual gateway address.
+const DISCOVERY_HOSTS_DEFAULT: [u8; 6] = [1, 2, 100, 250, 253, 254];
+const DISCOVERY_RANDOM_DEFAULT: usize = 4;
+
+const VLAN_PRIORITY_DEFAULT: u8 = 1;
+const VLAN_ID_MAX: u16 = 4095;
+const VLAN_PRIORITY_MAX: u8 = 7;
 
 const CLI_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -32,12 +50,59 @@ const EXAMPLES_HELP: &str = "EXAMPLES:
     # Launch a scan on a specific range
     arp-scan -i eth0 -n 10.37.3.1,10.37.4.55/24
 
+    # Launch a scan on a range without a few sensitive hosts
+    arp-scan -n 10.37.0.0/16 --exclude 10.37.0.1,10.37.8.0/24
+
+    # Find occupied subnets in a large private range
+    arp-scan -n 10.0.0.0/8 --discover --discover-random 2
+
+    # Launch a scan on two interfaces in parallel, with a range for the first
+    arp-scan -i eth0.10=10.0.10.0/24 -i eth0.20
+
     # Launch a scan on WiFi interface with fake IP and stealth profile
     arp-scan -i eth0 --source-ip 192.168.0.42 --profile stealth
 
+    # Resume a large scan interrupted with CTRL+C
+    arp-scan -n 10.0.0.0/16 --checkpoint scan.json
+    arp-scan -n 10.0.0.0/16 --resume scan.json
+
+    # Save each scan and find when a host was first and last seen
+    arp-scan -n 10.0.0.0/24 --store scans.db
+    arp-scan history --store scans.db --mac 00:16:f6:00:00:01
+
+    # Capture all scan frames for Wireshark
+    arp-scan -n 10.0.0.0/24 --pcap scan.pcapng
+
+    # Stream each discovered host as a JSON line
+    arp-scan -n 10.0.0.0/16 -o ndjson
+
+    # Replace the C arp-scan in scripts parsing its output
+    arp-scan --localnet --plain --ignoredups
+
+    # Generate dnsmasq static leases from the scan results
+    arp-scan -n 10.0.0.0/24 --format-template 'dhcp-host={mac},{ip},{hostname|host-unknown}'
+
     # Launch a scan on VLAN 45 with JSON output
     arp-scan -Q 45 -o json
 
+    # Display a table and archive the results in JSON and CSV files
+    arp-scan -n 10.0.0.0/24 -o json:scan.json -o csv:scan.csv
+
+    # Write an HTML report for an audit
+    arp-scan -n 10.0.0.0/24 -o html:report.html
+
+    # Launch a scan on VLAN 45 (priority 5) inside provider VLAN 300
+    arp-scan -Q 45:5 --outer-vlan 300
+
+    # Find hosts with a network card in promiscuous mode
+    arp-scan -n 192.168.1.0/24 --promisc-check
+
+    # Launch a scan on several VLANs of a trunk port
+    arp-scan -i eth0 -Q 10,20,100-120 --vlan-network 20=10.0.20.0/24@10.0.20.250
+
+    # Check a host list and stop as soon as all hosts have answered
+    arp-scan -f hosts.txt --adaptive
+
 ";
 
 /**
@@ -57,7 +122,14 @@ pub fn build_args() -> Command {
         .arg(
             Arg::new("interface").short('i').long("interface")
                 .value_name("INTERFACE_NAME")
-                .help("Network interface")
+                .action(ArgAction::Append)
+                .help("Network interface (INTERFACE[=RANGE], repeat for many)")
+        )
+        .arg(
+            Arg::new("all_interfaces").long("all-interfaces")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("interface")
+                .help("Scan all eligible network interfaces in parallel")
         )
         .arg(
             Arg::new("network").short('n').long("network")
@@ -70,11 +142,47 @@ pub fn build_args() -> Command {
                 .conflicts_with("network")
                 .help("Read IPv4 addresses from a file")
         )
+        .arg(
+            Arg::new("exclude").long("exclude")
+                .value_name("NETWORK_RANGE")
+                .help("Network range excluded from the scan")
+        )
+        .arg(
+            Arg::new("exclude_file").long("exclude-file")
+                .value_name("FILE_PATH")
+                .help("Read excluded IPv4 addresses from a file")
+        )
+        .arg(
+            Arg::new("include_broadcast").long("include-broadcast")
+                .action(ArgAction::SetTrue)
+                .help("Also scan network and broadcast addresses")
+        )
+        .arg(
+            Arg::new("discover").long("discover")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["checkpoint", "resume"])
+                .help("Probe samples in each /24 and scan occupied subnets")
+        )
+        .arg(
+            Arg::new("discover_hosts").long("discover-hosts")
+                .value_name("HOST_OCTETS")
+                .help("Host octets probed in each /24 for discovery")
+        )
+        .arg(
+            Arg::new("discover_random").long("discover-random")
+                .value_name("COUNT")
+                .help("Random hosts probed in each /24 for discovery")
+        )
         .arg(
             Arg::new("timeout").short('t').long("timeout")
                 .value_name("TIMEOUT_DURATION")
                 .help("ARP response timeout")
         )
+        .arg(
+            Arg::new("adaptive").long("adaptive")
+                .action(ArgAction::SetTrue)
+                .help("End the scan once responses stop (timeout is an upper bound)")
+        )
         .arg(
             Arg::new("source_ip").short('S').long("source-ip")
                 .value_name("SOURCE_IPV4")
@@ -97,8 +205,22 @@ pub fn build_args() -> Command {
         )
         .arg(
             Arg::new("vlan").short('Q').long("vlan")
-                .value_name("VLAN_ID")
-                .help("Send using 802.1Q with VLAN ID")
+                .value_name("VLAN_TAGS")
+                .help("Send using 802.1Q with VLAN tags (ID[:PCP[:DEI]], lists & ranges)")
+        )
+        .arg(
+            Arg::new("vlan_network").long("vlan-network")
+                .value_name("VLAN_NETWORK")
+                .action(ArgAction::Append)
+                .requires("vlan")
+                .help("Network range for a VLAN (ID=RANGE[@SOURCE_IPV4])")
+        )
+        .arg(
+            Arg::new("outer_vlan").long("outer-vlan")
+                .value_name("VLAN_TAG")
+                .action(ArgAction::Append)
+                .requires("vlan")
+                .help("Add an outer 802.1ad tag (QinQ), outermost first")
         )
         .arg(
             Arg::new("retry_count").short('r').long("retry")
@@ -110,10 +232,36 @@ pub fn build_args() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Randomize the target list")
         )
+        .arg(
+            Arg::new("seed").long("seed")
+                .value_name("SEED")
+                .help("Random seed for a reproducible target order (implies -R)")
+        )
+        .arg(
+            Arg::new("pcap").long("pcap")
+                .value_name("FILE_PATH")
+                .help("Capture all sent and received frames in a pcapng file")
+        )
+        .arg(
+            Arg::new("store").long("store")
+                .value_name("DB_PATH")
+                .help("Save the scan results in an SQLite database")
+        )
+        .arg(
+            Arg::new("checkpoint").long("checkpoint")
+                .value_name("FILE_PATH")
+                .help("Save the scan progress in a checkpoint file")
+        )
+        .arg(
+            Arg::new("resume").long("resume")
+                .value_name("FILE_PATH")
+                .conflicts_with("seed")
+                .help("Resume an interrupted scan from a checkpoint file")
+        )
         .arg(
             Arg::new("interval").short('I').long("interval")
                 .value_name("INTERVAL_DURATION")
-                .help("Milliseconds between ARP requests")
+                .help("Milliseconds between ARP requests (or 'us' suffix)")
         )
         .arg(
             Arg::new("bandwidth").short('B').long("bandwidth")
@@ -121,6 +269,17 @@ pub fn build_args() -> Command {
                 .conflicts_with("interval")
                 .help("Limit scan bandwidth (bits/second)")
         )
+        .arg(
+            Arg::new("rate").long("rate")
+                .value_name("PACKETS")
+                .conflicts_with_all(["interval", "bandwidth"])
+                .help("Limit scan rate (packets/second)")
+        )
+        .arg(
+            Arg::new("burst").long("burst")
+                .value_name("PACKETS")
+                .help("Maximum ARP requests sent in a single burst")
+        )
         .arg(
             Arg::new("oui-file").long("oui-file")
                 .value_name("FILE_PATH")
@@ -140,8 +299,83 @@ pub fn build_args() -> Command {
         )
         .arg(
             Arg::new("output").short('o').long("output")
-                .value_name("FORMAT")
-                .help("Define output format")
+                .value_name("FORMAT[:PATH]")
+                .action(ArgAction::Append)
+                .help("Define output format, optionally written to a file (repeatable)")
+        )
+        .arg(
+            Arg::new("output_file").long("output-file")
+                .value_name("PATH")
+                .help("Write the main output to a file instead of stdout")
+        )
+        .arg(
+            Arg::new("format_template").long("format-template")
+                .value_name("TEMPLATE")
+                .help("Main output with a line per host, e.g. '{ip} {mac} {vendor|unknown}'")
+        )
+        .arg(
+            Arg::new("format_header").long("format-header")
+                .value_name("TEMPLATE")
+                .requires("format_template")
+                .help("Header line of the template output")
+        )
+        .arg(
+            Arg::new("format_footer").long("format-footer")
+                .value_name("TEMPLATE")
+                .requires("format_template")
+                .help("Footer line of the template output")
+        )
+        .arg(
+            Arg::new("format_escape").long("format-escape")
+                .value_name("ESCAPE")
+                .requires("format_template")
+                .help("Escaping of template values (none/csv/json/shell)")
+        )
+        .arg(
+            Arg::new("sort").long("sort")
+                .value_name("KEY")
+                .help("Sort the plain output (ip/mac/vendor/hostname/rtt/discovery)")
+        )
+        .arg(
+            Arg::new("reverse").long("reverse")
+                .action(ArgAction::SetTrue)
+                .help("Reverse the sort order of the plain output")
+        )
+        .arg(
+            Arg::new("columns").long("columns")
+                .value_name("COLUMNS")
+                .help("Columns of the plain output, separated by commas")
+        )
+        .arg(
+            Arg::new("no_header").long("no-header")
+                .action(ArgAction::SetTrue)
+                .help("Hide the header of the plain output table")
+        )
+        .arg(
+            Arg::new("no_color").long("no-color")
+                .action(ArgAction::SetTrue)
+                .help("Disable colors in the plain output (also set by NO_COLOR)")
+        )
+        .arg(
+            Arg::new("localnet").long("localnet")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["network", "file"])
+                .help("Scan the local networks of the interface (classic arp-scan)")
+        )
+        .arg(
+            Arg::new("quiet").short('q').long("quiet")
+                .action(ArgAction::SetTrue)
+                .help("Only display IPv4 and MAC addresses (classic arp-scan)")
+        )
+        .arg(
+            Arg::new("plain").short('x').long("plain")
+                .action(ArgAction::SetTrue)
+                .help("Hide the header and footer lines (classic arp-scan)")
+        )
+        .arg(
+            Arg::new("ignoredups").short('g').long("ignoredups")
+                .action(ArgAction::SetTrue)
+                .help("Hide duplicate responses (classic arp-scan)")
         )
         .arg(
             Arg::new("hw_type").long("hw-type")
@@ -168,20 +402,189 @@ pub fn build_args() -> Command {
                 .value_name("OPERATION_ID")
                 .help("Custom ARP operation ID")
         )
+        .arg(
+            Arg::new("proxy_threshold").long("proxy-threshold")
+                .value_name("IP_COUNT")
+                .help("Flag MACs answering for many IPv4 (0 to disable)")
+        )
+        .arg(
+            Arg::new("collapse_proxy").long("collapse-proxy")
+                .action(ArgAction::SetTrue)
+                .help("Collapse flagged MACs into a single result row")
+        )
+        .arg(
+            Arg::new("promisc_check").long("promisc-check")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("destination_mac")
+                .help("Detect hosts in promiscuous mode with bogus MACs")
+        )
         .arg(
             Arg::new("packet_help").long("packet-help")
                 .action(ArgAction::SetTrue)
                 .exclusive(true)
                 .help("Print details about an ARP packet")
         )
+        .subcommand(
+            Command::new("history")
+                .about("Query the scan results saved with '--store'")
+                .arg(
+                    Arg::new("store").long("store")
+                        .value_name("DB_PATH")
+                        .required(true)
+                        .help("SQLite database of saved scans")
+                )
+                .arg(
+                    Arg::new("mac").long("mac")
+                        .value_name("MAC_ADDRESS")
+                        .help("Show when a MAC address was first and last seen")
+                )
+                .arg(
+                    Arg::new("ip").long("ip")
+                        .value_name("IPV4")
+                        .conflicts_with("mac")
+                        .help("Show when an IPv4 address was first and last seen")
+                )
+                .arg(
+                    Arg::new("limit").long("limit")
+                        .value_name("SCAN_COUNT")
+                        .conflicts_with_all(["mac", "ip"])
+                        .help("Number of past scans to list (default: 20)")
+                )
+        )
         .after_help(EXAMPLES_HELP)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
     Plain,
     Json,
     Yaml,
-    Csv
+    Csv,
+    Ndjson,
+    Xml,
+    Classic,
+    Html,
+    Template
+}
+
+impl OutputFormat {
+
+    pub fn parse(format_text: &str) -> Result<OutputFormat, String> {
+
+        match format_text {
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "plain" | "text" => Ok(OutputFormat::Plain),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "xml" => Ok(OutputFormat::Xml),
+            "classic" => Ok(OutputFormat::Classic),
+            "html" => Ok(OutputFormat::Html),
+            _ => Err(format!("Expected correct output format (json/yaml/csv/ndjson/xml/classic/html/plain), got {}", format_text))
+        }
+    }
+}
+
+/**
+ * An output written to a file once the scan is finished, in addition to (or
+ * instead of) the main output on stdout.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputTarget {
+    pub format: OutputFormat,
+    pub path: String
+}
+
+/**
+ * The sort order of plain results. Results are sorted by interface, VLAN and
+ * IPv4 by default, other keys are compared first with the same fallback.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Ip,
+    Mac,
+    Vendor,
+    Hostname,
+    Rtt,
+    Discovery
+}
+
+impl SortKey {
+
+    pub fn parse(key_text: &str) -> Result<SortKey, String> {
+
+        match key_text {
+            "ip" => Ok(SortKey::Ip),
+            "mac" => Ok(SortKey::Mac),
+            "vendor" => Ok(SortKey::Vendor),
+            "hostname" => Ok(SortKey::Hostname),
+            "rtt" => Ok(SortKey::Rtt),
+            "discovery" => Ok(SortKey::Discovery),
+            _ => Err(format!("Expected correct sort key (ip/mac/vendor/hostname/rtt/discovery), got {}", key_text))
+        }
+    }
+}
+
+/**
+ * A column of the plain output table.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableColumn {
+    Interface,
+    Vlan,
+    Ip,
+    Mac,
+    EthernetMac,
+    Hostname,
+    Vendor,
+    Rtt,
+    Notes
+}
+
+impl TableColumn {
+
+    pub fn parse(column_text: &str) -> Result<TableColumn, String> {
+
+        match column_text.trim() {
+            "interface" => Ok(TableColumn::Interface),
+            "vlan" => Ok(TableColumn::Vlan),
+            "ip" => Ok(TableColumn::Ip),
+            "mac" => Ok(TableColumn::Mac),
+            "ethernet-mac" => Ok(TableColumn::EthernetMac),
+            "hostname" => Ok(TableColumn::Hostname),
+            "vendor" => Ok(TableColumn::Vendor),
+            "rtt" => Ok(TableColumn::Rtt),
+            "notes" => Ok(TableColumn::Notes),
+            _ => Err(format!("Expected correct column (interface/vlan/ip/mac/ethernet-mac/hostname/vendor/rtt/notes), got {}", column_text))
+        }
+    }
+
+    /**
+     * Parse a comma-separated list of columns, in display order.
+     */
+    pub fn parse_list(columns_text: &str) -> Result<Vec<TableColumn>, String> {
+
+        let columns = columns_text.split(',')
+            .map(TableColumn::parse)
+            .collect::<Result<Vec<TableColumn>, String>>()?;
+
+        for (index, column) in columns.iter().enumerate() {
+            if columns[..index].contains(column) {
+                return Err(format!("Expected each column only once ({})", columns_text));
+            }
+        }
+        Ok(columns)
+    }
+}
+
+/**
+ * Colors are disabled with the '--no-color' flag or with a non-empty NO_COLOR
+ * environment variable (see https://no-color.org).
+ */
+pub fn is_color_enabled(no_color: bool) -> bool {
+
+    let has_no_color_variable = env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty());
+    !no_color && !has_no_color_variable
 }
 
 pub enum ProfileType {
@@ -191,52 +594,349 @@ pub enum ProfileType {
     Chaos
 }
 
+impl ProfileType {
+
+    pub fn label(&self) -> &str {
+
+        match self {
+            ProfileType::Default => "default",
+            ProfileType::Fast => "fast",
+            ProfileType::Stealth => "stealth",
+            ProfileType::Chaos => "chaos"
+        }
+    }
+}
+
+/**
+ * Scan timing constraints, either expressed as an interval between requests
+ * (in microseconds), a bandwidth (in bits per second) or a packet rate (in
+ * packets per second).
+ */
 pub enum ScanTiming {
     Interval(u64),
-    Bandwidth(u64)
+    Bandwidth(u64),
+    PacketRate(u64)
 }
 
+/**
+ * A single VLAN tag in the Ethernet frame. The tag protocol identifier will
+ * be 802.1Q for customer tags and 802.1ad for service tags (QinQ stacks).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    pub tag_protocol: EtherType,
+    pub vlan_id: u16,
+    pub priority: u8,
+    pub drop_eligible: bool
+}
+
+impl VlanTag {
+
+    /**
+     * Parse a VLAN tag with the 'ID[:PCP[:DEI]]' syntax, where the priority
+     * code point (PCP) and drop eligible indicator (DEI) fields are optional.
+     */
+    pub fn parse(tag_text: &str, tag_protocol: EtherType) -> Result<VlanTag, String> {
+
+        let mut fields = tag_text.split(':');
+
+        let vlan_id = match fields.next().map(|field| field.parse::<u16>()) {
+            Some(Ok(vlan_id)) if vlan_id <= VLAN_ID_MAX => vlan_id,
+            _ => return Err(format!("Expected valid VLAN identifier ({})", tag_text))
+        };
+
+        let priority = match fields.next().map(|field| field.parse::<u8>()) {
+            Some(Ok(priority)) if priority <= VLAN_PRIORITY_MAX => priority,
+            Some(_) => return Err(format!("Expected VLAN priority between 0 and 7 ({})", tag_text)),
+            None => VLAN_PRIORITY_DEFAULT
+        };
+
+        let drop_eligible = match fields.next() {
+            Some("0") | None => false,
+            Some("1") => true,
+            Some(_) => return Err(format!("Expected VLAN drop eligible indicator 0 or 1 ({})", tag_text))
+        };
+
+        if fields.next().is_some() {
+            return Err(format!("Expected VLAN tag with ID[:PCP[:DEI]] syntax ({})", tag_text));
+        }
+
+        Ok(VlanTag {
+            tag_protocol,
+            vlan_id,
+            priority,
+            drop_eligible
+        })
+    }
+
+    /**
+     * Parse a comma-separated list of VLAN tags. Each item may either be a
+     * single tag or a VLAN range ('100-120:5'), where the optional PCP & DEI
+     * fields are applied to every VLAN in the range.
+     */
+    pub fn parse_list(tags_text: &str, tag_protocol: EtherType) -> Result<Vec<VlanTag>, String> {
+
+        let mut vlan_tags: Vec<VlanTag> = vec![];
+        for item_text in tags_text.split(',') {
+
+            let (id_text, fields_text) = match item_text.find(':') {
+                Some(separator) => item_text.split_at(separator),
+                None => (item_text, "")
+            };
+
+            let (first_id, last_id) = match id_text.split_once('-') {
+                Some((first_text, last_text)) => {
+                    let first_tag = VlanTag::parse(first_text, tag_protocol)?;
+                    let last_tag = VlanTag::parse(last_text, tag_protocol)?;
+                    (first_tag.vlan_id, last_tag.vlan_id)
+                },
+                None => {
+                    let single_tag = VlanTag::parse(id_text, tag_protocol)?;
+                    (single_tag.vlan_id, single_tag.vlan_id)
+                }
+            };
+
+            if first_id > last_id {
+                return Err(format!("Expected ascending VLAN range ({})", item_text));
+            }
+
+            for vlan_id in first_id..=last_id {
+
+                let vlan_tag = VlanTag::parse(&format!("{}{}", vlan_id, fields_text), tag_protocol)?;
+                if !vlan_tags.iter().any(|known_tag| known_tag.vlan_id == vlan_id) {
+                    vlan_tags.push(vlan_tag);
+                }
+            }
+        }
+
+        Ok(vlan_tags)
+    }
+}
+
+/**
+ * Overrides the scanned network range (and optionally the ARP source IPv4)
+ * for a single VLAN. VLANs without override will use the global targets.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VlanNetwork {
+    pub vlan_id: u16,
+    pub network_range: Vec<IpNetwork>,
+    pub range_networks: Vec<IpNetwork>,
+    pub source_ipv4: Option<Ipv4Addr>
+}
+
+impl VlanNetwork {
+
+    /**
+     * Parse a VLAN network with the 'ID=RANGE[@SOURCE_IPV4]' syntax, where the
+     * range may contain multiple networks separated by commas.
+     */
+    pub fn parse(network_text: &str) -> Result<VlanNetwork, String> {
+
+        let (id_text, target_text) = network_text.split_once('=').ok_or_else(|| {
+            format!("Expected VLAN network with ID=RANGE[@SOURCE_IPV4] syntax ({})", network_text)
+        })?;
+
+        let vlan_id = VlanTag::parse(id_text, EtherTypes::Vlan)?.vlan_id;
+
+        let (range_text, source_ipv4) = match target_text.split_once('@') {
+            Some((range_text, source_text)) => {
+                let source_ipv4 = source_text.parse::<Ipv4Addr>().map_err(|_| {
+                    format!("Expected valid IPv4 as VLAN source IP ({})", network_text)
+                })?;
+                (range_text, Some(source_ipv4))
+            },
+            None => (target_text, None)
+        };
+
+        let (network_range, range_networks) = ScanOptions::compute_target_networks(None, Some(&range_text.to_string()))?.unwrap_or_default();
+
+        Ok(VlanNetwork {
+            vlan_id,
+            network_range,
+            range_networks,
+            source_ipv4
+        })
+    }
+}
+
+/**
+ * A network interface requested by the user, optionally with a dedicated
+ * network range. Interfaces without range will use the global targets.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceTarget {
+    pub interface_name: String,
+    pub network_range: Option<Vec<IpNetwork>>,
+    pub range_networks: Vec<IpNetwork>
+}
+
+impl InterfaceTarget {
+
+    /**
+     * Parse an interface target with the 'INTERFACE[=RANGE]' syntax, where the
+     * range may contain multiple networks separated by commas.
+     */
+    pub fn parse(target_text: &str) -> Result<InterfaceTarget, String> {
+
+        let (interface_name, network_range, range_networks) = match target_text.split_once('=') {
+            Some((interface_name, range_text)) => {
+                let (network_range, range_networks) = ScanOptions::compute_target_networks(None, Some(&range_text.to_string()))?.unwrap_or_default();
+                (interface_name, Some(network_range), range_networks)
+            },
+            None => (target_text, None, vec![])
+        };
+
+        if interface_name.is_empty() {
+            return Err(format!("Expected interface name with INTERFACE[=RANGE] syntax ({})", target_text));
+        }
+
+        Ok(InterfaceTarget {
+            interface_name: interface_name.to_string(),
+            network_range,
+            range_networks
+        })
+    }
+}
+
+/**
+ * A query of the 'history' command on the results store, either the past
+ * scans or the sightings of a single host.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HistoryQuery {
+    Scans(usize),
+    Mac(MacAddr),
+    Ip(Ipv4Addr)
+}
+
+impl HistoryQuery {
+
+    /**
+     * Build the history query from the arguments of the 'history' command,
+     * past scans are listed when no host is given.
+     */
+    pub fn new(matches: &ArgMatches) -> Result<HistoryQuery, String> {
+
+        if let Some(mac_text) = matches.get_one::<String>("mac") {
+            return mac_text.parse::<MacAddr>()
+                .map(HistoryQuery::Mac)
+                .map_err(|_| format!("Expected valid MAC address ({})", mac_text));
+        }
+
+        if let Some(ip_text) = matches.get_one::<String>("ip") {
+            return ip_text.parse::<Ipv4Addr>()
+                .map(HistoryQuery::Ip)
+                .map_err(|_| format!("Expected valid IPv4 address ({})", ip_text));
+        }
+
+        match matches.get_one::<String>("limit") {
+            Some(limit_text) => match limit_text.parse::<usize>() {
+                Ok(limit) if limit > 0 => Ok(HistoryQuery::Scans(limit)),
+                _ => Err(format!("Expected positive number of scans ({})", limit_text))
+            },
+            None => Ok(HistoryQuery::Scans(HISTORY_SCAN_LIMIT))
+        }
+    }
+}
+
+// A target specification with its line number (when read from a file)
+type TargetLine = (Option<usize>, String);
+
+// The requested networks, with the CIDR blocks expanded from target ranges
+type TargetNetworks = (Vec<IpNetwork>, Vec<IpNetwork>);
+
 pub struct ScanOptions {
     pub profile: ProfileType,
-    pub interface_name: Option<String>,
+    pub interface_targets: Vec<InterfaceTarget>,
+    pub all_interfaces: bool,
     pub network_range: Option<Vec<ipnetwork::IpNetwork>>,
+    pub range_networks: Vec<IpNetwork>,
+    pub excluded_networks: Vec<IpNetwork>,
+    pub skip_network_bounds: bool,
+    pub discover_subnets: bool,
+    pub discovery_hosts: Vec<u8>,
+    pub discovery_random_count: usize,
     pub timeout_ms: u64,
+    pub adaptive_timeout: bool,
     pub resolve_hostname: bool,
     pub source_ipv4: Option<Ipv4Addr>,
     pub source_mac: Option<MacAddr>,
     pub destination_mac: Option<MacAddr>,
-    pub vlan_id: Option<u16>,
+    pub outer_vlan_tags: Vec<VlanTag>,
+    pub vlan_tags: Vec<VlanTag>,
+    pub vlan_networks: Vec<VlanNetwork>,
     pub retry_count: usize,
     pub scan_timing: ScanTiming,
-    pub randomize_targets: bool,
+    pub burst_size: u32,
+    pub random_seed: Option<u64>,
+    pub pcap_file: Option<String>,
+    pub store_file: Option<String>,
+    pub checkpoint_file: Option<String>,
+    pub resume_checkpoint: Option<ScanCheckpoint>,
     pub output: OutputFormat,
+    pub sort_key: SortKey,
+    pub reverse_sort: bool,
+    pub table_columns: Option<Vec<TableColumn>>,
+    pub show_header: bool,
+    pub is_colored: bool,
+    pub classic_quiet: bool,
+    pub classic_plain: bool,
+    pub ignore_duplicates: bool,
+    pub output_template: Option<OutputTemplate>,
+    pub output_file: Option<String>,
+    pub output_targets: Vec<OutputTarget>,
     pub oui_file: String,
     pub hw_type: Option<ArpHardwareType>,
     pub hw_addr: Option<u8>,
     pub proto_type: Option<EtherType>,
     pub proto_addr: Option<u8>,
     pub arp_operation: Option<ArpOperation>,
+    pub proxy_threshold: usize,
+    pub collapse_proxy: bool,
+    pub promisc_check: bool,
     pub packet_help: bool,
 }
 
 impl ScanOptions {
 
-    fn list_required_networks(file_value: Option<&String>, network_value: Option<&String>) -> Result<Option<Vec<String>>, String> {
+    /**
+     * List all target specifications requested through a file or the CLI,
+     * each one with its line number when read from a file. Blank lines and
+     * comments (starting with '#') are ignored, the '-' path reads targets
+     * from the standard input.
+     */
+    fn list_required_networks(file_value: Option<&String>, network_value: Option<&String>) -> Result<Option<Vec<TargetLine>>, String> {
 
         let network_options = (file_value, network_value);
         match network_options {
             (Some(file_path), None) => {
 
-                let path = Path::new(file_path);
-                fs::read_to_string(path).map(|content| {
-                    Some(content.lines().map(|line| line.to_string()).collect())
-                }).map_err(|err| {
-                    format!("Could not open file {} - {}", file_path, err)
-                })
+                let content = match file_path.as_str() {
+                    "-" => {
+                        let mut content = String::new();
+                        io::stdin().read_to_string(&mut content).map(|_| content).map_err(|err| {
+                            format!("Could not read targets from standard input - {}", err)
+                        })
+                    },
+                    _ => fs::read_to_string(Path::new(file_path)).map_err(|err| {
+                        format!("Could not open file {} - {}", file_path, err)
+                    })
+                }?;
+
+                Ok(Some(content.lines().enumerate().filter_map(|(line_index, line)| {
+
+                    let target_text = line.split('#').next().unwrap_or_default().trim();
+                    match target_text.is_empty() {
+                        true => None,
+                        false => Some((Some(line_index + 1), target_text.to_string()))
+                    }
+
+                }).collect()))
 
             },
             (None, Some(raw_ranges)) => {
-                Ok(Some(raw_ranges.split(',').map(|line| line.to_string()).collect()))
+                Ok(Some(raw_ranges.split(',').map(|target_text| (None, target_text.trim().to_string())).collect()))
             },
             _ => Ok(None)
         }
@@ -245,54 +945,80 @@ impl ScanOptions {
     /**
      * Computes the whole network range requested by the user through CLI
      * arguments or files. This method will fail of a failure has been detected
-     * (either on the IO level or the network syntax parsing)
+     * (either on the IO level or the network syntax parsing). Target ranges and
+     * patterns are expanded in CIDR networks (see 'targets::parse_target').
      */
     fn compute_networks(file_value: Option<&String>, network_value: Option<&String>) -> Result<Option<Vec<IpNetwork>>, String> {
 
-        let required_networks: Option<Vec<String>> = ScanOptions::list_required_networks(file_value, network_value)?;
-        if required_networks.is_none() {
-            return Ok(None);
-        }
+        let target_networks = ScanOptions::compute_target_networks(file_value, network_value)?;
+        Ok(target_networks.map(|(networks, _)| networks))
+    }
 
-        let mut networks: Vec<IpNetwork> = vec![];
-        for network_text in required_networks.unwrap() {
+    /**
+     * Computes the requested network range like 'compute_networks', also
+     * returning the CIDR blocks expanded from target ranges and patterns. The
+     * network and broadcast addresses of these blocks should not be skipped.
+     */
+    fn compute_target_networks(file_value: Option<&String>, network_value: Option<&String>) -> Result<Option<TargetNetworks>, String> {
 
-            match IpNetwork::from_str(&network_text) {
-                Ok(parsed_network) => {
-                    networks.push(parsed_network);
-                    Ok(())
-                },
-                Err(err) => {
-                    Err(format!("Expected valid IPv4 network range ({})", err))
-                }
-            }?;
+        let required_networks = match ScanOptions::list_required_networks(file_value, network_value)? {
+            Some(required_networks) => required_networks,
+            None => return Ok(None)
+        };
+
+        let mut networks: Vec<IpNetwork> = vec![];
+        let mut range_networks: Vec<IpNetwork> = vec![];
+        for (line_number, network_text) in required_networks {
+
+            let parsed_networks = targets::parse_target(&network_text).map_err(|err| match line_number {
+                Some(line_number) => format!("Line {}: {}", line_number, err),
+                None => err
+            })?;
+            if targets::is_range_target(&network_text) {
+                range_networks.extend(parsed_networks.iter().copied());
+            }
+            networks.extend(parsed_networks);
         }
-        Ok(Some(networks))
+        Ok(Some((networks, range_networks)))
     }
 
     /**
      * Computes scan timing constraints, as requested by the user through CLI
      * arguments. The scan timing constraints will be either expressed in bandwidth
-     * (bits per second) or interval between ARP requests (in milliseconds).
+     * (bits per second), packet rate (packets per second) or interval between ARP
+     * requests (in microseconds).
      */
     fn compute_scan_timing(matches: &ArgMatches, profile: &ProfileType) -> ScanTiming {
 
-        match (matches.get_one::<String>("bandwidth"), matches.get_one::<String>("interval")) {
-            (Some(bandwidth_text), None) => {
+        let timing_options = (
+            matches.get_one::<String>("bandwidth"),
+            matches.get_one::<String>("interval"),
+            matches.get_one::<String>("rate")
+        );
+
+        match timing_options {
+            (Some(bandwidth_text), None, None) => {
                 let bits_second: u64 = bandwidth_text.parse().unwrap_or_else(|err| {
                     eprintln!("Expected positive number, {}", err);
                     process::exit(1);
                 });
                 ScanTiming::Bandwidth(bits_second)
             },
-            (None, Some(interval_text)) => parse_to_milliseconds(interval_text).map(ScanTiming::Interval).unwrap_or_else(|err| {
+            (None, Some(interval_text), None) => parse_to_microseconds(interval_text).map(ScanTiming::Interval).unwrap_or_else(|err| {
                 eprintln!("Expected correct interval, {}", err);
                 process::exit(1);
             }),
+            (None, None, Some(rate_text)) => {
+                let packets_second: u64 = rate_text.parse().unwrap_or_else(|err| {
+                    eprintln!("Expected positive number, {}", err);
+                    process::exit(1);
+                });
+                ScanTiming::PacketRate(packets_second)
+            },
             _ => match profile {
-                ProfileType::Stealth => ScanTiming::Interval(REQUEST_MS_INTERVAL * 2),
+                ProfileType::Stealth => ScanTiming::Interval(REQUEST_US_INTERVAL * 2),
                 ProfileType::Fast => ScanTiming::Interval(0),
-                _ => ScanTiming::Interval(REQUEST_MS_INTERVAL)
+                _ => ScanTiming::Interval(REQUEST_US_INTERVAL)
             }
         }
     }
@@ -321,16 +1047,64 @@ impl ScanOptions {
             None => ProfileType::Default
         };
 
-        let interface_name = matches.get_one::<String>("interface").cloned();
+        let interface_targets: Vec<InterfaceTarget> = matches.get_many::<String>("interface").unwrap_or_default()
+            .map(|target_text| InterfaceTarget::parse(target_text))
+            .collect::<Result<Vec<InterfaceTarget>, String>>()
+            .unwrap_or_else(|err| {
+                eprintln!("Could not compute requested network interfaces");
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+
+        let all_interfaces = matches.get_flag("all_interfaces");
 
         let file_option = matches.get_one::<String>("file");
         let network_option = matches.get_one::<String>("network");
 
-        let network_range = ScanOptions::compute_networks(file_option, network_option).unwrap_or_else(|err| {
+        let target_networks = ScanOptions::compute_target_networks(file_option, network_option).unwrap_or_else(|err| {
             eprintln!("Could not compute requested network range to scan");
             eprintln!("{}", err);
             process::exit(1);
         });
+        let (network_range, range_networks) = match target_networks {
+            Some((network_range, range_networks)) => (Some(network_range), range_networks),
+            None => (None, vec![])
+        };
+
+        // Excluded ranges may be given both on the command line and in a file,
+        // all of them are removed from the scan targets.
+        let excluded_networks: Vec<IpNetwork> = [
+            ScanOptions::compute_networks(None, matches.get_one::<String>("exclude")),
+            ScanOptions::compute_networks(matches.get_one::<String>("exclude_file"), None)
+        ].into_iter().flat_map(|excluded_networks| {
+
+            excluded_networks.unwrap_or_else(|err| {
+                eprintln!("Could not compute excluded network range");
+                eprintln!("{}", err);
+                process::exit(1);
+            }).unwrap_or_default()
+
+        }).collect();
+
+        let skip_network_bounds = !matches.get_flag("include_broadcast");
+
+        let discover_subnets = matches.get_flag("discover");
+
+        let discovery_hosts: Vec<u8> = match matches.get_one::<String>("discover_hosts") {
+            Some(hosts_text) => hosts_text.split(',').map(|host_text| host_text.trim().parse::<u8>()).collect::<Result<Vec<u8>, _>>().unwrap_or_else(|err| {
+                eprintln!("Expected valid host octets for subnet discovery ({})", err);
+                process::exit(1);
+            }),
+            None => DISCOVERY_HOSTS_DEFAULT.to_vec()
+        };
+
+        let discovery_random_count: usize = match matches.get_one::<String>("discover_random") {
+            Some(count_text) => count_text.parse::<usize>().unwrap_or_else(|err| {
+                eprintln!("Expected positive number of random discovery hosts ({})", err);
+                process::exit(1);
+            }),
+            None => DISCOVERY_RANDOM_DEFAULT
+        };
 
         let timeout_ms: u64 = match matches.get_one::<String>("timeout") {
             Some(timeout_text) => parse_to_milliseconds(timeout_text).unwrap_or_else(|err| {
@@ -343,9 +1117,26 @@ impl ScanOptions {
             }
         };
 
+        let adaptive_timeout = matches.get_flag("adaptive");
+
         // Hostnames will not be resolved in numeric mode or stealth profile
         let resolve_hostname = !matches.get_flag("numeric") && !matches!(profile, ProfileType::Stealth);
 
+        let sort_key = match matches.get_one::<String>("sort") {
+            Some(key_text) => SortKey::parse(key_text).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            }),
+            None => SortKey::Ip
+        };
+
+        let table_columns = matches.get_one::<String>("columns").map(|columns_text| {
+            TableColumn::parse_list(columns_text).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            })
+        });
+
         let source_ipv4: Option<Ipv4Addr> = match matches.get_one::<String>("source_ip") {
             Some(source_ip) => {
                 
@@ -388,20 +1179,39 @@ impl ScanOptions {
             None => None
         };
     
-        let vlan_id: Option<u16> = match matches.get_one::<String>("vlan") {
-            Some(vlan) => {
-    
-                match vlan.parse::<u16>() {
-                    Ok(vlan_number) => Some(vlan_number),
-                    Err(_) => {
-                        eprintln!("Expected valid VLAN identifier");
-                        process::exit(1);
-                    }
-                }
-            },
-            None => None
+        // Outer service tags (802.1ad) are listed first, the customer tags
+        // given with the '--vlan' option will always be the innermost tag.
+        // Each customer tag will be scanned with the same outer tag stack.
+        let outer_vlan_tags: Vec<VlanTag> = matches.get_many::<String>("outer_vlan").unwrap_or_default()
+            .map(|tag_text| VlanTag::parse(tag_text, EtherTypes::PBridge))
+            .collect::<Result<Vec<VlanTag>, String>>()
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+
+        let vlan_tags: Vec<VlanTag> = match matches.get_one::<String>("vlan") {
+            Some(tags_text) => VlanTag::parse_list(tags_text, EtherTypes::Vlan).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            }),
+            None => vec![]
         };
 
+        let vlan_networks: Vec<VlanNetwork> = matches.get_many::<String>("vlan_network").unwrap_or_default()
+            .map(|network_text| VlanNetwork::parse(network_text))
+            .collect::<Result<Vec<VlanNetwork>, String>>()
+            .unwrap_or_else(|err| {
+                eprintln!("Could not compute requested VLAN network range");
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+
+        if let Some(unknown_network) = vlan_networks.iter().find(|vlan_network| !vlan_tags.iter().any(|tag| tag.vlan_id == vlan_network.vlan_id)) {
+            eprintln!("VLAN {} has a network range but is not in the scanned VLAN list", unknown_network.vlan_id);
+            process::exit(1);
+        }
+
         let retry_count = match matches.get_one::<String>("retry_count") {
             Some(retry_count) => {
     
@@ -424,25 +1234,95 @@ impl ScanOptions {
 
         let scan_timing: ScanTiming = ScanOptions::compute_scan_timing(matches, &profile);
 
-        let output = match matches.get_one::<String>("output") {
-            Some(output_request) => {
+        let burst_size = match matches.get_one::<String>("burst") {
+            Some(burst_text) => {
 
-                match output_request.as_ref() {
-                    "json" => OutputFormat::Json,
-                    "yaml" => OutputFormat::Yaml,
-                    "plain" | "text" => OutputFormat::Plain,
-                    "csv" => OutputFormat::Csv,
+                match burst_text.parse::<u32>() {
+                    Ok(burst_size) if burst_size > 0 => burst_size,
                     _ => {
-                        eprintln!("Expected correct output format (json/yaml/plain)");
+                        eprintln!("Expected strictly positive number for burst size");
                         process::exit(1);
                     }
                 }
             },
-            None => OutputFormat::Plain
+            None => BURST_SIZE_DEFAULT
+        };
+
+        let output_requests: Vec<&String> = matches.get_many::<String>("output")
+            .map(|output_requests| output_requests.collect())
+            .unwrap_or_default();
+        let output_file = matches.get_one::<String>("output_file").cloned();
+
+        // Flags of the C arp-scan select the classic output by default, so
+        // that existing scripts parsing this output keep working.
+        let classic_quiet = matches.get_flag("quiet");
+        let classic_plain = matches.get_flag("plain");
+        let ignore_duplicates = matches.get_flag("ignoredups");
+        let output_template = ScanOptions::parse_output_template(matches).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        let default_output = match (&output_template, matches.get_flag("localnet") || classic_quiet || classic_plain || ignore_duplicates) {
+            (Some(_), _) => OutputFormat::Template,
+            (None, true) => OutputFormat::Classic,
+            (None, false) => OutputFormat::Plain
         };
+        let (output, output_targets) = ScanOptions::parse_outputs(&output_requests, output_file.as_ref(), default_output).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+
+        if output_template.is_some() && output != OutputFormat::Template {
+            eprintln!("Expected no other main output format with '--format-template'");
+            process::exit(1);
+        }
+
+        let has_classic_flags = classic_quiet || classic_plain || ignore_duplicates;
+        if has_classic_flags && output != OutputFormat::Classic {
+            eprintln!("Expected classic output with '--quiet', '--plain' or '--ignoredups'");
+            process::exit(1);
+        }
 
         let randomize_targets = matches.get_flag("random") || matches!(profile, ProfileType::Stealth | ProfileType::Chaos);
 
+        let resume_checkpoint = matches.get_one::<String>("resume").map(|resume_path| {
+            ScanCheckpoint::load(resume_path).unwrap_or_else(|err| {
+                eprintln!("Could not resume scan");
+                eprintln!("{}", err);
+                process::exit(1);
+            })
+        });
+
+        // Checkpoints are saved in the resumed file, unless another checkpoint
+        // file has been requested.
+        let checkpoint_file = matches.get_one::<String>("checkpoint")
+            .or_else(|| matches.get_one::<String>("resume"))
+            .cloned();
+
+        let pcap_file = matches.get_one::<String>("pcap").cloned();
+        let store_file = matches.get_one::<String>("store").cloned();
+
+        // A random seed is always picked for randomized scans, it will then be
+        // displayed to allow reproducing the same target order. A resumed scan
+        // continues with the seed of the interrupted scan.
+        let random_seed = match matches.get_one::<String>("seed") {
+            Some(seed_text) => {
+
+                match seed_text.parse::<u64>() {
+                    Ok(seed) => Some(seed),
+                    Err(_) => {
+                        eprintln!("Expected positive number for random seed");
+                        process::exit(1);
+                    }
+                }
+            },
+            None => match (&resume_checkpoint, randomize_targets) {
+                (Some(checkpoint), _) => checkpoint.random_seed,
+                (None, true) => Some(rand::random()),
+                (None, false) => None
+            }
+        };
+
         let oui_file: String = match matches.get_one::<String>("oui-file") {
             Some(file) => file.to_string(),
             None => "/usr/share/arp-scan/ieee-oui.csv".to_string()
@@ -518,40 +1398,193 @@ impl ScanOptions {
             None => None
         };
 
+        let proxy_threshold = match matches.get_one::<String>("proxy_threshold") {
+            Some(threshold_text) => {
+
+                match threshold_text.parse::<usize>() {
+                    Ok(threshold) => threshold,
+                    Err(_) => {
+                        eprintln!("Expected positive number for proxy threshold");
+                        process::exit(1);
+                    }
+                }
+            },
+            None => PROXY_THRESHOLD_DEFAULT
+        };
+
+        let collapse_proxy = matches.get_flag("collapse_proxy");
+
+        let promisc_check = matches.get_flag("promisc_check");
+
         let packet_help = matches.get_flag("packet_help");
     
         Arc::new(ScanOptions {
             profile,
-            interface_name,
+            interface_targets,
+            all_interfaces,
             network_range,
+            range_networks,
+            excluded_networks,
+            skip_network_bounds,
+            discover_subnets,
+            discovery_hosts,
+            discovery_random_count,
             timeout_ms,
+            adaptive_timeout,
             resolve_hostname,
             source_ipv4,
             destination_mac,
             source_mac,
-            vlan_id,
+            outer_vlan_tags,
+            vlan_tags,
+            vlan_networks,
             retry_count,
             scan_timing,
-            randomize_targets,
+            burst_size,
+            random_seed,
+            pcap_file,
+            store_file,
+            checkpoint_file,
+            resume_checkpoint,
             output,
+            sort_key,
+            reverse_sort: matches.get_flag("reverse"),
+            table_columns,
+            show_header: !matches.get_flag("no_header"),
+            is_colored: is_color_enabled(matches.get_flag("no_color")),
+            classic_quiet,
+            classic_plain,
+            ignore_duplicates,
+            output_template,
+            output_file,
+            output_targets,
             oui_file,
             hw_type,
             hw_addr,
             proto_type,
             proto_addr,
             arp_operation,
+            proxy_threshold,
+            collapse_proxy,
+            promisc_check,
             packet_help,
         })
     }
 
+    /**
+     * Parse the requested outputs, each with the 'FORMAT[:PATH]' syntax. The
+     * output without path is the main output (the default format otherwise),
+     * written on stdout unless an output file is given. All other outputs are
+     * files.
+     */
+    fn parse_outputs(output_requests: &[&String], output_file: Option<&String>, default_format: OutputFormat) -> Result<(OutputFormat, Vec<OutputTarget>), String> {
+
+        let mut main_format: Option<OutputFormat> = None;
+        let mut output_targets: Vec<OutputTarget> = vec![];
+
+        for output_request in output_requests.iter() {
+
+            match output_request.split_once(':') {
+                Some((format_text, path)) => {
+                    if path.is_empty() {
+                        return Err(format!("Expected output file path after format ({})", output_request));
+                    }
+                    output_targets.push(OutputTarget {
+                        format: OutputFormat::parse(format_text)?,
+                        path: path.to_string()
+                    });
+                },
+                None => {
+                    if main_format.is_some() {
+                        return Err("Expected a single output format without file path".to_string());
+                    }
+                    main_format = Some(OutputFormat::parse(output_request)?);
+                }
+            }
+        }
+
+        let main_format = main_format.unwrap_or(default_format);
+        if let Some(output_file) = output_file {
+            output_targets.push(OutputTarget {
+                format: main_format,
+                path: output_file.to_string()
+            });
+        }
+
+        for (index, output_target) in output_targets.iter().enumerate() {
+            if output_targets[..index].iter().any(|previous_target| previous_target.path == output_target.path) {
+                return Err(format!("Expected distinct output file paths ({} is used twice)", output_target.path));
+            }
+        }
+
+        Ok((main_format, output_targets))
+    }
+
+    /**
+     * Parse the user-defined output templates, the header and footer only
+     * being allowed with a host template.
+     */
+    fn parse_output_template(matches: &ArgMatches) -> Result<Option<OutputTemplate>, String> {
+
+        let host_text = match matches.get_one::<String>("format_template") {
+            Some(host_text) => host_text,
+            None => return Ok(None)
+        };
+
+        let header = matches.get_one::<String>("format_header").map(|header_text| Template::parse(header_text, false)).transpose()?;
+        let footer = matches.get_one::<String>("format_footer").map(|footer_text| Template::parse(footer_text, false)).transpose()?;
+        let escape = match matches.get_one::<String>("format_escape") {
+            Some(escape_text) => TemplateEscape::parse(escape_text)?,
+            None => TemplateEscape::Raw
+        };
+
+        Ok(Some(OutputTemplate {
+            header,
+            host: Template::parse(host_text, true)?,
+            footer,
+            escape
+        }))
+    }
+
+    /**
+     * The main output is written on stdout, unless it has been redirected to
+     * a file with '--output-file'.
+     */
+    pub fn has_stdout_output(&self) -> bool {
+
+        self.output_file.is_none()
+    }
+
     pub fn is_plain_output(&self) -> bool {
 
         matches!(&self.output, OutputFormat::Plain)
     }
 
+    pub fn is_multi_interface(&self) -> bool {
+
+        self.all_interfaces || self.interface_targets.len() > 1
+    }
+
     pub fn has_vlan(&self) -> bool {
 
-        matches!(&self.vlan_id, Some(_)) 
+        !self.vlan_tags.is_empty()
+    }
+
+    pub fn find_vlan_network(&self, vlan_id: u16) -> Option<&VlanNetwork> {
+
+        self.vlan_networks.iter().find(|vlan_network| vlan_network.vlan_id == vlan_id)
+    }
+
+    /**
+     * List all CIDR blocks expanded from target ranges and patterns, in the
+     * global targets, the interface targets and the VLAN networks.
+     */
+    pub fn list_range_networks(&self) -> Vec<IpNetwork> {
+
+        let interface_networks = self.interface_targets.iter().flat_map(|interface_target| interface_target.range_networks.iter());
+        let vlan_networks = self.vlan_networks.iter().flat_map(|vlan_network| vlan_network.range_networks.iter());
+
+        self.range_networks.iter().chain(interface_networks).chain(vlan_networks).copied().collect()
     }
 
     pub fn request_protocol_print(&self) -> bool {
@@ -659,6 +1692,41 @@ mod tests {
         assert_eq!(networks, Ok(Some(target_network)));
     }
 
+    #[test]
+    fn should_handle_target_ranges() {
+
+        let networks = ScanOptions::compute_networks(None, Some(&"10.0.0.0-10.0.1.255, 10.2.*.*".to_string()));
+
+        let target_network: Vec<IpNetwork> = vec![
+            IpNetwork::V4(
+                Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 23).unwrap()
+            ),
+            IpNetwork::V4(
+                Ipv4Network::new(Ipv4Addr::new(10, 2, 0, 0), 16).unwrap()
+            )
+        ];
+
+        assert_eq!(networks, Ok(Some(target_network)));
+    }
+
+    #[test]
+    fn should_skip_file_comments() {
+
+        let networks = ScanOptions::compute_networks(Some(&"./data/target-list.txt".to_string()), None).unwrap().unwrap();
+
+        assert_eq!(networks.len(), 5);
+        assert_eq!(networks[0], IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 1), 32).unwrap()));
+        assert_eq!(networks[4], IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 4, 0, 0), 16).unwrap()));
+    }
+
+    #[test]
+    fn should_cite_failing_file_line() {
+
+        let networks = ScanOptions::compute_networks(Some(&"./data/target-list-invalid.txt".to_string()), None);
+
+        assert_eq!(networks, Err("Line 4: Expected valid IPv4 network range (invalid address: 192.168.1.300)".to_string()));
+    }
+
     #[test]
     fn should_fail_incorrect_network() {
         
@@ -667,6 +1735,188 @@ mod tests {
         assert_eq!(networks, Err("Expected valid IPv4 network range (invalid address: 500.10.10.10/24)".to_string()));
     }
 
+    #[test]
+    fn should_parse_vlan_identifier() {
+
+        let vlan_tag = VlanTag::parse("45", EtherTypes::Vlan);
+
+        assert_eq!(vlan_tag, Ok(VlanTag {
+            tag_protocol: EtherTypes::Vlan,
+            vlan_id: 45,
+            priority: 1,
+            drop_eligible: false
+        }));
+    }
+
+    #[test]
+    fn should_parse_vlan_priority_and_dei() {
+
+        let vlan_tag = VlanTag::parse("300:5:1", EtherTypes::PBridge);
+
+        assert_eq!(vlan_tag, Ok(VlanTag {
+            tag_protocol: EtherTypes::PBridge,
+            vlan_id: 300,
+            priority: 5,
+            drop_eligible: true
+        }));
+    }
+
+    #[test]
+    fn should_fail_incorrect_vlan_tag() {
+
+        assert_eq!(VlanTag::parse("5000", EtherTypes::Vlan), Err("Expected valid VLAN identifier (5000)".to_string()));
+        assert_eq!(VlanTag::parse("45:8", EtherTypes::Vlan), Err("Expected VLAN priority between 0 and 7 (45:8)".to_string()));
+        assert_eq!(VlanTag::parse("45:5:2", EtherTypes::Vlan), Err("Expected VLAN drop eligible indicator 0 or 1 (45:5:2)".to_string()));
+    }
+
+    #[test]
+    fn should_parse_vlan_list_and_ranges() {
+
+        let vlan_tags = VlanTag::parse_list("10,20:5,100-102:3:1", EtherTypes::Vlan).unwrap();
+
+        let vlan_ids: Vec<u16> = vlan_tags.iter().map(|tag| tag.vlan_id).collect();
+        assert_eq!(vlan_ids, vec![10, 20, 100, 101, 102]);
+        assert_eq!(vlan_tags[1].priority, 5);
+        assert_eq!(vlan_tags[4].priority, 3);
+        assert!(vlan_tags[4].drop_eligible);
+    }
+
+    #[test]
+    fn should_fail_descending_vlan_range() {
+
+        assert_eq!(VlanTag::parse_list("120-100", EtherTypes::Vlan), Err("Expected ascending VLAN range (120-100)".to_string()));
+    }
+
+    #[test]
+    fn should_parse_vlan_network() {
+
+        let vlan_network = VlanNetwork::parse("20=10.0.20.0/24,10.0.21.1@10.0.20.250");
+
+        assert_eq!(vlan_network, Ok(VlanNetwork {
+            vlan_id: 20,
+            network_range: vec![
+                IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 20, 0), 24).unwrap()),
+                IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 21, 1), 32).unwrap())
+            ],
+            range_networks: vec![],
+            source_ipv4: Some(Ipv4Addr::new(10, 0, 20, 250))
+        }));
+    }
+
+    #[test]
+    fn should_parse_interface_target() {
+
+        assert_eq!(InterfaceTarget::parse("eth0.10"), Ok(InterfaceTarget {
+            interface_name: "eth0.10".to_string(),
+            network_range: None,
+            range_networks: vec![]
+        }));
+        assert_eq!(InterfaceTarget::parse("eth0.20=10.0.20.0/24"), Ok(InterfaceTarget {
+            interface_name: "eth0.20".to_string(),
+            network_range: Some(vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 20, 0), 24).unwrap())]),
+            range_networks: vec![]
+        }));
+    }
+
+    #[test]
+    fn should_fail_incorrect_interface_target() {
+
+        assert!(InterfaceTarget::parse("=10.0.20.0/24").is_err());
+        assert!(InterfaceTarget::parse("eth0=no-network").is_err());
+    }
+
+    #[test]
+    fn should_handle_many_interfaces() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-i", "eth0", "-i", "eth1=10.0.1.0/24"]);
+        let options = ScanOptions::new(&matches);
+
+        assert_eq!(options.interface_targets.len(), 2);
+        assert!(options.is_multi_interface());
+    }
+
+    #[test]
+    fn should_parse_excluded_networks() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--exclude", "192.168.1.1,192.168.1.128/25", "--exclude-file", "./data/ip-list.txt"]);
+        let options = ScanOptions::new(&matches);
+
+        assert_eq!(options.excluded_networks.len(), 5);
+        assert_eq!(options.excluded_networks[1], IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 128), 25).unwrap()));
+        assert!(options.skip_network_bounds);
+    }
+
+    #[test]
+    fn should_include_network_bounds() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--include-broadcast"]);
+        let options = ScanOptions::new(&matches);
+
+        assert!(options.excluded_networks.is_empty());
+        assert!(!options.skip_network_bounds);
+    }
+
+    #[test]
+    fn should_parse_file_outputs() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-o", "json:scan.json", "-o", "csv:/tmp/scan.csv"]);
+        let options = ScanOptions::new(&matches);
+
+        assert!(options.is_plain_output());
+        assert!(options.has_stdout_output());
+        assert_eq!(options.output_targets, vec![
+            OutputTarget { format: OutputFormat::Json, path: "scan.json".to_string() },
+            OutputTarget { format: OutputFormat::Csv, path: "/tmp/scan.csv".to_string() }
+        ]);
+    }
+
+    #[test]
+    fn should_redirect_main_output() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-o", "yaml", "--output-file", "scan.yaml", "-o", "plain:scan.txt"]);
+        let options = ScanOptions::new(&matches);
+
+        assert_eq!(options.output, OutputFormat::Yaml);
+        assert!(!options.has_stdout_output());
+        assert_eq!(options.output_targets.len(), 2);
+        assert_eq!(options.output_targets[1], OutputTarget { format: OutputFormat::Yaml, path: "scan.yaml".to_string() });
+    }
+
+    #[test]
+    fn should_fail_incorrect_outputs() {
+
+        let json_output = "json".to_string();
+        let csv_output = "csv".to_string();
+        let first_file_output = "json:scan.json".to_string();
+        let second_file_output = "csv:scan.json".to_string();
+
+        assert_eq!(ScanOptions::parse_outputs(&[&json_output, &csv_output], None, OutputFormat::Plain), Err("Expected a single output format without file path".to_string()));
+        assert_eq!(ScanOptions::parse_outputs(&[&first_file_output, &second_file_output], None, OutputFormat::Plain), Err("Expected distinct output file paths (scan.json is used twice)".to_string()));
+        assert!(ScanOptions::parse_outputs(&[&"pdf:scan.pdf".to_string()], None, OutputFormat::Plain).is_err());
+        assert!(ScanOptions::parse_outputs(&[&"json:".to_string()], None, OutputFormat::Plain).is_err());
+    }
+
+    #[test]
+    fn should_parse_table_columns() {
+
+        assert_eq!(TableColumn::parse_list("ip, mac,rtt"), Ok(vec![TableColumn::Ip, TableColumn::Mac, TableColumn::Rtt]));
+        assert_eq!(TableColumn::parse_list("ip,ip"), Err("Expected each column only once (ip,ip)".to_string()));
+        assert!(TableColumn::parse_list("ip,ttl").is_err());
+    }
+
+    #[test]
+    fn should_parse_table_settings() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--sort", "discovery", "--reverse", "--no-header", "--no-color"]);
+        let options = ScanOptions::new(&matches);
+
+        assert_eq!(options.sort_key, SortKey::Discovery);
+        assert!(options.reverse_sort);
+        assert!(!options.show_header);
+        assert!(!options.is_colored);
+        assert_eq!(options.table_columns, None);
+    }
+
     #[test]
     fn should_fail_unreadable_network() {
         
@@ -675,4 +1925,13 @@ mod tests {
         assert_eq!(networks, Err("Expected valid IPv4 network range (invalid address: no-network)".to_string()));
     }
 
+    #[test]
+    fn should_not_throttle_fast_profile() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--profile", "fast"]);
+        assert!(matches!(ScanOptions::compute_scan_timing(&matches, &ProfileType::Fast), ScanTiming::Interval(0)));
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "--profile", "fast", "--rate", "2000"]);
+        assert!(matches!(ScanOptions::compute_scan_timing(&matches, &ProfileType::Fast), ScanTiming::PacketRate(2000)));
+    }
 }
@@ -0,0 +1,306 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pnet_datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+
+// Block types and options of the pcapng format (see the IETF draft
+// 'draft-ietf-opsawg-pcapng'), frames are always Ethernet frames.
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const LINKTYPE_ETHERNET: u16 = 1;
+const OPTION_END: u16 = 0;
+const OPTION_INTERFACE_NAME: u16 = 2;
+const OPTION_TIMESTAMP_RESOLUTION: u16 = 9;
+
+// Timestamps are written with a nanosecond resolution (10^-9 seconds)
+const TIMESTAMP_RESOLUTION: u8 = 9;
+
+/**
+ * Writes all captured frames in a pcapng file, readable by Wireshark or
+ * tcpdump without any libpcap dependency. Each scanned interface has its own
+ * interface description, frames of all interfaces share the same file.
+ */
+pub struct PcapWriter {
+    file_path: String,
+    interface_names: Vec<String>,
+    writer: Mutex<BufWriter<File>>,
+    has_failed: AtomicBool
+}
+
+impl PcapWriter {
+
+    /**
+     * Create the pcapng file, with a section header and the description of
+     * all interfaces that will be scanned.
+     */
+    pub fn create(file_path: &str, interface_names: &[String]) -> Result<PcapWriter, String> {
+
+        let file = File::create(file_path).map_err(|err| {
+            format!("Could not create pcap file {} - {}", file_path, err)
+        })?;
+        let mut writer = BufWriter::new(file);
+
+        let mut header = build_section_header();
+        for interface_name in interface_names.iter() {
+            header.extend(build_interface_description(interface_name));
+        }
+        writer.write_all(&header).map_err(|err| {
+            format!("Could not write pcap file {} - {}", file_path, err)
+        })?;
+
+        Ok(PcapWriter {
+            file_path: file_path.to_string(),
+            interface_names: interface_names.to_vec(),
+            writer: Mutex::new(writer),
+            has_failed: AtomicBool::new(false)
+        })
+    }
+
+    /**
+     * Append a frame to the capture file. A write failure does not stop the
+     * scan, a single warning is displayed and the next frames are dropped.
+     */
+    pub fn write_frame(&self, interface_id: u32, timestamp: SystemTime, frame: &[u8]) {
+
+        if self.has_failed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let packet_block = build_enhanced_packet(interface_id, timestamp, frame);
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = writer.write_all(&packet_block) {
+            self.report_failure(err);
+        }
+    }
+
+    /**
+     * Flush all buffered frames, once the scan is over.
+     */
+    pub fn flush(&self) -> Result<(), String> {
+
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writer.flush().map_err(|err| {
+            format!("Could not write pcap file {} - {}", self.file_path, err)
+        })
+    }
+
+    fn find_interface_id(&self, interface_name: &str) -> Option<u32> {
+
+        self.interface_names.iter()
+            .position(|name| name == interface_name)
+            .map(|position| position as u32)
+    }
+
+    fn report_failure(&self, err: io::Error) {
+
+        if !self.has_failed.swap(true, Ordering::Relaxed) {
+            eprintln!("[warn] Could not write pcap file {}, capture stopped ({})", self.file_path, err);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub type TimestampReader = crate::socket::TimestampReader;
+
+#[cfg(not(target_os = "linux"))]
+pub struct TimestampReader;
+
+#[cfg(not(target_os = "linux"))]
+impl TimestampReader {
+    pub fn read(&self) -> io::Result<SystemTime> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "kernel timestamps are not supported"))
+    }
+}
+
+/**
+ * Wrap a datalink channel so that all sent and received frames are written
+ * in the capture file. Received frames use the kernel timestamp when the
+ * socket gives one, other frames are timestamped in userspace.
+ */
+pub fn capture_channel(tx: Box<dyn DataLinkSender>, rx: Box<dyn DataLinkReceiver>, interface: &NetworkInterface, pcap_writer: &Arc<PcapWriter>, timestamp_reader: Option<TimestampReader>) -> (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>) {
+
+    let interface_id = pcap_writer.find_interface_id(&interface.name).unwrap_or_else(|| {
+        eprintln!("Interface {} is not described in the pcap file", interface.name);
+        process::exit(1);
+    });
+
+    let sender = CapturingSender {
+        inner: tx,
+        pcap_writer: Arc::clone(pcap_writer),
+        interface_id
+    };
+    let receiver = CapturingReceiver {
+        inner: rx,
+        pcap_writer: Arc::clone(pcap_writer),
+        interface_id,
+        timestamp_reader
+    };
+    (Box::new(sender), Box::new(receiver))
+}
+
+struct CapturingSender {
+    inner: Box<dyn DataLinkSender>,
+    pcap_writer: Arc<PcapWriter>,
+    interface_id: u32
+}
+
+struct CapturingReceiver {
+    inner: Box<dyn DataLinkReceiver>,
+    pcap_writer: Arc<PcapWriter>,
+    interface_id: u32,
+    timestamp_reader: Option<TimestampReader>
+}
+
+impl DataLinkSender for CapturingSender {
+
+    fn build_and_send(&mut self, num_packets: usize, packet_size: usize, func: &mut dyn FnMut(&mut [u8])) -> Option<io::Result<()>> {
+
+        let pcap_writer = &self.pcap_writer;
+        let interface_id = self.interface_id;
+        self.inner.build_and_send(num_packets, packet_size, &mut |buffer| {
+            func(buffer);
+            pcap_writer.write_frame(interface_id, SystemTime::now(), buffer);
+        })
+    }
+
+    fn send_to(&mut self, packet: &[u8], dst: Option<NetworkInterface>) -> Option<io::Result<()>> {
+
+        self.pcap_writer.write_frame(self.interface_id, SystemTime::now(), packet);
+        self.inner.send_to(packet, dst)
+    }
+}
+
+impl DataLinkReceiver for CapturingReceiver {
+
+    fn next(&mut self) -> io::Result<&[u8]> {
+
+        let frame = self.inner.next()?;
+
+        let timestamp = self.timestamp_reader.as_ref()
+            .and_then(|reader| reader.read().ok())
+            .unwrap_or_else(SystemTime::now);
+        self.pcap_writer.write_frame(self.interface_id, timestamp, frame);
+
+        Ok(frame)
+    }
+}
+
+fn build_section_header() -> Vec<u8> {
+
+    let mut body: Vec<u8> = vec![];
+    body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend(1u16.to_le_bytes());
+    body.extend(0u16.to_le_bytes());
+    body.extend((-1i64).to_le_bytes());
+    build_block(SECTION_HEADER_BLOCK, body)
+}
+
+fn build_interface_description(interface_name: &str) -> Vec<u8> {
+
+    let mut body: Vec<u8> = vec![];
+    body.extend(LINKTYPE_ETHERNET.to_le_bytes());
+    body.extend(0u16.to_le_bytes());
+    body.extend(0u32.to_le_bytes());
+    push_option(&mut body, OPTION_INTERFACE_NAME, interface_name.as_bytes());
+    push_option(&mut body, OPTION_TIMESTAMP_RESOLUTION, &[TIMESTAMP_RESOLUTION]);
+    push_option(&mut body, OPTION_END, &[]);
+    build_block(INTERFACE_DESCRIPTION_BLOCK, body)
+}
+
+fn build_enhanced_packet(interface_id: u32, timestamp: SystemTime, frame: &[u8]) -> Vec<u8> {
+
+    let timestamp_ns = timestamp.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or(0);
+
+    let mut body: Vec<u8> = Vec::with_capacity(20 + frame.len() + 3);
+    body.extend(interface_id.to_le_bytes());
+    body.extend(((timestamp_ns >> 32) as u32).to_le_bytes());
+    body.extend((timestamp_ns as u32).to_le_bytes());
+    body.extend((frame.len() as u32).to_le_bytes());
+    body.extend((frame.len() as u32).to_le_bytes());
+    body.extend(frame);
+    pad_block(&mut body);
+    build_block(ENHANCED_PACKET_BLOCK, body)
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    pad_block(body);
+}
+
+/**
+ * Pad a block body to a 32-bit boundary, as required by all pcapng fields.
+ */
+fn pad_block(body: &mut Vec<u8>) {
+
+    let padding = (4 - body.len() % 4) % 4;
+    body.extend(vec![0u8; padding]);
+}
+
+/**
+ * Build a full pcapng block, the block length is written both before and
+ * after the body.
+ */
+fn build_block(block_type: u32, body: Vec<u8>) -> Vec<u8> {
+
+    let block_length = (body.len() + 12) as u32;
+
+    let mut block: Vec<u8> = Vec::with_capacity(block_length as usize);
+    block.extend(block_type.to_le_bytes());
+    block.extend(block_length.to_le_bytes());
+    block.extend(body);
+    block.extend(block_length.to_le_bytes());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn should_build_section_header() {
+
+        let header = build_section_header();
+
+        assert_eq!(header.len(), 28);
+        assert_eq!(header[0..4], [0x0a, 0x0d, 0x0d, 0x0a]);
+        assert_eq!(header[8..12], [0x4d, 0x3c, 0x2b, 0x1a]);
+        assert_eq!(header[4..8], header[24..28]);
+    }
+
+    #[test]
+    fn should_describe_named_interface() {
+
+        let description = build_interface_description("eth0");
+
+        assert_eq!(description.len(), 40);
+        assert_eq!(description[8..10], [1, 0]);
+        assert_eq!(description[16..24], [2, 0, 4, 0, b'e', b't', b'h', b'0']);
+        assert_eq!(description[24..29], [9, 0, 1, 0, 9]);
+    }
+
+    #[test]
+    fn should_pad_packet_frame() {
+
+        let timestamp = UNIX_EPOCH + Duration::new(5, 42);
+        let packet_block = build_enhanced_packet(1, timestamp, &[0xff; 42]);
+
+        assert_eq!(packet_block.len(), 76);
+        assert_eq!(packet_block[4..8], 76u32.to_le_bytes());
+        assert_eq!(packet_block[8..12], 1u32.to_le_bytes());
+        assert_eq!(packet_block[12..16], 1u32.to_le_bytes());
+        assert_eq!(packet_block[16..20], 705_032_746u32.to_le_bytes());
+        assert_eq!(packet_block[20..24], 42u32.to_le_bytes());
+        assert_eq!(packet_block[70..72], [0, 0]);
+    }
+}
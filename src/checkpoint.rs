@@ -0,0 +1,168 @@
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+// The checkpoint version is increased on each incompatible format change
+const CHECKPOINT_VERSION: u32 = 1;
+
+/**
+ * A scan checkpoint records the progress of an interrupted scan, so that a
+ * later run can continue exactly where the previous one stopped. Hosts found
+ * before the interruption are kept and merged with the new results.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScanCheckpoint {
+    pub version: u32,
+    pub random_seed: Option<u64>,
+    pub interfaces: Vec<InterfaceProgress>,
+    pub hosts: Vec<CheckpointHost>
+}
+
+/**
+ * The scan progress on a single interface: the current phase (standard ARP
+ * requests or promiscuous probes), the retry round and the iterator position
+ * of each scan lane. The target count is used to detect a different scan.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceProgress {
+    pub interface_name: String,
+    pub target_count: u64,
+    pub phase: usize,
+    pub retry_index: usize,
+    pub lane_positions: Vec<u64>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CheckpointHost {
+    pub interface_name: String,
+    pub vlan_id: Option<u16>,
+    pub ipv4: Ipv4Addr,
+    pub mac: String
+}
+
+impl ScanCheckpoint {
+
+    pub fn new(random_seed: Option<u64>, interfaces: Vec<InterfaceProgress>, hosts: Vec<CheckpointHost>) -> ScanCheckpoint {
+
+        ScanCheckpoint {
+            version: CHECKPOINT_VERSION,
+            random_seed,
+            interfaces,
+            hosts
+        }
+    }
+
+    /**
+     * Load a checkpoint from a JSON file. Checkpoints written by another
+     * version of the checkpoint format are rejected.
+     */
+    pub fn load(file_path: &str) -> Result<ScanCheckpoint, String> {
+
+        let content = fs::read_to_string(file_path).map_err(|err| {
+            format!("Could not open checkpoint file {} - {}", file_path, err)
+        })?;
+
+        let checkpoint: ScanCheckpoint = serde_json::from_str(&content).map_err(|err| {
+            format!("Could not parse checkpoint file {} - {}", file_path, err)
+        })?;
+
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!("Unsupported checkpoint version {} (expected {})", checkpoint.version, CHECKPOINT_VERSION));
+        }
+        Ok(checkpoint)
+    }
+
+    /**
+     * Save the checkpoint as a JSON file. The checkpoint is first written in
+     * a temporary file and then renamed, an interruption while writing will
+     * then never corrupt the previous checkpoint.
+     */
+    pub fn save(&self, file_path: &str) -> Result<(), String> {
+
+        let content = serde_json::to_string_pretty(self).map_err(|err| {
+            format!("Could not serialize checkpoint ({})", err)
+        })?;
+
+        let temporary_path = format!("{}.tmp", file_path);
+        fs::write(&temporary_path, content).map_err(|err| {
+            format!("Could not write checkpoint file {} - {}", temporary_path, err)
+        })?;
+        fs::rename(&temporary_path, file_path).map_err(|err| {
+            format!("Could not replace checkpoint file {} - {}", file_path, err)
+        })
+    }
+
+    pub fn find_progress(&self, interface_name: &str) -> Option<&InterfaceProgress> {
+
+        self.interfaces.iter().find(|progress| progress.interface_name == interface_name)
+    }
+}
+
+/**
+ * Remove the checkpoint file of a completed scan, a missing file is not
+ * considered as an error.
+ */
+pub fn remove_checkpoint(file_path: &str) -> Result<(), String> {
+
+    match Path::new(file_path).exists() {
+        true => fs::remove_file(file_path).map_err(|err| format!("Could not remove checkpoint file {} - {}", file_path, err)),
+        false => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+
+    fn build_checkpoint() -> ScanCheckpoint {
+
+        let progress = InterfaceProgress {
+            interface_name: "eth0".to_string(),
+            target_count: 256,
+            phase: 0,
+            retry_index: 1,
+            lane_positions: vec![42]
+        };
+        let host = CheckpointHost {
+            interface_name: "eth0".to_string(),
+            vlan_id: None,
+            ipv4: Ipv4Addr::new(192, 168, 1, 1),
+            mac: "00:16:f6:00:00:01".to_string()
+        };
+        ScanCheckpoint::new(Some(1234), vec![progress], vec![host])
+    }
+
+    #[test]
+    fn should_save_and_load_checkpoint() {
+
+        let file_path = env::temp_dir().join("arp-scan-checkpoint-test.json");
+        let file_path = file_path.to_str().unwrap();
+
+        let checkpoint = build_checkpoint();
+        checkpoint.save(file_path).unwrap();
+
+        assert_eq!(ScanCheckpoint::load(file_path), Ok(checkpoint));
+
+        remove_checkpoint(file_path).unwrap();
+        assert!(!Path::new(file_path).exists());
+    }
+
+    #[test]
+    fn should_find_interface_progress() {
+
+        let checkpoint = build_checkpoint();
+
+        assert_eq!(checkpoint.find_progress("eth0").map(|progress| progress.lane_positions.clone()), Some(vec![42]));
+        assert_eq!(checkpoint.find_progress("eth1"), None);
+    }
+
+    #[test]
+    fn should_fail_unknown_checkpoint() {
+
+        assert!(ScanCheckpoint::load("./data/unknown-checkpoint.json").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+
+use ipnetwork::Ipv4Network;
+
+use crate::args::ScanOptions;
+use crate::network::{ScanLane, TargetDetails, TargetRange};
+use crate::permutation::{self, TargetPermutation};
+
+// Subnets are discovered with a /24 granularity, each /24 being either fully
+// scanned (at least one sample answered) or skipped.
+pub const SUBNET_PREFIX: u8 = 24;
+const SUBNET_BITS: u32 = 32 - SUBNET_PREFIX as u32;
+const SUBNET_SIZE: u32 = 1 << SUBNET_BITS;
+
+/**
+ * The subnet discovery results, computed once the occupied subnets have been
+ * fully scanned. Each occupied subnet is reported with its host count.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubnetDiscovery {
+    pub sampled_count: usize,
+    pub subnets: Vec<SubnetOccupancy>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubnetOccupancy {
+    pub interface_name: Option<String>,
+    pub vlan_id: Option<u16>,
+    pub subnet: Ipv4Network,
+    pub host_count: usize
+}
+
+// A subnet identified by its interface, VLAN and first 24 bits
+type SubnetKey = (Option<String>, Option<u16>, u32);
+
+/**
+ * List all /24 subnets overlapping the given target ranges, in the order of
+ * the target ranges. Each subnet is identified by its first 24 bits.
+ */
+pub fn list_subnets(target_ranges: &[TargetRange]) -> Vec<u32> {
+
+    let mut known_subnets: HashSet<u32> = HashSet::new();
+    let mut subnets: Vec<u32> = vec![];
+    for range in target_ranges.iter() {
+        for subnet in (range.first >> SUBNET_BITS)..=(range.last >> SUBNET_BITS) {
+            if known_subnets.insert(subnet) {
+                subnets.push(subnet);
+            }
+        }
+    }
+    subnets
+}
+
+/**
+ * Compute the sample addresses probed in every /24 subnet of the target
+ * ranges: the requested host octets (likely gateways and servers) and a few
+ * random octets picked with the seed. Samples outside of the target ranges
+ * (excluded addresses, network bounds, ...) are never probed.
+ */
+pub fn compute_sample_ranges(target_ranges: &[TargetRange], sample_hosts: &[u8], random_count: usize, seed: u64) -> Vec<TargetRange> {
+
+    let mut sample_ranges: Vec<TargetRange> = vec![];
+    for subnet in list_subnets(target_ranges) {
+
+        let mut sample_octets: Vec<u32> = vec![];
+        for host in sample_hosts.iter() {
+            if !sample_octets.contains(&u32::from(*host)) {
+                sample_octets.push(u32::from(*host));
+            }
+        }
+
+        // Random picks follow a permutation of all octets, the same seed will
+        // then always probe the same addresses.
+        let octet_permutation = TargetPermutation::new(u64::from(SUBNET_SIZE), permutation::derive_seed(seed, u64::from(subnet)));
+        let random_octets = (0..u64::from(SUBNET_SIZE))
+            .map(|index| octet_permutation.permute(index) as u32)
+            .filter(|octet| !sample_octets.contains(octet))
+            .take(random_count)
+            .collect::<Vec<u32>>();
+        sample_octets.extend(random_octets);
+
+        for octet in sample_octets {
+
+            let sample_address = (subnet << SUBNET_BITS) | octet;
+            if target_ranges.iter().any(|range| range.first <= sample_address && sample_address <= range.last) {
+                sample_ranges.push(TargetRange { first: sample_address, last: sample_address });
+            }
+        }
+    }
+    sample_ranges
+}
+
+/**
+ * Replace the targets of each scan lane by the sample addresses of all its
+ * subnets, for the first discovery scan.
+ */
+pub fn compute_sample_lanes(scan_lanes: &[ScanLane], options: &ScanOptions, seed: u64) -> Vec<ScanLane> {
+
+    scan_lanes.iter().map(|lane| ScanLane {
+        target_ranges: compute_sample_ranges(&lane.target_ranges, &options.discovery_hosts, options.discovery_random_count, seed),
+        ..lane.clone()
+    }).collect()
+}
+
+/**
+ * Find the subnets where at least one sample answered, for each VLAN.
+ */
+pub fn find_occupied_subnets(target_details: &[TargetDetails]) -> HashSet<(Option<u16>, u32)> {
+
+    target_details.iter()
+        .map(|detail| (detail.vlan_id, u32::from(detail.ipv4) >> SUBNET_BITS))
+        .collect()
+}
+
+/**
+ * Restrict target ranges to the given subnets, contiguous subnets being kept
+ * in a single range.
+ */
+pub fn restrict_ranges(target_ranges: &[TargetRange], subnets: &HashSet<u32>) -> Vec<TargetRange> {
+
+    let mut restricted_ranges: Vec<TargetRange> = vec![];
+    for range in target_ranges.iter() {
+        for subnet in (range.first >> SUBNET_BITS)..=(range.last >> SUBNET_BITS) {
+
+            if !subnets.contains(&subnet) {
+                continue;
+            }
+
+            let first = range.first.max(subnet << SUBNET_BITS);
+            let last = range.last.min((subnet << SUBNET_BITS) | (SUBNET_SIZE - 1));
+            match restricted_ranges.last_mut() {
+                Some(previous_range) if previous_range.last.checked_add(1) == Some(first) => previous_range.last = last,
+                _ => restricted_ranges.push(TargetRange { first, last })
+            }
+        }
+    }
+    restricted_ranges
+}
+
+/**
+ * Keep only the occupied subnets in the targets of each scan lane, for the
+ * second (full) discovery scan.
+ */
+pub fn restrict_lanes(scan_lanes: &[ScanLane], occupied_subnets: &HashSet<(Option<u16>, u32)>) -> Vec<ScanLane> {
+
+    scan_lanes.iter().map(|lane| {
+
+        let lane_subnets: HashSet<u32> = occupied_subnets.iter()
+            .filter(|(vlan_id, _)| *vlan_id == lane.vlan_id)
+            .map(|(_, subnet)| *subnet)
+            .collect();
+
+        ScanLane {
+            target_ranges: restrict_ranges(&lane.target_ranges, &lane_subnets),
+            ..lane.clone()
+        }
+
+    }).collect()
+}
+
+/**
+ * Count the hosts found in each /24 subnet (per interface and VLAN), subnets
+ * are sorted like scan results.
+ */
+pub fn compute_subnet_occupancy(target_details: &[TargetDetails], sampled_count: usize) -> SubnetDiscovery {
+
+    let mut host_counts: HashMap<SubnetKey, usize> = HashMap::new();
+    for detail in target_details.iter() {
+        let subnet_key = (detail.interface_name.clone(), detail.vlan_id, u32::from(detail.ipv4) >> SUBNET_BITS);
+        *host_counts.entry(subnet_key).or_insert(0) += 1;
+    }
+
+    let mut sorted_counts: Vec<(SubnetKey, usize)> = host_counts.into_iter().collect();
+    sorted_counts.sort();
+
+    let subnets = sorted_counts.into_iter().map(|((interface_name, vlan_id, subnet), host_count)| {
+
+        let subnet_address = Ipv4Addr::from(subnet << SUBNET_BITS);
+        SubnetOccupancy {
+            interface_name,
+            vlan_id,
+            subnet: Ipv4Network::new(subnet_address, SUBNET_PREFIX).expect("Internal subnet computation failed"),
+            host_count
+        }
+
+    }).collect();
+
+    SubnetDiscovery {
+        sampled_count,
+        subnets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use pnet_datalink::MacAddr;
+
+    fn build_range(first: Ipv4Addr, last: Ipv4Addr) -> TargetRange {
+        TargetRange { first: u32::from(first), last: u32::from(last) }
+    }
+
+    fn build_target(ipv4: Ipv4Addr) -> TargetDetails {
+
+        TargetDetails {
+            interface_name: Some("eth0".to_string()),
+            ..TargetDetails::for_test(ipv4, MacAddr::zero())
+        }
+    }
+
+    #[test]
+    fn should_list_overlapped_subnets() {
+
+        let target_ranges = vec![
+            build_range(Ipv4Addr::new(10, 0, 0, 200), Ipv4Addr::new(10, 0, 2, 10)),
+            build_range(Ipv4Addr::new(10, 0, 1, 1), Ipv4Addr::new(10, 0, 1, 1))
+        ];
+
+        assert_eq!(list_subnets(&target_ranges), vec![0x0a0000, 0x0a0001, 0x0a0002]);
+    }
+
+    #[test]
+    fn should_sample_each_subnet() {
+
+        let target_ranges = vec![
+            build_range(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 3, 255))
+        ];
+
+        let sample_ranges = compute_sample_ranges(&target_ranges, &[1, 254], 3, 42);
+
+        assert_eq!(sample_ranges.len(), 20);
+        assert_eq!(sample_ranges[0], build_range(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(sample_ranges[1], build_range(Ipv4Addr::new(10, 0, 0, 254), Ipv4Addr::new(10, 0, 0, 254)));
+        assert_eq!(compute_sample_ranges(&target_ranges, &[1, 254], 3, 42), sample_ranges);
+    }
+
+    #[test]
+    fn should_not_sample_outside_targets() {
+
+        let target_ranges = vec![
+            build_range(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 20))
+        ];
+
+        let sample_ranges = compute_sample_ranges(&target_ranges, &[1, 15], 0, 42);
+
+        assert_eq!(sample_ranges, vec![build_range(Ipv4Addr::new(10, 0, 0, 15), Ipv4Addr::new(10, 0, 0, 15))]);
+    }
+
+    #[test]
+    fn should_restrict_to_occupied_subnets() {
+
+        let target_ranges = vec![
+            build_range(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 9, 254))
+        ];
+        let occupied_subnets: HashSet<u32> = [0x0a0002, 0x0a0003, 0x0a0009].into_iter().collect();
+
+        assert_eq!(restrict_ranges(&target_ranges, &occupied_subnets), vec![
+            build_range(Ipv4Addr::new(10, 0, 2, 0), Ipv4Addr::new(10, 0, 3, 255)),
+            build_range(Ipv4Addr::new(10, 0, 9, 0), Ipv4Addr::new(10, 0, 9, 254))
+        ]);
+    }
+
+    #[test]
+    fn should_count_subnet_hosts() {
+
+        let target_details = vec![
+            build_target(Ipv4Addr::new(10, 0, 2, 1)),
+            build_target(Ipv4Addr::new(10, 0, 1, 1)),
+            build_target(Ipv4Addr::new(10, 0, 2, 77))
+        ];
+
+        let occupied_subnets = find_occupied_subnets(&target_details);
+        let subnet_discovery = compute_subnet_occupancy(&target_details, 16);
+
+        assert_eq!(occupied_subnets.len(), 2);
+        assert!(occupied_subnets.contains(&(None, 0x0a0002)));
+        assert_eq!(subnet_discovery.sampled_count, 16);
+        assert_eq!(subnet_discovery.subnets.len(), 2);
+        assert_eq!(subnet_discovery.subnets[0].subnet, Ipv4Network::new(Ipv4Addr::new(10, 0, 1, 0), 24).unwrap());
+        assert_eq!(subnet_discovery.subnets[1].host_count, 2);
+    }
+}
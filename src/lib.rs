@@ -1,30 +1,69 @@
+pub mod analysis;
 pub mod args;
+pub mod capture;
+pub mod checkpoint;
+pub mod discovery;
+pub mod metadata;
+pub mod monitor;
 pub mod network;
+pub mod permutation;
+pub mod ratelimit;
+pub mod report;
+#[cfg(target_os = "linux")]
+pub mod socket;
+pub mod store;
+pub mod stream;
+pub mod targets;
+pub mod template;
 pub mod time;
 pub mod utils;
 pub mod vendor;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::process;
+use std::str::FromStr;
 use std::thread;
-use std::sync::Arc;
-use std::time::Duration;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-use crate::args::{ScanOptions, OutputFormat};
-use crate::network::NetworkIterator;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use clap::ArgMatches;
+
+use crate::args::{HistoryQuery, ScanOptions, OutputFormat};
+use pnet_datalink::{MacAddr, NetworkInterface, DataLinkSender, DataLinkReceiver};
+
+use crate::capture::{PcapWriter, TimestampReader};
+use crate::checkpoint::{CheckpointHost, InterfaceProgress, ScanCheckpoint};
+use crate::metadata::ScanMetadata;
+use crate::monitor::ResponseMonitor;
+use crate::network::{FrameTemplate, KernelStatistics, NetworkIterator, ResponseSummary, ScanLane, TargetDetails};
+use crate::ratelimit::RateLimiter;
+use crate::store::ResultStore;
+use crate::stream::HostStream;
 use crate::vendor::Vendor;
 
+const CHECKPOINT_INTERVAL_MS: u64 = 5000;
+
 pub fn start_scan() {
     simple_logger::init_with_env().expect("Error initiating simple logger.");
     
+    let matches = args::build_args().get_matches();
+
+    // Query the results store if requested
+    // ----------------------------------------
+    // The 'history' command only reads previously saved scans, it does not
+    // need any network access nor root permissions.
+
+    if let Some(history_matches) = matches.subcommand_matches("history") {
+        show_history(history_matches);
+        process::exit(0);
+    }
+
     // Upgrade user privileges when needed
     // ----------------------------------------
     // Providing a prompt for the user when
     // the app is run and user is not root    
     sudo::escalate_if_needed().expect("You need root permissions to run this app. Unable to escalate to sudo");
-    
-    let matches = args::build_args().get_matches();
 
     // Find interfaces & list them if requested
     // ----------------------------------------
@@ -57,58 +96,87 @@ pub fn start_scan() {
         process::exit(0);
     }
     
-    let (selected_interface, ip_networks) = network::compute_network_configuration(&interfaces, &scan_options);
+    let interface_configurations = network::compute_network_configuration(&interfaces, &scan_options);
 
     if scan_options.is_plain_output() {
-        utils::display_prescan_details(&ip_networks, selected_interface, scan_options.clone());
+        utils::display_prescan_details(&interface_configurations, scan_options.clone());
     }
 
     // Start ARP scan operation
     // ------------------------
-    // ARP responses on the interface will be collected in a separate thread,
-    // while the main thread sends a batch of ARP requests for each IP in the
-    // local network.
-
-    let channel_config = pnet_datalink::Config {
-        read_timeout: Some(Duration::from_millis(network::DATALINK_RCV_TIMEOUT)), 
-        ..pnet_datalink::Config::default()
-    };
-
-    let (mut tx, mut rx) = match pnet_datalink::channel(selected_interface, channel_config) {
-        Ok(pnet_datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => {
-            eprintln!("Expected an Ethernet datalink channel");
-            process::exit(1);
-        },
-        Err(error) => {
-            eprintln!("Datalink channel creation failed ({})", error);
-            process::exit(1);
-        }
-    };
-
-    // The 'timed_out' mutex is shared accross the main thread (which performs
-    // ARP packet sending) and the response thread (which receives and stores
-    // all ARP responses).
-    let timed_out = Arc::new(AtomicBool::new(false));
-    let cloned_timed_out = Arc::clone(&timed_out);
-
-    let mut vendor_list = Vendor::new();
-
-    let cloned_options = Arc::clone(&scan_options);
-    let arp_responses = thread::spawn(move || network::receive_arp_responses(&mut rx, cloned_options, cloned_timed_out, &mut vendor_list));
+    // Each interface is scanned in a dedicated thread. On each interface, ARP
+    // responses will be collected in a separate thread, while the interface
+    // thread sends a batch of ARP requests for each IP in the local network.
+
+    let interface_lanes: Vec<(&NetworkInterface, Vec<ScanLane>)> = interface_configurations.iter()
+        .map(|(interface, ip_networks)| (*interface, network::compute_scan_lanes(interface, ip_networks, &scan_options)))
+        .collect();
+
+    // A subnet discovery first probes a few samples in each /24 subnet, only
+    // subnets with at least one response will be fully scanned afterwards.
+    let discovery_seed = scan_options.random_seed.unwrap_or_else(rand::random);
+    let interface_scans: Vec<InterfaceScan> = interface_lanes.iter().map(|(interface, scan_lanes)| {
+
+        let scan_lanes = match scan_options.discover_subnets {
+            true => discovery::compute_sample_lanes(scan_lanes, &scan_options, discovery_seed),
+            false => scan_lanes.clone()
+        };
+        InterfaceScan::new(interface, scan_lanes, &scan_options)
+
+    }).collect();
+
+    // A promiscuous check sends the standard ARP requests first, and then
+    // a request for each bogus destination MAC address (one phase each).
+    let mut destination_phases: Vec<MacAddr> = vec![network::find_destination_mac(&scan_options)];
+    if scan_options.promisc_check {
+        destination_phases.extend(network::PROMISC_PROBES.iter().map(|(_, probe_mac)| *probe_mac));
+    }
 
-    let network_size = utils::compute_network_size(&ip_networks);
+    let network_size: u128 = interface_scans.iter().map(|interface_scan| compute_lanes_size(&interface_scan.scan_lanes)).sum();
+    let network_size = network_size * destination_phases.len() as u128;
 
     let estimations = network::compute_scan_estimation(network_size, &scan_options);
-    let interval_ms = estimations.interval_ms;
 
     if scan_options.is_plain_output() {
 
         let formatted_ms = time::format_milliseconds(estimations.duration_ms);
+        let formatted_rate = match estimations.packet_rate {
+            Some(packet_rate) => format!("{:.0} packets/s", packet_rate),
+            None => "no rate limit".to_string()
+        };
         println!("Estimated scan time {} ({} bytes, {} bytes/s)", formatted_ms, estimations.request_size, estimations.bandwidth);
-        println!("Sending {} ARP requests (waiting at least {}ms, {}ms request interval)", network_size, scan_options.timeout_ms, interval_ms);
+        let formatted_wait = match scan_options.adaptive_timeout {
+            true => format!("adaptive wait up to {}ms", scan_options.timeout_ms),
+            false => format!("waiting at least {}ms", scan_options.timeout_ms)
+        };
+        if scan_options.discover_subnets {
+            println!("Probing samples in {} subnets to discover occupied subnets", count_lanes_subnets(&interface_lanes));
+        }
+        println!("Sending {} ARP requests ({}, {})", network_size, formatted_wait, formatted_rate);
+        if let Some(resume_checkpoint) = &scan_options.resume_checkpoint {
+            println!("Resuming scan from checkpoint ({} hosts already found)", resume_checkpoint.hosts.len());
+        }
     }
 
+    // A streamed output gives the scan settings first, and then each host as
+    // soon as its first response has been received.
+    let interface_names: Vec<String> = interface_scans.iter().map(|interface_scan| interface_scan.interface.name.clone()).collect();
+    let host_stream = match scan_options.output {
+        OutputFormat::Ndjson if scan_options.has_stdout_output() => {
+            stream::print_start_record(interface_names.clone(), network_size, &scan_options);
+            Some(Mutex::new(HostStream::new()))
+        },
+        _ => None
+    };
+
+    // The rate limiter is shared by all interface threads, the requested rate
+    // is then a global budget for the whole scan.
+    let rate_limiter = Mutex::new(RateLimiter::new(estimations.packet_rate, scan_options.burst_size));
+
+    // Requests are sent in batches filling the datalink send buffer, a batch
+    // is sent earlier when the rate limiter has to wait for the next token.
+    let batch_size = network::compute_batch_capacity(&scan_options);
+
     let has_reached_timeout = Arc::new(AtomicBool::new(false));
     let cloned_reached_timeout = Arc::clone(&has_reached_timeout);
 
@@ -120,51 +188,704 @@ pub fn start_scan() {
         process::exit(1);
     });
 
-    let source_ip = network::find_source_ip(selected_interface, scan_options.source_ipv4);
+    // All interfaces share a single capture file, each interface having its
+    // own description block.
+    let pcap_writer = scan_options.pcap_file.as_ref().map(|pcap_file| {
+        Arc::new(PcapWriter::create(pcap_file, &interface_names).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        }))
+    });
 
-    // The retry count does right now use a 'brute-force' strategy without
-    // synchronization process with the already known hosts.
-    for _ in 0..scan_options.retry_count {
+    // The results store is opened before the scan, so that a wrong database
+    // path is reported before spending time on the network.
+    let mut result_store = scan_options.store_file.as_ref().map(|store_file| {
+        ResultStore::open(store_file).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        })
+    });
 
-        if has_reached_timeout.load(Ordering::Relaxed) {
-            break;
+    let scan_context = ScanContext {
+        options: &scan_options,
+        destination_phases: &destination_phases,
+        rate_limiter: &rate_limiter,
+        has_reached_timeout: &has_reached_timeout,
+        batch_size,
+        host_stream: host_stream.as_ref(),
+        pcap_writer: pcap_writer.as_ref()
+    };
+
+    let started_at = SystemTime::now();
+    let interface_results = run_interface_scans(&interface_scans, &scan_context);
+
+    // An interrupted scan saves a final checkpoint, while a completed scan
+    // removes its checkpoint file since there is nothing left to resume.
+    if let Some(checkpoint_file) = &scan_options.checkpoint_file {
+
+        match has_reached_timeout.load(Ordering::Relaxed) {
+            true => {
+                save_checkpoint(checkpoint_file, &interface_scans, &scan_options);
+                eprintln!("Scan progress saved, use '--resume {}' to continue the scan", checkpoint_file);
+            },
+            false => checkpoint::remove_checkpoint(checkpoint_file).unwrap_or_else(|err| {
+                eprintln!("[warn] {}", err);
+            })
         }
+    }
 
-        let ip_addresses = NetworkIterator::new(&ip_networks, scan_options.randomize_targets);
+    let (mut response_summary, target_details) = match scan_options.discover_subnets {
+        true => scan_occupied_subnets(&interface_lanes, interface_results, &scan_context),
+        false => merge_interface_results(interface_results)
+    };
 
-        for ip_address in ip_addresses {
+    if let Some(pcap_writer) = &pcap_writer {
+        pcap_writer.flush().unwrap_or_else(|err| {
+            eprintln!("[warn] {}", err);
+        });
+    }
 
-            if has_reached_timeout.load(Ordering::Relaxed) {
-                break;
+    let target_details = merge_resumed_hosts(target_details, &scan_options);
+    let target_details = analysis::flag_shared_macs(target_details, &scan_options);
+
+    let rate_limiter = rate_limiter.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+    response_summary.request_count = rate_limiter.packet_count();
+    response_summary.requested_rate = estimations.packet_rate;
+    response_summary.achieved_rate = rate_limiter.achieved_rate();
+    response_summary.scan_metadata = Some(ScanMetadata::new(started_at, &interface_lanes, &scan_options));
+
+    // File outputs are written before the main output, each file receiving
+    // its own copy of the results. A failed file does not prevent the other
+    // outputs from being written.
+    let mut has_output_error = false;
+    if let Some(result_store) = &mut result_store {
+        if let Err(err) = result_store.save_scan(&response_summary, &target_details) {
+            eprintln!("{}", err);
+            has_output_error = true;
+        }
+    }
+
+    for output_target in scan_options.output_targets.iter() {
+
+        let content = format_output(output_target.format, response_summary.clone(), target_details.clone(), interface_names.clone(), network_size, &scan_options);
+        if let Err(err) = utils::write_file_atomically(&output_target.path, &content) {
+            eprintln!("{}", err);
+            has_output_error = true;
+        }
+    }
+
+    if scan_options.has_stdout_output() {
+
+        match &scan_options.output {
+            OutputFormat::Plain => utils::display_scan_results(response_summary, target_details, &scan_options),
+            OutputFormat::Json => println!("{}", utils::export_to_json(response_summary, target_details)),
+            OutputFormat::Yaml => println!("{}", utils::export_to_yaml(response_summary, target_details)),
+            OutputFormat::Csv => print!("{}", utils::export_to_csv(response_summary, target_details)),
+            OutputFormat::Xml => print!("{}", utils::export_to_xml(response_summary, target_details)),
+            OutputFormat::Classic => print!("{}", utils::export_to_classic(response_summary, target_details, network_size, &scan_options)),
+            OutputFormat::Html => print!("{}", report::export_to_html(response_summary, target_details)),
+            OutputFormat::Template => print!("{}", format_template_output(response_summary, target_details, &scan_options)),
+            OutputFormat::Ndjson => {
+                if let Some(host_stream) = &host_stream {
+                    host_stream.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).stream_remaining(&target_details);
+                }
+                stream::print_summary_record(&response_summary, target_details.len());
             }
+        }
+    }
+
+    if has_output_error {
+        process::exit(1);
+    }
+}
+
+/**
+ * Run the 'history' command on a results store, listing either the past scans
+ * or the sightings of a single host.
+ */
+fn show_history(history_matches: &ArgMatches) {
+
+    let history_query = HistoryQuery::new(history_matches).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    let store_file = history_matches.get_one::<String>("store").expect("Store path is required by the history command");
+    let result_store = ResultStore::open(store_file).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    let query_result = match history_query {
+        HistoryQuery::Scans(limit) => result_store.list_scans(limit).map(|stored_scans| utils::show_stored_scans(&stored_scans)),
+        HistoryQuery::Mac(mac) => result_store.find_mac_history(&mac.to_string()).map(|sightings| utils::show_host_sightings(&sightings)),
+        HistoryQuery::Ip(ipv4) => result_store.find_ip_history(&ipv4.to_string()).map(|sightings| utils::show_host_sightings(&sightings))
+    };
+
+    query_result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+}
+
+/**
+ * Format the scan results for an output file, the plain output being written
+ * without terminal colors.
+ */
+fn format_output(output_format: OutputFormat, response_summary: ResponseSummary, target_details: Vec<TargetDetails>, interface_names: Vec<String>, network_size: u128, options: &ScanOptions) -> String {
+
+    match output_format {
+        OutputFormat::Plain => utils::format_scan_results(response_summary, target_details, options, false),
+        OutputFormat::Json => format!("{}\n", utils::export_to_json(response_summary, target_details)),
+        OutputFormat::Yaml => format!("{}\n", utils::export_to_yaml(response_summary, target_details)),
+        OutputFormat::Csv => utils::export_to_csv(response_summary, target_details),
+        OutputFormat::Xml => utils::export_to_xml(response_summary, target_details),
+        OutputFormat::Classic => utils::export_to_classic(response_summary, target_details, network_size, options),
+        OutputFormat::Html => report::export_to_html(response_summary, target_details),
+        OutputFormat::Template => format_template_output(response_summary, target_details, options),
+        OutputFormat::Ndjson => stream::export_to_ndjson(interface_names, network_size, response_summary, target_details, options)
+    }
+}
+
+/**
+ * Format the scan results with the user-defined templates, the template
+ * output being only selected when '--format-template' has been given.
+ */
+fn format_template_output(response_summary: ResponseSummary, target_details: Vec<TargetDetails>, options: &ScanOptions) -> String {
+
+    let output_template = options.output_template.as_ref().expect("Template output requires a host template");
+    template::export_to_template(response_summary, target_details, output_template)
+}
+
+/**
+ * Groups all scan settings and states shared by the interface threads. The
+ * rate limiter and halt signal are global, all interfaces being stopped by a
+ * single CTRL+C.
+ */
+struct ScanContext<'a> {
+    options: &'a Arc<ScanOptions>,
+    destination_phases: &'a [MacAddr],
+    rate_limiter: &'a Mutex<RateLimiter>,
+    has_reached_timeout: &'a AtomicBool,
+    batch_size: usize,
+    host_stream: Option<&'a Mutex<HostStream>>,
+    pcap_writer: Option<&'a Arc<PcapWriter>>
+}
 
-            if let IpAddr::V4(ipv4_address) = ip_address {
-                network::send_arp_request(&mut tx, selected_interface, source_ip, ipv4_address, Arc::clone(&scan_options));
-                thread::sleep(Duration::from_millis(interval_ms));
+/**
+ * The scan of a single network interface, with all targets split in lanes.
+ * The progress and the response monitor are shared with the main thread, which
+ * saves checkpoints while the interface is being scanned.
+ */
+struct InterfaceScan<'a> {
+    interface: &'a NetworkInterface,
+    scan_lanes: Vec<ScanLane>,
+    progress: Mutex<InterfaceProgress>,
+    monitor: Arc<ResponseMonitor>
+}
+
+impl<'a> InterfaceScan<'a> {
+
+    fn new(interface: &'a NetworkInterface, scan_lanes: Vec<ScanLane>, scan_options: &ScanOptions) -> InterfaceScan<'a> {
+
+        let target_count = compute_lanes_size(&scan_lanes);
+
+        // A resumed scan must target exactly the same hosts, otherwise the
+        // saved iterator positions would be meaningless.
+        let progress = match &scan_options.resume_checkpoint {
+            Some(resume_checkpoint) => {
+
+                let resumed_progress = resume_checkpoint.find_progress(&interface.name)
+                    .filter(|progress| u128::from(progress.target_count) == target_count && progress.lane_positions.len() == scan_lanes.len());
+
+                match resumed_progress {
+                    Some(progress) => progress.clone(),
+                    None => {
+                        eprintln!("The checkpoint does not match the requested scan on interface {}", interface.name);
+                        eprintln!("Make sure to resume with the same interfaces, networks and VLANs");
+                        process::exit(1);
+                    }
+                }
+            },
+            None => InterfaceProgress {
+                interface_name: interface.name.clone(),
+                target_count: u64::try_from(target_count).unwrap_or(u64::MAX),
+                phase: 0,
+                retry_index: 0,
+                lane_positions: vec![0; scan_lanes.len()]
             }
+        };
+
+        // The response monitor measures response times for the adaptive timeout.
+        // An adaptive scan may also end early once every target has answered,
+        // except for promiscuous checks where all probes must be sent.
+        let expected_responses = match scan_options.adaptive_timeout && !scan_options.promisc_check {
+            true => usize::try_from(target_count).ok(),
+            false => None
+        };
+
+        InterfaceScan {
+            interface,
+            scan_lanes,
+            progress: Mutex::new(progress),
+            monitor: Arc::new(ResponseMonitor::new(expected_responses))
         }
     }
 
-    // Once the ARP packets are sent, the main thread will sleep for T seconds
-    // (where T is the timeout option). After the sleep phase, the response
-    // thread will receive a stop request through the 'timed_out' mutex.
-    let mut sleep_ms_mount: u64 = 0;
-    while !has_reached_timeout.load(Ordering::Relaxed) && sleep_ms_mount < scan_options.timeout_ms {
-        
-        thread::sleep(Duration::from_millis(100));
-        sleep_ms_mount += 100;
+    fn update_progress(&self, phase: usize, retry_index: usize, lane_positions: Vec<u64>) {
+
+        let mut progress = self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        progress.phase = phase;
+        progress.retry_index = retry_index;
+        progress.lane_positions = lane_positions;
     }
+}
+
+/**
+ * Scan all interfaces in parallel (one thread each) and collect the results
+ * of each interface, in the order of the interface scans.
+ */
+fn run_interface_scans(interface_scans: &[InterfaceScan], context: &ScanContext) -> Vec<(ResponseSummary, Vec<TargetDetails>)> {
+
+    thread::scope(|scope| {
+
+        let scan_threads: Vec<thread::ScopedJoinHandle<(ResponseSummary, Vec<TargetDetails>)>> = interface_scans.iter()
+            .map(|interface_scan| scope.spawn(|| scan_interface(interface_scan, context)))
+            .collect();
+
+        // The scan progress is periodically saved while interfaces are being
+        // scanned, a crash will then only lose the last seconds of the scan.
+        // New responses are also streamed from here, host enrichment (DNS,
+        // vendor) then never slows down the response threads.
+        if context.options.checkpoint_file.is_some() || context.host_stream.is_some() {
+
+            let mut last_checkpoint = Instant::now();
+            while !scan_threads.iter().all(|scan_thread| scan_thread.is_finished()) {
+
+                thread::sleep(Duration::from_millis(100));
+                if let Some(host_stream) = context.host_stream {
+                    stream_new_responses(interface_scans, host_stream, context.options);
+                }
+
+                let checkpoint_file = match &context.options.checkpoint_file {
+                    Some(checkpoint_file) => checkpoint_file,
+                    None => continue
+                };
+                if last_checkpoint.elapsed() >= Duration::from_millis(CHECKPOINT_INTERVAL_MS) {
+                    save_checkpoint(checkpoint_file, interface_scans, context.options);
+                    last_checkpoint = Instant::now();
+                }
+            }
+        }
+
+        scan_threads.into_iter().map(|scan_thread| scan_thread.join().unwrap_or_else(|error| {
+            eprintln!("Failed to close interface scan thread ({:?})", error);
+            process::exit(1);
+        })).collect()
+    })
+}
+
+fn stream_new_responses(interface_scans: &[InterfaceScan], host_stream: &Mutex<HostStream>, scan_options: &ScanOptions) {
+
+    let mut host_stream = host_stream.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for interface_scan in interface_scans.iter() {
+        for (vlan_id, ipv4, mac) in interface_scan.monitor.list_responses() {
+            host_stream.stream_response(&interface_scan.interface.name, vlan_id, ipv4, mac, scan_options);
+        }
+    }
+}
+
+fn merge_interface_results(interface_results: Vec<(ResponseSummary, Vec<TargetDetails>)>) -> (ResponseSummary, Vec<TargetDetails>) {
+
+    let response_summary = ResponseSummary::merge(interface_results.iter().map(|(summary, _)| summary));
+    let target_details: Vec<TargetDetails> = interface_results.into_iter().flat_map(|(_, target_details)| target_details).collect();
+    (response_summary, target_details)
+}
+
+/**
+ * Fully scan the subnets where at least one sample answered, once the sample
+ * scan is over. Sample responses are kept for hosts that did not answer the
+ * full scan, and the occupancy of each subnet is added to the summary.
+ */
+fn scan_occupied_subnets(interface_lanes: &[(&NetworkInterface, Vec<ScanLane>)], sample_results: Vec<(ResponseSummary, Vec<TargetDetails>)>, context: &ScanContext) -> (ResponseSummary, Vec<TargetDetails>) {
+
+    let scan_options = context.options;
+    let sampled_count = count_lanes_subnets(interface_lanes);
+
+    let subnet_scans: Vec<InterfaceScan> = interface_lanes.iter().zip(sample_results.iter()).map(|((interface, scan_lanes), (_, sample_details))| {
+
+        let occupied_subnets = discovery::find_occupied_subnets(sample_details);
+        InterfaceScan::new(interface, discovery::restrict_lanes(scan_lanes, &occupied_subnets), scan_options)
+
+    }).collect();
+
+    let (sample_summary, sample_details) = merge_interface_results(sample_results);
+    if context.has_reached_timeout.load(Ordering::Relaxed) {
+        let mut response_summary = sample_summary;
+        response_summary.subnet_discovery = Some(discovery::compute_subnet_occupancy(&sample_details, sampled_count));
+        return (response_summary, sample_details);
+    }
+
+    if scan_options.is_plain_output() {
+        let occupied_count = discovery::find_occupied_subnets(&sample_details).len();
+        let target_count: u128 = subnet_scans.iter().map(|subnet_scan| compute_lanes_size(&subnet_scan.scan_lanes)).sum();
+        println!("Found {} occupied subnets, sending {} ARP requests", occupied_count, target_count);
+    }
+
+    let (subnet_summary, mut target_details) = merge_interface_results(run_interface_scans(&subnet_scans, context));
+
+    for sample_detail in sample_details.into_iter() {
+
+        let is_known = target_details.iter().any(|detail| {
+            detail.interface_name == sample_detail.interface_name && detail.vlan_id == sample_detail.vlan_id && detail.ipv4 == sample_detail.ipv4
+        });
+        if !is_known {
+            target_details.push(sample_detail);
+        }
+    }
+
+    // Both scans run one after the other, their durations are then added.
+    let mut response_summary = ResponseSummary::merge([&sample_summary, &subnet_summary].into_iter());
+    response_summary.duration_ms = sample_summary.duration_ms + subnet_summary.duration_ms;
+    response_summary.subnet_discovery = Some(discovery::compute_subnet_occupancy(&target_details, sampled_count));
+
+    (response_summary, target_details)
+}
+
+fn count_lanes_subnets(interface_lanes: &[(&NetworkInterface, Vec<ScanLane>)]) -> usize {
+
+    interface_lanes.iter()
+        .flat_map(|(_, scan_lanes)| scan_lanes.iter())
+        .map(|lane| discovery::list_subnets(&lane.target_ranges).len())
+        .sum()
+}
+
+fn compute_lanes_size(scan_lanes: &[ScanLane]) -> u128 {
+
+    scan_lanes.iter().map(|lane| {
+        utils::compute_network_size(&lane.target_ranges)
+    }).sum()
+}
+
+/**
+ * Perform the ARP scan on a single network interface, from the datalink
+ * channel creation to the collection of all responses. All results are linked
+ * to the interface name. A resumed scan starts at the saved progress.
+ */
+fn scan_interface(interface_scan: &InterfaceScan, context: &ScanContext) -> (ResponseSummary, Vec<TargetDetails>) {
+
+    let interface = interface_scan.interface;
+    let scan_lanes = &interface_scan.scan_lanes;
+    let response_monitor = &interface_scan.monitor;
+    let scan_options = context.options;
+    let has_reached_timeout = context.has_reached_timeout;
+
+    let resumed_progress = interface_scan.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+
+    let (tx, rx, statistics_reader) = open_datalink_channel(interface, scan_options);
+    let (mut tx, mut rx) = match context.pcap_writer {
+        Some(pcap_writer) => capture::capture_channel(tx, rx, interface, pcap_writer, open_timestamp_reader(&statistics_reader)),
+        None => (tx, rx)
+    };
+
+    // The 'timed_out' mutex is shared accross the interface thread (which
+    // performs ARP packet sending) and the response thread (which receives
+    // and stores all ARP responses).
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let cloned_timed_out = Arc::clone(&timed_out);
+
+    // The probe phase is only used for promiscuous checks, and tells the
+    // response thread which bogus MAC address is currently being sent.
+    let probe_phase = Arc::new(AtomicUsize::new(0));
+    let cloned_probe_phase = Arc::clone(&probe_phase);
+
+    let mut vendor_list = Vendor::new();
+
+    let cloned_options = Arc::clone(scan_options);
+    let cloned_lanes = scan_lanes.to_vec();
+    let cloned_monitor = Arc::clone(response_monitor);
+    let arp_responses = thread::spawn(move || network::receive_arp_responses(&mut rx, cloned_options, &cloned_lanes, cloned_timed_out, cloned_probe_phase, cloned_monitor, &mut vendor_list));
+
+    for (phase, destination_mac) in context.destination_phases.iter().enumerate().skip(resumed_progress.phase) {
+
+        // Responses of the previous phase are awaited before switching to the
+        // next probe, to avoid linking late responses to the wrong probe.
+        if phase > resumed_progress.phase {
+            wait_scan_timeout(has_reached_timeout, scan_options.timeout_ms, response_monitor, scan_options.adaptive_timeout);
+        }
+        probe_phase.store(phase, Ordering::Relaxed);
+
+        // Frame templates are built once per lane, only the target IPv4 is
+        // then written in the datalink buffer for each request.
+        let frame_templates: Vec<FrameTemplate> = scan_lanes.iter()
+            .map(|lane| FrameTemplate::new(interface, lane, *destination_mac, scan_options))
+            .collect();
+
+        let first_retry = match phase == resumed_progress.phase {
+            true => resumed_progress.retry_index,
+            false => 0
+        };
+
+        // The retry count does right now use a 'brute-force' strategy without
+        // synchronization process with the already known hosts.
+        for retry_index in first_retry..scan_options.retry_count {
+
+            if has_reached_timeout.load(Ordering::Relaxed) || response_monitor.has_all_responses() {
+                break;
+            }
+
+            // Each scan lane (VLAN) has its own target iterator, requests are
+            // interleaved accross lanes to spread the load on all VLANs. Each
+            // retry round has its own random order, derived from the seed.
+            let round_seed = scan_options.random_seed.map(|seed| permutation::derive_seed(seed, retry_index as u64));
+            let is_resumed_round = phase == resumed_progress.phase && retry_index == resumed_progress.retry_index;
+            let mut lane_iterators: Vec<(&FrameTemplate, NetworkIterator)> = scan_lanes.iter().zip(frame_templates.iter()).enumerate().map(|(lane_index, (lane, template))| {
+
+                let mut lane_iterator = NetworkIterator::from_ranges(lane.target_ranges.clone(), round_seed);
+                if is_resumed_round {
+                    lane_iterator.skip_to(resumed_progress.lane_positions[lane_index]);
+                }
+                (template, lane_iterator)
+            }).collect();
+
+            let mut request_batch: Vec<(&FrameTemplate, Ipv4Addr)> = Vec::with_capacity(context.batch_size);
+            let mut has_pending_targets = true;
+            while has_pending_targets && !has_reached_timeout.load(Ordering::Relaxed) {
+
+                has_pending_targets = false;
+                for lane_index in 0..lane_iterators.len() {
+
+                    if has_reached_timeout.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    // The pending batch is sent once full, or before waiting
+                    // for a token so that paced requests are never delayed.
+                    let has_token = context.rate_limiter.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).has_token();
+                    if !request_batch.is_empty() && (!has_token || request_batch.len() >= context.batch_size) {
+                        response_monitor.record_requests(request_batch.iter().map(|(template, target_ip)| (template.vlan_id(), *target_ip)));
+                        network::send_arp_batch(&mut tx, &request_batch);
+                        request_batch.clear();
+
+                        let lane_positions = lane_iterators.iter().map(|(_, lane_iterator)| lane_iterator.position()).collect();
+                        interface_scan.update_progress(phase, retry_index, lane_positions);
+                    }
+
+                    let (template, ip_addresses) = &mut lane_iterators[lane_index];
+                    let ip_address = match ip_addresses.next() {
+                        Some(ip_address) => ip_address,
+                        None => continue
+                    };
+                    has_pending_targets = true;
+
+                    if let IpAddr::V4(ipv4_address) = ip_address {
+                        context.rate_limiter.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).acquire();
+                        request_batch.push((*template, ipv4_address));
+                    }
+                }
+            }
+            response_monitor.record_requests(request_batch.iter().map(|(template, target_ip)| (template.vlan_id(), *target_ip)));
+            network::send_arp_batch(&mut tx, &request_batch);
+
+            let lane_positions = lane_iterators.iter().map(|(_, lane_iterator)| lane_iterator.position()).collect();
+            interface_scan.update_progress(phase, retry_index, lane_positions);
+
+            if !has_reached_timeout.load(Ordering::Relaxed) {
+                interface_scan.update_progress(phase, retry_index + 1, vec![0; scan_lanes.len()]);
+            }
+        }
+
+        if !has_reached_timeout.load(Ordering::Relaxed) {
+            interface_scan.update_progress(phase + 1, 0, vec![0; scan_lanes.len()]);
+        }
+    }
+
+    // Once the ARP packets are sent, the interface thread will sleep for T
+    // seconds (where T is the timeout option). After the sleep phase, the
+    // response thread will receive a stop request through 'timed_out'.
+    wait_scan_timeout(has_reached_timeout, scan_options.timeout_ms, response_monitor, scan_options.adaptive_timeout);
     timed_out.store(true, Ordering::Relaxed);
 
-    let (response_summary, target_details) = arp_responses.join().unwrap_or_else(|error| {
+    let (mut response_summary, target_details) = arp_responses.join().unwrap_or_else(|error| {
         eprintln!("Failed to close receive thread ({:?})", error);
         process::exit(1);
     });
 
-    match &scan_options.output {
-        OutputFormat::Plain => utils::display_scan_results(response_summary, target_details, &scan_options),
-        OutputFormat::Json => println!("{}", utils::export_to_json(response_summary, target_details)),
-        OutputFormat::Yaml => println!("{}", utils::export_to_yaml(response_summary, target_details)),
-        OutputFormat::Csv => print!("{}", utils::export_to_csv(response_summary, target_details))
+    response_summary.rtt_p99_us = response_monitor.rtt_percentile().map(|rtt| rtt.as_micros());
+    response_summary.kernel_statistics = read_kernel_statistics(statistics_reader);
+
+    let target_details = target_details.into_iter().map(|mut target_detail| {
+        target_detail.interface_name = Some(interface.name.clone());
+        target_detail
+    }).collect();
+
+    (response_summary, target_details)
+}
+
+/**
+ * Save the progress of all interface scans in a checkpoint file, including
+ * hosts found by previous (resumed) runs and hosts found so far. A failure
+ * to save a checkpoint does not stop the scan.
+ */
+fn save_checkpoint(checkpoint_file: &str, interface_scans: &[InterfaceScan], scan_options: &ScanOptions) {
+
+    let interfaces: Vec<InterfaceProgress> = interface_scans.iter()
+        .map(|interface_scan| interface_scan.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone())
+        .collect();
+
+    let mut hosts: Vec<CheckpointHost> = match &scan_options.resume_checkpoint {
+        Some(resume_checkpoint) => resume_checkpoint.hosts.clone(),
+        None => vec![]
+    };
+    for interface_scan in interface_scans.iter() {
+        for (vlan_id, ipv4, mac) in interface_scan.monitor.list_responses() {
+
+            let interface_name = &interface_scan.interface.name;
+            hosts.retain(|host| !(host.interface_name == *interface_name && host.vlan_id == vlan_id && host.ipv4 == ipv4));
+            hosts.push(CheckpointHost {
+                interface_name: interface_name.clone(),
+                vlan_id,
+                ipv4,
+                mac: mac.to_string()
+            });
+        }
+    }
+
+    let scan_checkpoint = ScanCheckpoint::new(scan_options.random_seed, interfaces, hosts);
+    scan_checkpoint.save(checkpoint_file).unwrap_or_else(|err| {
+        eprintln!("[warn] {}", err);
+    });
+}
+
+/**
+ * Add the hosts found by the resumed scan to the new results. A host found
+ * again keeps its new details, others are added with a hostname and vendor.
+ */
+fn merge_resumed_hosts(mut target_details: Vec<TargetDetails>, scan_options: &ScanOptions) -> Vec<TargetDetails> {
+
+    let resume_checkpoint = match &scan_options.resume_checkpoint {
+        Some(resume_checkpoint) => resume_checkpoint,
+        None => return target_details
+    };
+
+    let resumed_details: Vec<TargetDetails> = resume_checkpoint.hosts.iter().filter(|host| {
+
+        !target_details.iter().any(|detail| {
+            detail.interface_name.as_deref() == Some(host.interface_name.as_str()) && detail.vlan_id == host.vlan_id && detail.ipv4 == host.ipv4
+        })
+
+    }).filter_map(|host| {
+
+        let mac = MacAddr::from_str(&host.mac).ok()?;
+        Some(TargetDetails {
+            ipv4: host.ipv4,
+            mac,
+            ethernet_mac: mac,
+            vlan_id: host.vlan_id,
+            interface_name: Some(host.interface_name.clone()),
+            hostname: None,
+            vendor: None,
+            flags: vec![],
+            rtt: None,
+            discovered_at: None,
+            response_macs: vec![mac]
+        })
+
+    }).collect();
+
+    let mut vendor_list = Vendor::new();
+    target_details.extend(network::enrich_target_details(resumed_details, scan_options, &mut vendor_list));
+    target_details
+}
+
+#[cfg(target_os = "linux")]
+type StatisticsReader = Option<socket::StatisticsReader>;
+
+#[cfg(not(target_os = "linux"))]
+type StatisticsReader = Option<()>;
+
+/**
+ * Open the datalink channel used to send ARP requests and receive responses.
+ * On Linux, a kernel filter is attached to the socket so only ARP frames are
+ * copied to the response thread. Other platforms (or a filter failure) use
+ * the default pnet channel, filtering ARP frames in userspace.
+ */
+fn open_datalink_channel(interface: &NetworkInterface, options: &ScanOptions) -> (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>, StatisticsReader) {
+
+    let read_timeout = Duration::from_millis(network::DATALINK_RCV_TIMEOUT);
+
+    #[cfg(target_os = "linux")]
+    match socket::open_filtered_channel(interface, options.has_vlan(), read_timeout, network::DATALINK_SEND_BUFFER) {
+        Ok((tx, rx, statistics_reader)) => return (tx, rx, Some(statistics_reader)),
+        Err(error) => eprintln!("[warn] Could not attach kernel ARP filter, filtering in userspace ({})", error)
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = options;
+
+    let channel_config = pnet_datalink::Config {
+        read_timeout: Some(read_timeout), 
+        write_buffer_size: network::DATALINK_SEND_BUFFER,
+        ..pnet_datalink::Config::default()
+    };
+
+    match pnet_datalink::channel(interface, channel_config) {
+        Ok(pnet_datalink::Channel::Ethernet(tx, rx)) => (tx, rx, None),
+        Ok(_) => {
+            eprintln!("Expected an Ethernet datalink channel");
+            process::exit(1);
+        },
+        Err(error) => {
+            eprintln!("Datalink channel creation failed ({})", error);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_kernel_statistics(statistics_reader: StatisticsReader) -> Option<KernelStatistics> {
+
+    statistics_reader.and_then(|reader| reader.read().map_err(|error| {
+        eprintln!("[warn] Could not read kernel socket statistics ({})", error);
+    }).ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_kernel_statistics(_statistics_reader: StatisticsReader) -> Option<KernelStatistics> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn open_timestamp_reader(statistics_reader: &StatisticsReader) -> Option<TimestampReader> {
+
+    statistics_reader.as_ref().map(|reader| reader.timestamp_reader())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_timestamp_reader(_statistics_reader: &StatisticsReader) -> Option<TimestampReader> {
+    None
+}
+
+/**
+ * Sleep for the given timeout (in milliseconds) with 100ms steps, the sleep
+ * phase will be interrupted if a halt signal has been received. In adaptive
+ * mode, the sleep phase ends (with 10ms steps) as soon as all targets have
+ * answered or no response has been received for a while.
+ */
+fn wait_scan_timeout(has_reached_timeout: &AtomicBool, timeout_ms: u64, monitor: &ResponseMonitor, is_adaptive: bool) {
+
+    let sleep_step = match is_adaptive {
+        true => Duration::from_millis(10),
+        false => Duration::from_millis(100)
+    };
+    let timeout = Duration::from_millis(timeout_ms);
+    let start_sleep = Instant::now();
+
+    while !has_reached_timeout.load(Ordering::Relaxed) && start_sleep.elapsed() < timeout {
+
+        if is_adaptive && (monitor.has_all_responses() || monitor.is_quiet()) {
+            break;
+        }
+        thread::sleep(sleep_step);
     }
 }
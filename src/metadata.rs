@@ -0,0 +1,157 @@
+use std::process;
+use std::time::SystemTime;
+
+use pnet_datalink::NetworkInterface;
+use serde::Serialize;
+
+use crate::args::{ScanOptions, ScanTiming};
+use crate::network::{self, ScanLane};
+use crate::time;
+
+// The schema version is increased on each incompatible change of the
+// structured exports (renamed or removed fields, changed value types).
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+const CLI_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/**
+ * Describes how and where a scan has been performed, so that archived scan
+ * results can be compared later on (same networks, same timing settings, ...).
+ */
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ScanMetadata {
+    pub schema_version: u32,
+    pub version: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub hostname: Option<String>,
+    pub profile: String,
+    pub interfaces: Vec<InterfaceMetadata>,
+    pub timing: TimingMetadata
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceMetadata {
+    pub name: String,
+    pub mac: String,
+    pub lanes: Vec<LaneMetadata>
+}
+
+/**
+ * A scan lane of an interface, with its VLAN, ARP source IPv4 and networks.
+ */
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct LaneMetadata {
+    pub vlan: Option<u16>,
+    pub source_ipv4: String,
+    pub networks: Vec<String>
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TimingMetadata {
+    pub timeout_ms: u64,
+    pub adaptive_timeout: bool,
+    pub retry_count: usize,
+    pub interval_us: Option<u64>,
+    pub bandwidth: Option<u64>,
+    pub packet_rate: Option<u64>,
+    pub burst_size: u32,
+    pub random_seed: Option<u64>
+}
+
+impl ScanMetadata {
+
+    /**
+     * Build the metadata of a finished scan, the end timestamp being the
+     * current time.
+     */
+    pub fn new(started_at: SystemTime, interface_lanes: &[(&NetworkInterface, Vec<ScanLane>)], options: &ScanOptions) -> ScanMetadata {
+
+        let interfaces = interface_lanes.iter().map(|(interface, scan_lanes)| InterfaceMetadata {
+            name: interface.name.clone(),
+            mac: network::find_source_mac(interface, options).to_string(),
+            lanes: scan_lanes.iter().map(|lane| LaneMetadata {
+                vlan: lane.vlan_id,
+                source_ipv4: lane.source_ip.to_string(),
+                networks: lane.ip_networks.iter().map(|network| network.to_string()).collect()
+            }).collect()
+        }).collect();
+
+        let (interval_us, bandwidth, packet_rate) = match options.scan_timing {
+            ScanTiming::Interval(interval_us) => (Some(interval_us), None, None),
+            ScanTiming::Bandwidth(bandwidth) => (None, Some(bandwidth), None),
+            ScanTiming::PacketRate(packet_rate) => (None, None, Some(packet_rate))
+        };
+
+        ScanMetadata {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            version: CLI_VERSION.to_string(),
+            started_at: time::format_rfc3339(started_at),
+            finished_at: time::format_rfc3339(SystemTime::now()),
+            hostname: dns_lookup::get_hostname().ok(),
+            profile: options.profile.label().to_string(),
+            interfaces,
+            timing: TimingMetadata {
+                timeout_ms: options.timeout_ms,
+                adaptive_timeout: options.adaptive_timeout,
+                retry_count: options.retry_count,
+                interval_us,
+                bandwidth,
+                packet_rate,
+                burst_size: options.burst_size,
+                random_seed: options.random_seed
+            }
+        }
+    }
+
+    /**
+     * Format the metadata as CSV comment lines, placed before the CSV header.
+     * Nested values (interfaces and timing settings) are written as JSON.
+     */
+    pub fn format_csv_comments(&self) -> String {
+
+        let mut comments = String::new();
+        comments.push_str(&format!("# schema_version: {}\n", self.schema_version));
+        comments.push_str(&format!("# version: {}\n", self.version));
+        comments.push_str(&format!("# started_at: {}\n", self.started_at));
+        comments.push_str(&format!("# finished_at: {}\n", self.finished_at));
+        comments.push_str(&format!("# hostname: {}\n", self.hostname.as_deref().unwrap_or("")));
+        comments.push_str(&format!("# profile: {}\n", self.profile));
+        comments.push_str(&format!("# interfaces: {}\n", format_json(&self.interfaces)));
+        comments.push_str(&format!("# timing: {}\n", format_json(&self.timing)));
+        comments
+    }
+}
+
+fn format_json<T: Serialize>(value: &T) -> String {
+
+    serde_json::to_string(value).unwrap_or_else(|err| {
+        eprintln!("Could not export scan metadata ({})", err);
+        process::exit(1);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn should_format_csv_comments() {
+
+        let matches = crate::args::build_args().get_matches_from(vec!["arp-scan", "-t", "2s", "-r", "3"]);
+        let options = ScanOptions::new(&matches);
+        let mut metadata = ScanMetadata::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000), &[], &options);
+        metadata.hostname = Some("scanner".to_string());
+
+        let comments = metadata.format_csv_comments();
+
+        assert_eq!(metadata.started_at, "2023-11-14T22:13:20Z");
+        assert!(comments.starts_with("# schema_version: 1\n"));
+        assert!(comments.contains("# hostname: scanner\n"));
+        assert!(comments.contains("# interfaces: []\n"));
+        assert!(comments.contains("\"timeout_ms\":2000,\"adaptive_timeout\":false,\"retry_count\":3"));
+        assert!(comments.lines().all(|line| line.starts_with("# ")));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use pnet_datalink::MacAddr;
+
+// An adaptive scan stops once no response has been received for this many
+// times the 99th percentile RTT, with a minimum quiet period since a few
+// hosts (sleeping WiFi clients, busy routers) are always slower than others.
+const ADAPTIVE_RTT_FACTOR: u32 = 4;
+const ADAPTIVE_QUIET_MIN_MS: u64 = 100;
+
+const RTT_PERCENTILE: f64 = 0.99;
+
+/**
+ * The response monitor is shared between the sending thread and the response
+ * thread. It records when each target was requested, measures the round-trip
+ * time of ARP responses and tells an adaptive scan when to stop waiting.
+ */
+pub struct ResponseMonitor {
+    expected_count: Option<usize>,
+    state: Mutex<MonitorState>
+}
+
+struct MonitorState {
+    request_times: HashMap<(Option<u16>, Ipv4Addr), Instant>,
+    answered_targets: HashSet<(Option<u16>, Ipv4Addr)>,
+    responses: HashMap<(Option<u16>, Ipv4Addr), MacAddr>,
+    rtt_samples: Vec<Duration>,
+    last_activity: Instant
+}
+
+impl ResponseMonitor {
+
+    /**
+     * Create a new response monitor, the expected count is the number of
+     * distinct targets that may answer (no early exit if none is given). A
+     * target is identified by its VLAN and IPv4, the same IPv4 may be scanned
+     * on several VLANs.
+     */
+    pub fn new(expected_count: Option<usize>) -> ResponseMonitor {
+
+        ResponseMonitor {
+            expected_count,
+            state: Mutex::new(MonitorState {
+                request_times: HashMap::new(),
+                answered_targets: HashSet::new(),
+                responses: HashMap::new(),
+                rtt_samples: vec![],
+                last_activity: Instant::now()
+            })
+        }
+    }
+
+    /**
+     * Record the sending time of a batch of ARP requests. A retry overwrites
+     * the previous sending time, the RTT is measured from the last request.
+     */
+    pub fn record_requests(&self, targets: impl Iterator<Item = (Option<u16>, Ipv4Addr)>) {
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for target in targets {
+            state.request_times.insert(target, now);
+        }
+        state.last_activity = now;
+    }
+
+    /**
+     * Record an ARP reply and return the measured round-trip time. Replies
+     * from hosts that were never requested on this VLAN (gratuitous ARP,
+     * other scanners, ...) are ignored.
+     */
+    pub fn record_response(&self, vlan_id: Option<u16>, sender_ip: Ipv4Addr, sender_mac: MacAddr) -> Option<Duration> {
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let request_time = *state.request_times.get(&(vlan_id, sender_ip))?;
+        let rtt = now.duration_since(request_time);
+
+        state.rtt_samples.push(rtt);
+        state.answered_targets.insert((vlan_id, sender_ip));
+        state.responses.insert((vlan_id, sender_ip), sender_mac);
+        state.last_activity = now;
+
+        Some(rtt)
+    }
+
+    /**
+     * Returns true once every expected target has answered at least once,
+     * the scan can then end without waiting for the timeout.
+     */
+    pub fn has_all_responses(&self) -> bool {
+
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match self.expected_count {
+            Some(expected_count) => expected_count > 0 && state.answered_targets.len() >= expected_count,
+            None => false
+        }
+    }
+
+    /**
+     * List all hosts that answered a request so far, as (VLAN, IPv4, MAC).
+     * This allows saving the scan progress while the scan is running.
+     */
+    pub fn list_responses(&self) -> Vec<(Option<u16>, Ipv4Addr, MacAddr)> {
+
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.responses.iter().map(|((vlan_id, ipv4), mac)| (*vlan_id, *ipv4, *mac)).collect()
+    }
+
+    pub fn rtt_percentile(&self) -> Option<Duration> {
+
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        compute_percentile(&state.rtt_samples, RTT_PERCENTILE)
+    }
+
+    /**
+     * Returns true if no request was sent and no response was received for
+     * the adaptive quiet period. Without any RTT sample, the scan can not
+     * adapt and will be considered as active until the full timeout.
+     */
+    pub fn is_quiet(&self) -> bool {
+
+        let last_activity = {
+            let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            state.last_activity
+        };
+
+        match self.rtt_percentile() {
+            Some(rtt) => last_activity.elapsed() >= compute_quiet_period(rtt),
+            None => false
+        }
+    }
+}
+
+/**
+ * Compute the duration without any response after which an adaptive scan
+ * considers that all hosts have answered.
+ */
+pub fn compute_quiet_period(rtt: Duration) -> Duration {
+
+    let quiet_period = rtt.saturating_mul(ADAPTIVE_RTT_FACTOR);
+    quiet_period.max(Duration::from_millis(ADAPTIVE_QUIET_MIN_MS))
+}
+
+/**
+ * Compute a percentile (between 0 and 1) of the given RTT samples with the
+ * nearest-rank method. No percentile can be computed without samples.
+ */
+pub fn compute_percentile(samples: &[Duration], percentile: f64) -> Option<Duration> {
+
+    if samples.is_empty() {
+        return None;
+    }
+
+    let mut sorted_samples = samples.to_vec();
+    sorted_samples.sort_unstable();
+
+    let rank = (percentile * sorted_samples.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, sorted_samples.len()) - 1;
+    Some(sorted_samples[index])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_compute_percentile() {
+
+        let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+
+        assert_eq!(compute_percentile(&samples, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(compute_percentile(&samples, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(compute_percentile(&samples, 0.0), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn should_not_compute_empty_percentile() {
+
+        assert_eq!(compute_percentile(&[], 0.99), None);
+    }
+
+    #[test]
+    fn should_bound_quiet_period() {
+
+        assert_eq!(compute_quiet_period(Duration::from_millis(2)), Duration::from_millis(100));
+        assert_eq!(compute_quiet_period(Duration::from_millis(50)), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn should_ignore_unrequested_responses() {
+
+        let monitor = ResponseMonitor::new(Some(1));
+        monitor.record_requests(vec![(None, Ipv4Addr::new(10, 0, 0, 1))].into_iter());
+
+        assert_eq!(monitor.record_response(None, Ipv4Addr::new(10, 0, 0, 2), MacAddr::zero()), None);
+        assert!(!monitor.has_all_responses());
+        assert_eq!(monitor.rtt_percentile(), None);
+    }
+
+    #[test]
+    fn should_detect_all_responses() {
+
+        let monitor = ResponseMonitor::new(Some(2));
+        monitor.record_requests(vec![(None, Ipv4Addr::new(10, 0, 0, 1)), (None, Ipv4Addr::new(10, 0, 0, 2))].into_iter());
+
+        assert!(monitor.record_response(None, Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()).is_some());
+        assert!(monitor.record_response(None, Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()).is_some());
+        assert!(!monitor.has_all_responses());
+
+        assert!(monitor.record_response(None, Ipv4Addr::new(10, 0, 0, 2), MacAddr::zero()).is_some());
+        assert!(monitor.has_all_responses());
+        assert!(monitor.rtt_percentile().is_some());
+        assert_eq!(monitor.list_responses().len(), 2);
+    }
+
+    #[test]
+    fn should_never_end_without_expected_count() {
+
+        let monitor = ResponseMonitor::new(None);
+        monitor.record_requests(vec![(None, Ipv4Addr::new(10, 0, 0, 1))].into_iter());
+        monitor.record_response(None, Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero());
+
+        assert!(!monitor.has_all_responses());
+    }
+
+    #[test]
+    fn should_track_responses_per_vlan() {
+
+        let monitor = ResponseMonitor::new(Some(2));
+        monitor.record_requests(vec![(Some(10), Ipv4Addr::new(10, 0, 0, 1)), (Some(20), Ipv4Addr::new(10, 0, 0, 1))].into_iter());
+
+        assert!(monitor.record_response(Some(10), Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()).is_some());
+        assert!(monitor.record_response(Some(10), Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()).is_some());
+        assert!(!monitor.has_all_responses());
+
+        assert_eq!(monitor.record_response(Some(30), Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()), None);
+        assert!(!monitor.has_all_responses());
+
+        assert!(monitor.record_response(Some(20), Ipv4Addr::new(10, 0, 0, 1), MacAddr::zero()).is_some());
+        assert!(monitor.has_all_responses());
+        assert_eq!(monitor.list_responses().len(), 2);
+    }
+}
@@ -1,41 +1,58 @@
 use std::process;
 use std::net::{IpAddr, Ipv4Addr};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::io::ErrorKind::TimedOut;
 use std::convert::TryInto;
 
 use dns_lookup::lookup_addr;
-use ipnetwork::IpNetwork;
+use ipnetwork::{IpNetwork, Ipv4Network};
 use pnet_datalink::{MacAddr, NetworkInterface, DataLinkSender, DataLinkReceiver};
-use pnet::packet::{MutablePacket, Packet};
+use pnet::packet::Packet;
 use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket, EtherTypes};
 use pnet::packet::arp::{MutableArpPacket, ArpOperations, ArpHardwareTypes, ArpPacket};
-use pnet::packet::vlan::{ClassOfService, MutableVlanPacket};
-use rand::prelude::*;
+use pnet::packet::vlan::{ClassOfService, MutableVlanPacket, VlanPacket};
 
-use crate::args::ScanOptions;
+use crate::args::{ScanOptions, VlanTag};
+use crate::discovery::SubnetDiscovery;
+use crate::metadata::ScanMetadata;
+use crate::monitor::ResponseMonitor;
+use crate::permutation::TargetPermutation;
 use crate::vendor::Vendor;
 use crate::utils;
 use crate::args::ScanTiming;
 
 pub const DATALINK_RCV_TIMEOUT: u64 = 500;
+pub const DATALINK_SEND_BUFFER: usize = 65536;
 
-const VLAN_QOS_DEFAULT: u8 = 1;
-const ARP_PACKET_SIZE: usize = 28;
-const VLAN_PACKET_SIZE: usize = 32;
+const ETHERNET_HEADER_SIZE: usize = 14;
+const VLAN_TAG_SIZE: usize = 4;
 
 const ETHERNET_STD_PACKET_SIZE: usize = 42;
-const ETHERNET_VLAN_PACKET_SIZE: usize = 46;
+const ARP_TARGET_IP_OFFSET: usize = 38;
+
+/**
+ * Bogus destination MAC addresses used to detect hosts in promiscuous mode.
+ * A network card filtering frames in hardware will drop these requests, while
+ * a card in promiscuous mode forwards them to a kernel that may answer.
+ */
+pub const PROMISC_PROBES: [(&str, MacAddr); 6] = [
+    ("B47", MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xfe)),
+    ("B16", MacAddr(0xff, 0xff, 0x00, 0x00, 0x00, 0x00)),
+    ("B8", MacAddr(0xff, 0x00, 0x00, 0x00, 0x00, 0x00)),
+    ("Gr", MacAddr(0x01, 0x00, 0x00, 0x00, 0x00, 0x00)),
+    ("M0", MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0x00)),
+    ("M1", MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0x01))
+];
 
 /**
  * Contains scan estimation records. This will be computed before the scan
  * starts and should give insights about the scan.
  */
 pub struct ScanEstimation {
-    pub interval_ms: u64,
+    pub packet_rate: Option<f64>,
     pub duration_ms: u128,
     pub request_size: u128,
     pub bandwidth: u128
@@ -45,62 +62,375 @@ pub struct ScanEstimation {
  * Gives high-level details about the scan response. This may include Ethernet
  * details (packet count, size, ...) and other technical network aspects.
  */
+#[derive(Clone, Default)]
 pub struct ResponseSummary {
     pub packet_count: usize,
     pub arp_count: usize,
-    pub duration_ms: u128
+    pub duration_ms: u128,
+    pub request_count: u64,
+    pub requested_rate: Option<f64>,
+    pub achieved_rate: Option<f64>,
+    pub rtt_p99_us: Option<u128>,
+    pub kernel_statistics: Option<KernelStatistics>,
+    pub subnet_discovery: Option<SubnetDiscovery>,
+    pub scan_metadata: Option<ScanMetadata>
+}
+
+impl ResponseSummary {
+
+    /**
+     * Merge the response summaries of many interface scans. Counters are added
+     * while the duration and response time of the slowest interface are kept.
+     * Request counters and rates are left to the caller, since the rate limiter
+     * is shared by all interfaces.
+     */
+    pub fn merge<'a>(summaries: impl Iterator<Item = &'a ResponseSummary>) -> ResponseSummary {
+
+        summaries.fold(ResponseSummary::default(), |merged, summary| {
+
+            let kernel_statistics = match (merged.kernel_statistics, summary.kernel_statistics) {
+                (Some(merged_statistics), Some(statistics)) => Some(KernelStatistics {
+                    packet_count: merged_statistics.packet_count + statistics.packet_count,
+                    drop_count: merged_statistics.drop_count + statistics.drop_count
+                }),
+                (merged_statistics, statistics) => merged_statistics.or(statistics)
+            };
+
+            ResponseSummary {
+                packet_count: merged.packet_count + summary.packet_count,
+                arp_count: merged.arp_count + summary.arp_count,
+                duration_ms: merged.duration_ms.max(summary.duration_ms),
+                rtt_p99_us: merged.rtt_p99_us.max(summary.rtt_p99_us),
+                kernel_statistics,
+                ..merged
+            }
+        })
+    }
+}
+
+/**
+ * Receive statistics reported by the kernel for a filtered socket. Frames
+ * rejected by the kernel filter are never counted nor copied to userspace,
+ * the packet count only includes the frames queued to the socket (drops are
+ * counted apart).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelStatistics {
+    pub packet_count: u64,
+    pub drop_count: u64
 }
 
 /**
  * A target detail represents a single host on the local network with an IPv4
  * address and a linked MAC address. Hostnames are optional since some hosts
  * does not respond to the resolve call (or the numeric mode may be enabled).
+ * The interface name is only known once the interface scan is over. The MAC
+ * is the last one received, while all distinct MACs are kept in arrival order.
  */
+#[derive(Clone)]
 pub struct TargetDetails {
     pub ipv4: Ipv4Addr,
     pub mac: MacAddr,
+    pub ethernet_mac: MacAddr,
+    pub vlan_id: Option<u16>,
+    pub interface_name: Option<String>,
     pub hostname: Option<String>,
-    pub vendor: Option<String>
+    pub vendor: Option<String>,
+    pub flags: Vec<TargetFlag>,
+    pub rtt: Option<Duration>,
+    pub discovered_at: Option<Instant>,
+    pub response_macs: Vec<MacAddr>
+}
+
+#[cfg(test)]
+impl TargetDetails {
+
+    /**
+     * Build a target answering with a single MAC address, other fields are
+     * empty and may be set with the struct update syntax.
+     */
+    pub fn for_test(ipv4: Ipv4Addr, mac: MacAddr) -> TargetDetails {
+
+        TargetDetails {
+            ipv4,
+            mac,
+            ethernet_mac: mac,
+            vlan_id: None,
+            interface_name: None,
+            hostname: None,
+            vendor: None,
+            flags: vec![],
+            rtt: None,
+            discovered_at: None,
+            response_macs: vec![mac]
+        }
+    }
+}
+
+/**
+ * A target flag marks a suspicious or noteworthy host behavior, found while
+ * analyzing all ARP responses after the scan.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetFlag {
+    SharedMac(usize),
+    PromiscuousProbe(usize),
+    MacMismatch
+}
+
+impl TargetFlag {
+
+    pub fn label(&self) -> String {
+
+        match self {
+            TargetFlag::SharedMac(ip_count) => format!("shared-mac:{}", ip_count),
+            TargetFlag::PromiscuousProbe(probe_index) => format!("promisc:{}", PROMISC_PROBES[*probe_index].0),
+            TargetFlag::MacMismatch => "mac-mismatch".to_string()
+        }
+    }
+}
+
+/**
+ * A scan lane groups all targets sharing the same VLAN tag stack and ARP
+ * source addresses. A scan without VLAN will have a single untagged lane,
+ * while a trunk scan will have one lane per requested VLAN.
+ */
+#[derive(Clone)]
+pub struct ScanLane {
+    pub vlan_id: Option<u16>,
+    pub vlan_tags: Vec<VlanTag>,
+    pub ip_networks: Vec<IpNetwork>,
+    pub target_ranges: Vec<TargetRange>,
+    pub source_ip: Ipv4Addr,
+    pub source_mac: MacAddr
+}
+
+/**
+ * An inclusive range of IPv4 targets. The networks of a scan lane are turned
+ * into target ranges once all excluded addresses have been removed, these
+ * ranges are then used for iterating and counting the real scan targets.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetRange {
+    pub first: u32,
+    pub last: u32
+}
+
+impl TargetRange {
+
+    pub fn size(&self) -> u64 {
+        u64::from(self.last - self.first) + 1
+    }
 }
 
 /**
  * Compute a network configuration based on the scan options and available
- * interfaces. This configuration will be used in the scan process to target a
- * specific network on a network interfaces.
+ * interfaces. This configuration will be used in the scan process to target
+ * specific networks on one or many network interfaces (each interface being
+ * scanned in parallel).
  */
-pub fn compute_network_configuration<'a>(interfaces: &'a [NetworkInterface], scan_options: &'a Arc<ScanOptions>) -> (&'a NetworkInterface, Vec<&'a IpNetwork>) {
+pub fn compute_network_configuration<'a>(interfaces: &'a [NetworkInterface], scan_options: &'a Arc<ScanOptions>) -> Vec<(&'a NetworkInterface, Vec<&'a IpNetwork>)> {
+
+    if scan_options.all_interfaces {
 
-    let interface_name = match &scan_options.interface_name {
-        Some(name) => String::from(name),
-        None => {
+        let eligible_interfaces: Vec<(&NetworkInterface, Vec<&IpNetwork>)> = interfaces.iter()
+            .filter(|interface| utils::is_eligible_interface(interface))
+            .map(|interface| (interface, select_interface_networks(interface, None, scan_options)))
+            .collect();
 
-            let name = utils::select_default_interface(interfaces).map(|interface| interface.name);
+        if eligible_interfaces.is_empty() {
+            eprintln!("Could not find any network interface ready for ARP scans");
+            eprintln!("Use 'arp scan -l' to list available interfaces");
+            process::exit(1);
+        }
+        return eligible_interfaces;
+    }
 
-            match name {
-                Some(name) => name,
-                None => {
-                    eprintln!("Could not find a default network interface");
-                    eprintln!("Use 'arp scan -l' to list available interfaces");
-                    process::exit(1);
-                }
+    if scan_options.interface_targets.is_empty() {
+
+        let interface_name = match utils::select_default_interface(interfaces) {
+            Some(interface) => interface.name,
+            None => {
+                eprintln!("Could not find a default network interface");
+                eprintln!("Use 'arp scan -l' to list available interfaces");
+                process::exit(1);
             }
+        };
+
+        let selected_interface = find_scan_interface(interfaces, &interface_name);
+        return vec![(selected_interface, select_interface_networks(selected_interface, None, scan_options))];
+    }
+
+    let mut configurations: Vec<(&NetworkInterface, Vec<&IpNetwork>)> = vec![];
+    for interface_target in scan_options.interface_targets.iter() {
+
+        let selected_interface = find_scan_interface(interfaces, &interface_target.interface_name);
+        if configurations.iter().any(|(interface, _)| interface.name == selected_interface.name) {
+            eprintln!("Interface {} is given more than once", selected_interface.name);
+            process::exit(1);
         }
-    };
 
-    let selected_interface: &NetworkInterface = interfaces.iter()
+        let ip_networks = select_interface_networks(selected_interface, interface_target.network_range.as_ref(), scan_options);
+        configurations.push((selected_interface, ip_networks));
+    }
+    configurations
+}
+
+fn find_scan_interface<'a>(interfaces: &'a [NetworkInterface], interface_name: &str) -> &'a NetworkInterface {
+
+    interfaces.iter()
         .find(|interface| { interface.name == interface_name && interface.is_up() && !interface.is_loopback() })
         .unwrap_or_else(|| {
             eprintln!("Could not find interface with name {}", interface_name);
             eprintln!("Make sure the interface is up, not loopback and has a valid IPv4");
             process::exit(1);
-        });
+        })
+}
+
+/**
+ * Select the networks scanned on an interface: a network range dedicated to
+ * the interface has the priority over the global network range, the networks
+ * of the interface itself being used by default.
+ */
+fn select_interface_networks<'a>(interface: &'a NetworkInterface, interface_range: Option<&'a Vec<IpNetwork>>, scan_options: &'a ScanOptions) -> Vec<&'a IpNetwork> {
 
-    let ip_networks: Vec<&ipnetwork::IpNetwork> = match &scan_options.network_range {
+    match interface_range.or(scan_options.network_range.as_ref()) {
         Some(network_range) => network_range.iter().collect(),
-        None => selected_interface.ips.iter().filter(|ip_network| ip_network.is_ipv4()).collect()
-    };
+        None => interface.ips.iter().filter(|ip_network| ip_network.is_ipv4()).collect()
+    }
+}
+
+/**
+ * Split the scan in lanes, one for each requested VLAN (or a single lane if
+ * no VLAN is given). VLANs with a dedicated network range or source IPv4 will
+ * override the default targets and source address of the scan.
+ */
+pub fn compute_scan_lanes(interface: &NetworkInterface, ip_networks: &[&IpNetwork], options: &ScanOptions) -> Vec<ScanLane> {
+
+    let default_networks: Vec<IpNetwork> = ip_networks.iter().map(|network| *(*network)).collect();
+    let source_mac = find_source_mac(interface, options);
+
+    if !options.has_vlan() {
+        let source_ip = find_source_ip(interface, options.source_ipv4);
+        return vec![ScanLane {
+            vlan_id: None,
+            vlan_tags: vec![],
+            target_ranges: compute_lane_ranges(interface, &default_networks, source_ip, options),
+            ip_networks: default_networks,
+            source_ip,
+            source_mac
+        }];
+    }
+
+    options.vlan_tags.iter().map(|vlan_tag| {
+
+        let vlan_network = options.find_vlan_network(vlan_tag.vlan_id);
+
+        let lane_networks = match vlan_network {
+            Some(vlan_network) => vlan_network.network_range.clone(),
+            None => default_networks.clone()
+        };
+        let forced_source_ipv4 = vlan_network.and_then(|vlan_network| vlan_network.source_ipv4).or(options.source_ipv4);
+        let source_ip = find_source_ip(interface, forced_source_ipv4);
+
+        let mut vlan_tags = options.outer_vlan_tags.clone();
+        vlan_tags.push(*vlan_tag);
+
+        ScanLane {
+            vlan_id: Some(vlan_tag.vlan_id),
+            vlan_tags,
+            target_ranges: compute_lane_ranges(interface, &lane_networks, source_ip, options),
+            ip_networks: lane_networks,
+            source_ip,
+            source_mac
+        }
+
+    }).collect()
+}
+
+/**
+ * Compute the target ranges of a scan lane, excluding the addresses given by
+ * the user and the addresses of the scanner itself (the interface IPv4s and
+ * the source IPv4 of the lane).
+ */
+fn compute_lane_ranges(interface: &NetworkInterface, lane_networks: &[IpNetwork], source_ip: Ipv4Addr, options: &ScanOptions) -> Vec<TargetRange> {
+
+    let mut excluded_networks: Vec<IpNetwork> = options.excluded_networks.clone();
+    let own_addresses = interface.ips.iter()
+        .filter_map(|ip_network| match ip_network {
+            IpNetwork::V4(ipv4_network) => Some(ipv4_network.ip()),
+            IpNetwork::V6(_) => None
+        })
+        .chain(std::iter::once(source_ip));
+
+    for own_address in own_addresses {
+        excluded_networks.push(IpNetwork::V4(Ipv4Network::from(own_address)));
+    }
+
+    compute_target_ranges(lane_networks, &excluded_networks, options.skip_network_bounds, &options.list_range_networks())
+}
+
+/**
+ * Turn networks in target ranges, without the excluded networks. Network and
+ * broadcast addresses can also be skipped, except for /31 and /32 networks
+ * where all addresses are valid hosts (RFC 3021) and for networks expanded
+ * from target ranges or patterns (these bounds were explicitly requested).
+ * The order of the networks is kept, overlapping networks are not merged.
+ */
+pub fn compute_target_ranges(ip_networks: &[IpNetwork], excluded_networks: &[IpNetwork], skip_network_bounds: bool, range_networks: &[IpNetwork]) -> Vec<TargetRange> {
+
+    let mut excluded_ranges: Vec<TargetRange> = excluded_networks.iter()
+        .filter_map(|network| match network {
+            IpNetwork::V4(ipv4_network) => Some(TargetRange {
+                first: u32::from(ipv4_network.network()),
+                last: u32::from(ipv4_network.broadcast())
+            }),
+            IpNetwork::V6(_) => None
+        })
+        .collect();
+    excluded_ranges.sort_by_key(|range| range.first);
+
+    let mut target_ranges: Vec<TargetRange> = vec![];
+    for ip_network in ip_networks.iter() {
+
+        let ipv4_network = match ip_network {
+            IpNetwork::V4(ipv4_network) => ipv4_network,
+            IpNetwork::V6(_) => {
+                eprintln!("IPv6 networks are not supported by the ARP protocol");
+                process::exit(1);
+            }
+        };
 
-    (selected_interface, ip_networks)
+        let mut first = u32::from(ipv4_network.network());
+        let mut last = u32::from(ipv4_network.broadcast());
+        if skip_network_bounds && ipv4_network.prefix() < 31 && !range_networks.contains(ip_network) {
+            first += 1;
+            last -= 1;
+        }
+
+        // Excluded ranges are sorted, each one cuts the remaining part of the
+        // network range (a target range is kept before each exclusion).
+        let mut next_first = Some(first);
+        for excluded_range in excluded_ranges.iter() {
+
+            let range_first = match next_first {
+                Some(range_first) => range_first,
+                None => break
+            };
+            if excluded_range.last < range_first || excluded_range.first > last {
+                continue;
+            }
+            if excluded_range.first > range_first {
+                target_ranges.push(TargetRange { first: range_first, last: excluded_range.first - 1 });
+            }
+            next_first = excluded_range.last.checked_add(1).filter(|value| *value <= last);
+        }
+
+        if let Some(range_first) = next_first {
+            target_ranges.push(TargetRange { first: range_first, last });
+        }
+    }
+    target_ranges
 }
 
 /**
@@ -111,10 +441,7 @@ pub fn compute_network_configuration<'a>(interfaces: &'a [NetworkInterface], sca
 pub fn compute_scan_estimation(host_count: u128, options: &Arc<ScanOptions>) -> ScanEstimation {
 
     let timeout: u128 = options.timeout_ms.into();
-    let packet_size: u128 = match options.has_vlan() {
-        true => ETHERNET_VLAN_PACKET_SIZE.try_into().expect("Internal number conversion failed for VLAN packet size"),
-        false => ETHERNET_STD_PACKET_SIZE.try_into().expect("Internal number conversion failed for Ethernet packet size")
-    };
+    let packet_size: u128 = compute_frame_size(options).try_into().expect("Internal number conversion failed for Ethernet packet size");
     let retry_count: u128 = options.retry_count.try_into().unwrap_or_else(|err| {
         eprintln!("[warn] Could not cast retry count, defaults to 1 - {}", err);
         1
@@ -122,40 +449,36 @@ pub fn compute_scan_estimation(host_count: u128, options: &Arc<ScanOptions>) ->
 
     // The values below are averages based on an amount of performed network
     // scans. This may of course vary based on network configurations.
-    let avg_arp_request_ms: u128 = 3;
+    let avg_arp_request_us: f64 = 100.0;
     let avg_resolve_ms = 500;
 
-    let request_size: u128 = host_count * packet_size;
-
-    // Either the user provides an interval (expressed in milliseconds), either
-    // he provides a bandwidth (in bits per second) or either we are using the
-    // default interval. The goal of the code below is to compute the interval
-    // & bandwidth, based on the given inputs. Note that the computations in
-    // each match arm are therefore linked (but rewritten, based on the inputs).
-    let (interval_ms, bandwidth, request_phase_ms): (u64, u128, u128) = match options.scan_timing {
-        ScanTiming::Bandwidth(bandwidth) => {
-
-            let bandwidth_lg: u128 = bandwidth.into();
-            let request_phase_ms: u128 = (request_size * 1000) / bandwidth_lg;
-            let interval_ms: u128 = (request_phase_ms/retry_count/host_count) - avg_arp_request_ms;
-            
-            (interval_ms.try_into().unwrap(), bandwidth_lg, request_phase_ms)
-
-        },
-        ScanTiming::Interval(interval) => {
-
-            let interval_ms_lg: u128 = interval.into();
-            let request_phase_ms: u128 = (host_count * (avg_arp_request_ms + interval_ms_lg)) * retry_count;
-            let bandwidth = (request_size * 1000) / request_phase_ms;
+    let request_count: u128 = host_count * retry_count;
+    let request_size: u128 = request_count * packet_size;
+
+    // Either the user provides an interval (expressed in microseconds), a
+    // bandwidth (in bits per second), a packet rate or either we are using the
+    // profile defaults. All timing constraints are converted to a packet rate,
+    // a zero value (or interval) meaning that no rate limit is applied.
+    let packet_rate: Option<f64> = match options.scan_timing {
+        ScanTiming::Interval(0) | ScanTiming::Bandwidth(0) | ScanTiming::PacketRate(0) => None,
+        ScanTiming::Interval(interval_us) => Some(1_000_000.0 / interval_us as f64),
+        ScanTiming::Bandwidth(bandwidth) => Some(bandwidth as f64 / (packet_size * 8) as f64),
+        ScanTiming::PacketRate(packets_second) => Some(packets_second as f64)
+    };
 
-            (interval, bandwidth, request_phase_ms)
-        }
+    let request_phase_ms: u128 = match packet_rate {
+        Some(rate) => (request_count as f64 * 1000.0 / rate) as u128,
+        None => (request_count as f64 * avg_arp_request_us / 1000.0) as u128
+    };
+    let bandwidth = match request_phase_ms {
+        0 => request_size * 1000,
+        _ => (request_size * 1000) / request_phase_ms
     };
     
     let duration_ms = request_phase_ms + timeout + avg_resolve_ms;
 
     ScanEstimation {
-        interval_ms,
+        packet_rate,
         duration_ms,
         request_size,
         bandwidth
@@ -163,44 +486,184 @@ pub fn compute_scan_estimation(host_count: u128, options: &Arc<ScanOptions>) ->
 }
 
 /**
- * Send a single ARP request - using a datalink-layer sender, a given network
- * interface and a target IPv4 address. The ARP request will be broadcasted to
- * the whole local network with the first valid IPv4 address on the interface.
+ * Computes the Ethernet frame size of a single ARP request. Each VLAN tag in
+ * the tag stack (802.1Q or 802.1ad) adds 4 bytes to the standard frame.
  */
-pub fn send_arp_request(tx: &mut Box<dyn DataLinkSender>, interface: &NetworkInterface, source_ip: Ipv4Addr, target_ip: Ipv4Addr, options: Arc<ScanOptions>) {
+pub fn compute_frame_size(options: &ScanOptions) -> usize {
 
-    let mut ethernet_buffer = match options.has_vlan() {
-        true => vec![0u8; ETHERNET_VLAN_PACKET_SIZE],
-        false => vec![0u8; ETHERNET_STD_PACKET_SIZE]
+    let tag_count = match options.has_vlan() {
+        true => options.outer_vlan_tags.len() + 1,
+        false => 0
     };
-    let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap_or_else(|| {
-        eprintln!("Could not build Ethernet packet");
-        process::exit(1);
-    });
+    ETHERNET_STD_PACKET_SIZE + VLAN_TAG_SIZE * tag_count
+}
+
+/**
+ * Find the destination MAC address of standard ARP requests. Requests are
+ * broadcasted, unless a destination MAC address has been forced.
+ */
+pub fn find_destination_mac(options: &ScanOptions) -> MacAddr {
 
-    let target_mac = match options.destination_mac {
+    match options.destination_mac {
         Some(forced_mac) => forced_mac,
         None => MacAddr::broadcast()
-    };
-    let source_mac = match options.source_mac {
+    }
+}
+
+/**
+ * Find the source MAC address of ARP requests, either the forced source MAC
+ * address or the network interface MAC address.
+ */
+pub fn find_source_mac(interface: &NetworkInterface, options: &ScanOptions) -> MacAddr {
+
+    match options.source_mac {
         Some(forced_source_mac) => forced_source_mac,
         None => interface.mac.unwrap_or_else(|| {
             eprintln!("Interface should have a MAC address");
             process::exit(1);
         })
+    }
+}
+
+/**
+ * Send a single ARP request - using a datalink-layer sender, a given network
+ * interface and a target IPv4 address. The ARP request will be sent to the
+ * destination MAC (usually broadcast) with the source IPv4 of the scan lane.
+ * The whole frame is built for each request, large scans should rather use
+ * frame templates with batches (see 'send_arp_batch').
+ */
+pub fn send_arp_request(tx: &mut Box<dyn DataLinkSender>, interface: &NetworkInterface, lane: &ScanLane, target_ip: Ipv4Addr, destination_mac: MacAddr, options: Arc<ScanOptions>) {
+
+    let source_mac = find_source_mac(interface, &options);
+    let ethernet_buffer = build_arp_frame(source_mac, destination_mac, lane.source_ip, target_ip, &lane.vlan_tags, &options);
+
+    tx.send_to(&ethernet_buffer, Some(interface.clone()));
+}
+
+/**
+ * A prebuilt ARP request frame for a scan lane and a destination MAC address.
+ * All header fields are written once, only the target IPv4 address will be
+ * patched for each request.
+ */
+pub struct FrameTemplate {
+    frame: Vec<u8>,
+    target_ip_offset: usize,
+    vlan_id: Option<u16>
+}
+
+impl FrameTemplate {
+
+    pub fn new(interface: &NetworkInterface, lane: &ScanLane, destination_mac: MacAddr, options: &ScanOptions) -> FrameTemplate {
+
+        let source_mac = find_source_mac(interface, options);
+        let frame = build_arp_frame(source_mac, destination_mac, lane.source_ip, Ipv4Addr::UNSPECIFIED, &lane.vlan_tags, options);
+
+        FrameTemplate {
+            frame,
+            target_ip_offset: ARP_TARGET_IP_OFFSET + VLAN_TAG_SIZE * lane.vlan_tags.len(),
+            vlan_id: lane.vlan_id
+        }
+    }
+
+    pub fn vlan_id(&self) -> Option<u16> {
+        self.vlan_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame.is_empty()
+    }
+
+    /**
+     * Write the ARP request for the target IPv4 in the given buffer, which
+     * should have the same size as the template frame.
+     */
+    pub fn write_frame(&self, target_ip: Ipv4Addr, buffer: &mut [u8]) {
+
+        buffer.copy_from_slice(&self.frame);
+        buffer[self.target_ip_offset..self.target_ip_offset + 4].copy_from_slice(&target_ip.octets());
+    }
+}
+
+/**
+ * Computes the maximum amount of frames sent in a single batch, based on the
+ * datalink send buffer size (all frames of a batch share this buffer).
+ */
+pub fn compute_batch_capacity(options: &ScanOptions) -> usize {
+
+    (DATALINK_SEND_BUFFER / compute_frame_size(options)).max(1)
+}
+
+/**
+ * Send a batch of ARP requests built from frame templates. The frames are
+ * written in the datalink send buffer without any allocation, all templates
+ * of the batch must have the same frame size.
+ */
+pub fn send_arp_batch(tx: &mut Box<dyn DataLinkSender>, batch: &[(&FrameTemplate, Ipv4Addr)]) {
+
+    let frame_size = match batch.first() {
+        Some((template, _)) => template.len(),
+        None => return
     };
 
+    let mut batch_index = 0;
+    let send_result = tx.build_and_send(batch.len(), frame_size, &mut |buffer| {
+
+        let (template, target_ip) = batch[batch_index];
+        template.write_frame(target_ip, buffer);
+        batch_index += 1;
+    });
+
+    if let Some(Err(error)) = send_result {
+        eprintln!("[warn] Could not send ARP requests ({})", error);
+    }
+}
+
+/**
+ * Build the raw Ethernet frame of an ARP request. VLAN tags are written after
+ * the Ethernet addresses (outermost first), each tag announcing the protocol
+ * of the next header until the ARP payload is reached.
+ */
+pub fn build_arp_frame(source_mac: MacAddr, target_mac: MacAddr, source_ip: Ipv4Addr, target_ip: Ipv4Addr, vlan_tags: &[VlanTag], options: &ScanOptions) -> Vec<u8> {
+
+    let mut ethernet_buffer = vec![0u8; ETHERNET_STD_PACKET_SIZE + VLAN_TAG_SIZE * vlan_tags.len()];
+    let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap_or_else(|| {
+        eprintln!("Could not build Ethernet packet");
+        process::exit(1);
+    });
+
     ethernet_packet.set_destination(target_mac);
     ethernet_packet.set_source(source_mac);
 
-    let selected_ethertype = match options.vlan_id {
-        Some(_) => EtherTypes::Vlan,
+    let selected_ethertype = match vlan_tags.first() {
+        Some(outer_tag) => outer_tag.tag_protocol,
         None => EtherTypes::Arp
     };
     ethernet_packet.set_ethertype(selected_ethertype);
 
-    let mut arp_buffer = [0u8; ARP_PACKET_SIZE];
-    let mut arp_packet = MutableArpPacket::new(&mut arp_buffer).unwrap_or_else(|| {
+    for (tag_index, vlan_tag) in vlan_tags.iter().enumerate() {
+
+        let tag_offset = ETHERNET_HEADER_SIZE + tag_index * VLAN_TAG_SIZE;
+        let mut vlan_packet = MutableVlanPacket::new(&mut ethernet_buffer[tag_offset..]).unwrap_or_else(|| {
+            eprintln!("Could not build VLAN packet");
+            process::exit(1);
+        });
+        vlan_packet.set_vlan_identifier(vlan_tag.vlan_id);
+        vlan_packet.set_priority_code_point(ClassOfService::new(vlan_tag.priority));
+        vlan_packet.set_drop_eligible_indicator(vlan_tag.drop_eligible.into());
+
+        let next_ethertype = match vlan_tags.get(tag_index + 1) {
+            Some(next_tag) => next_tag.tag_protocol,
+            None => EtherTypes::Arp
+        };
+        vlan_packet.set_ethertype(next_ethertype);
+    }
+
+    let arp_offset = ETHERNET_HEADER_SIZE + vlan_tags.len() * VLAN_TAG_SIZE;
+    let mut arp_packet = MutableArpPacket::new(&mut ethernet_buffer[arp_offset..]).unwrap_or_else(|| {
         eprintln!("Could not build ARP packet");
         process::exit(1);
     });
@@ -215,95 +678,104 @@ pub fn send_arp_request(tx: &mut Box<dyn DataLinkSender>, interface: &NetworkInt
     arp_packet.set_target_hw_addr(target_mac);
     arp_packet.set_target_proto_addr(target_ip);
 
-    if let Some(vlan_id) = options.vlan_id {
-
-        let mut vlan_buffer = [0u8; VLAN_PACKET_SIZE];
-        let mut vlan_packet = MutableVlanPacket::new(&mut vlan_buffer).unwrap_or_else(|| {
-            eprintln!("Could not build VLAN packet");
-            process::exit(1);
-        });
-        vlan_packet.set_vlan_identifier(vlan_id);
-        vlan_packet.set_priority_code_point(ClassOfService::new(VLAN_QOS_DEFAULT));
-        vlan_packet.set_drop_eligible_indicator(0);
-        vlan_packet.set_ethertype(EtherTypes::Arp);
-
-        vlan_packet.set_payload(arp_packet.packet_mut());
-
-        ethernet_packet.set_payload(vlan_packet.packet_mut());
-    }
-    else {
-        ethernet_packet.set_payload(arp_packet.packet_mut());
-    }
-
-    tx.send_to(ethernet_packet.to_immutable().packet(), Some(interface.clone()));
+    ethernet_buffer
 }
 
 /**
  * A network iterator for iterating over multiple network ranges in with a
  * low-memory approach. This iterator was crafted to allow iteration over huge
  * network ranges (192.168.0.0/16) without consuming excessive memory.
+ *
+ * All target ranges are seen as a single target space, where each target has
+ * an index. A random iteration will permute the target indexes over the whole
+ * space (see 'TargetPermutation'), instead of shuffling small windows.
  */
 pub struct NetworkIterator {
-    current_iterator: Option<ipnetwork::IpNetworkIterator>,
-    networks: Vec<IpNetwork>,
-    is_random: bool,
-    random_pool: Vec<IpAddr>
+    ranges: Vec<TargetRange>,
+    range_offsets: Vec<u64>,
+    target_count: u64,
+    position: u64,
+    permutation: Option<TargetPermutation>
 }
 
 impl NetworkIterator {
 
     pub fn new(networks_ref: &[&IpNetwork], is_random: bool) -> NetworkIterator {
 
-        // The IpNetwork struct implements the Clone trait, which means that a simple
-        // dereference will clone the struct in the new vector
-        let mut networks: Vec<IpNetwork> = networks_ref.iter().map(|network| *(*network)).collect();
-
-        if is_random {
-            let mut rng = rand::thread_rng();
-            networks.shuffle(&mut rng);
-        }
-
-        NetworkIterator {
-            current_iterator: None,
-            networks,
-            is_random,
-            random_pool: vec![]
-        }
+        let random_seed = match is_random {
+            true => Some(rand::random()),
+            false => None
+        };
+        NetworkIterator::with_seed(networks_ref, random_seed)
     }
 
     /**
-     * The functions below are not public and only used by the Iterator trait
-     * to help keep the next() code clean.
+     * Create a network iterator over all addresses of the given networks with
+     * a reproducible order. IPv6 networks are ignored (not supported by ARP).
      */
+    pub fn with_seed(networks_ref: &[&IpNetwork], random_seed: Option<u64>) -> NetworkIterator {
 
-    fn has_no_items_left(&self) -> bool {
-        self.current_iterator.is_none() && self.networks.is_empty() && self.random_pool.is_empty()
-    }
+        let ranges: Vec<TargetRange> = networks_ref.iter().filter_map(|network| match network {
+            IpNetwork::V4(ipv4_network) => Some(TargetRange {
+                first: u32::from(ipv4_network.network()),
+                last: u32::from(ipv4_network.broadcast())
+            }),
+            IpNetwork::V6(_) => None
+        }).collect();
 
-    fn fill_random_pool(&mut self) {
+        NetworkIterator::from_ranges(ranges, random_seed)
+    }
 
-        for _ in 0..1000 {
+    /**
+     * Create a network iterator over target ranges: targets are given in
+     * ascending order without seed, or in a pseudorandom order determined
+     * by the seed.
+     */
+    pub fn from_ranges(ranges: Vec<TargetRange>, random_seed: Option<u64>) -> NetworkIterator {
+
+        // Each range starts at the index following the last target of the
+        // previous range, the target space has no gap.
+        let mut range_offsets: Vec<u64> = Vec::with_capacity(ranges.len());
+        let mut target_count: u64 = 0;
+        for range in ranges.iter() {
+            range_offsets.push(target_count);
+            target_count += range.size();
+        }
 
-            let next_ip = self.current_iterator.as_mut().unwrap().next();
-            if next_ip.is_none() {
-                break;
-            }
+        let permutation = random_seed.map(|seed| TargetPermutation::new(target_count, seed));
 
-            self.random_pool.push(next_ip.unwrap());
+        NetworkIterator {
+            ranges,
+            range_offsets,
+            target_count,
+            position: 0,
+            permutation
         }
-
-        let mut rng = rand::thread_rng();
-        self.random_pool.shuffle(&mut rng);
     }
 
-    fn select_new_iterator(&mut self) {
+    /**
+     * The number of targets already given by the iterator, this position can
+     * be saved to resume an interrupted scan with the same random seed.
+     */
+    pub fn position(&self) -> u64 {
+        self.position
+    }
 
-        self.current_iterator = Some(self.networks.remove(0).iter());
+    pub fn skip_to(&mut self, position: u64) {
+        self.position = position.min(self.target_count);
     }
 
-    fn pop_next_iterator_address(&mut self) -> Option<IpAddr> {
+    /**
+     * Find the IPv4 address of a target index, by locating the range with
+     * the last offset before the index.
+     */
+    fn find_target_address(&self, target_index: u64) -> Ipv4Addr {
+
+        let range_index = self.range_offsets.partition_point(|offset| *offset <= target_index) - 1;
+        let range = &self.ranges[range_index];
+        let address_offset = target_index - self.range_offsets[range_index];
 
-        self.current_iterator.as_mut().map(|iterator| iterator.next()).unwrap_or(None)
+        Ipv4Addr::from(range.first + address_offset as u32)
     }
 
 }
@@ -314,29 +786,17 @@ impl Iterator for NetworkIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
 
-        if self.has_no_items_left() {
+        if self.position >= self.target_count {
             return None;
         }
 
-        if self.current_iterator.is_none() && !self.networks.is_empty() {
-            self.select_new_iterator();
-        }
-
-        if self.is_random && self.random_pool.is_empty() {
-            self.fill_random_pool();
-        }
-
-        let next_ip = match self.is_random {
-            true => self.random_pool.pop(),
-            false => self.pop_next_iterator_address()
+        let target_index = match &self.permutation {
+            Some(permutation) => permutation.permute(self.position),
+            None => self.position
         };
+        self.position += 1;
 
-        if next_ip.is_none() && !self.networks.is_empty() {
-            self.select_new_iterator();
-            return self.pop_next_iterator_address();
-        }
-
-        next_ip
+        Some(IpAddr::V4(self.find_target_address(target_index)))
     }
 }
 
@@ -367,10 +827,17 @@ pub fn find_source_ip(network_interface: &NetworkInterface, forced_source_ipv4:
  * when the N seconds are elapsed, the receiver loop will therefore only stop
  * on the next received frame. Therefore, the receiver should have been
  * configured to stop at certain intervals (500ms for example).
+ *
+ * The probe phase is set by the sending thread during promiscuous checks, a
+ * response received in phase N is linked to the N-1 promiscuous probe (phase
+ * 0 being the standard ARP requests).
+ *
+ * Each ARP response is reported to the response monitor, which measures the
+ * round-trip times used by the adaptive timeout.
  */
-pub fn receive_arp_responses(rx: &mut Box<dyn DataLinkReceiver>, options: Arc<ScanOptions>, timed_out: Arc<AtomicBool>, vendor_list: &mut Vendor) -> (ResponseSummary, Vec<TargetDetails>) {
+pub fn receive_arp_responses(rx: &mut Box<dyn DataLinkReceiver>, options: Arc<ScanOptions>, lanes: &[ScanLane], timed_out: Arc<AtomicBool>, probe_phase: Arc<AtomicUsize>, monitor: Arc<ResponseMonitor>, vendor_list: &mut Vendor) -> (ResponseSummary, Vec<TargetDetails>) {
 
-    let mut discover_map: HashMap<Ipv4Addr, TargetDetails> = HashMap::new();
+    let mut discover_map: HashMap<(Option<u16>, Ipv4Addr), TargetDetails> = HashMap::new();
     let start_recording = Instant::now();
 
     let mut packet_count = 0;
@@ -404,35 +871,115 @@ pub fn receive_arp_responses(rx: &mut Box<dyn DataLinkReceiver>, options: Arc<Sc
             None => continue
         };
 
-        let is_arp_type = matches!(ethernet_packet.get_ethertype(), EtherTypes::Arp);
-        if !is_arp_type {
-            continue;
-        }
+        let (frame_vlan_id, arp_offset) = match find_arp_payload(&ethernet_packet) {
+            Some(arp_location) => arp_location,
+            None => continue
+        };
 
-        let arp_packet = ArpPacket::new(&arp_buffer[MutableEthernetPacket::minimum_packet_size()..]);
+        let arp_packet = ArpPacket::new(&arp_buffer[arp_offset..]);
         arp_count += 1;
 
         // If we found an ARP packet, extract the details and add the essential
         // fields in the discover map. Please note that results are grouped by
-        // IPv4 address - which means that a MAC change will appear as two
-        // separete records in the result table.
+        // VLAN & IPv4 address - a MAC change reuses the same record, the last
+        // MAC is displayed and all MACs are listed in the response MACs.
         if let Some(arp) = arp_packet {
 
             let sender_ipv4 = arp.get_sender_proto_addr();
             let sender_mac = arp.get_sender_hw_addr();
+            let ethernet_mac = ethernet_packet.get_source();
+
+            // Most network drivers strip the VLAN tag before the frame reaches
+            // the socket, the VLAN is then guessed from the lane networks.
+            let vlan_id = match options.has_vlan() {
+                true => frame_vlan_id.or_else(|| find_lane_vlan(lanes, sender_ipv4)),
+                false => None
+            };
     
-            discover_map.insert(sender_ipv4, TargetDetails {
+            let rtt = match arp.get_operation() {
+                ArpOperations::Reply => monitor.record_response(vlan_id, sender_ipv4, sender_mac),
+                _ => None
+            };
+
+            let target_detail = discover_map.entry((vlan_id, sender_ipv4)).or_insert_with(|| TargetDetails {
                 ipv4: sender_ipv4,
                 mac: sender_mac,
+                ethernet_mac,
+                vlan_id,
+                interface_name: None,
                 hostname: None,
-                vendor: None
+                vendor: None,
+                flags: vec![],
+                rtt: None,
+                discovered_at: Some(Instant::now()),
+                response_macs: vec![]
             });
+            target_detail.mac = sender_mac;
+            target_detail.ethernet_mac = ethernet_mac;
+
+            // All MAC addresses answering for a same IPv4 are kept in arrival
+            // order, several MAC addresses being a sign of an IPv4 conflict.
+            if !target_detail.response_macs.contains(&sender_mac) {
+                target_detail.response_macs.push(sender_mac);
+            }
+
+            // The fastest response is kept, later responses to retries may
+            // be linked to an older request.
+            target_detail.rtt = match (target_detail.rtt, rtt) {
+                (Some(known_rtt), Some(rtt)) => Some(known_rtt.min(rtt)),
+                (known_rtt, rtt) => known_rtt.or(rtt)
+            };
+
+            // A different Ethernet source and ARP sender address is a sign of
+            // ARP spoofing or a bridging device, the flag is kept even if the
+            // next responses are consistent.
+            let has_mismatch = sender_mac != ethernet_mac;
+            if has_mismatch && !target_detail.flags.contains(&TargetFlag::MacMismatch) {
+                target_detail.flags.push(TargetFlag::MacMismatch);
+            }
+
+            // Only replies sent to the scanner answer a promiscuous probe, other
+            // ARP frames of the host (its own requests, gratuitous ARP, ...)
+            // are received by any network card during the probe phase.
+            let probe_flag = match probe_phase.load(Ordering::Relaxed) {
+                0 => None,
+                _ if !is_reply_to_scanner(&arp, lanes) => None,
+                phase => Some(TargetFlag::PromiscuousProbe(phase - 1))
+            };
+            if let Some(probe_flag) = probe_flag.filter(|flag| !target_detail.flags.contains(flag)) {
+                target_detail.flags.push(probe_flag);
+            }
         }
     }
 
     // For each target found, enhance each item with additional results
     // results such as the hostname & MAC vendor.
-    let target_details = discover_map.into_values().map(|mut target_detail| {
+    let target_details = enrich_target_details(discover_map.into_values().collect(), &options, vendor_list);
+
+    // The response summary can be used to display analytics related to the
+    // performed ARP scans (packet counts, timings, ...)
+    let response_summary = ResponseSummary {
+        packet_count,
+        arp_count,
+        duration_ms: start_recording.elapsed().as_millis(),
+        request_count: 0,
+        requested_rate: None,
+        achieved_rate: None,
+        rtt_p99_us: None,
+        kernel_statistics: None,
+        subnet_discovery: None,
+        scan_metadata: None
+    };
+    (response_summary, target_details)
+}
+
+/**
+ * Enhance each target with additional details, such as the hostname (unless
+ * disabled) and the MAC vendor.
+ */
+pub fn enrich_target_details(target_details: Vec<TargetDetails>, options: &ScanOptions, vendor_list: &mut Vendor) -> Vec<TargetDetails> {
+
+    target_details.into_iter().map(|mut target_detail| {
 
         if options.resolve_hostname {
             target_detail.hostname = find_hostname(target_detail.ipv4);
@@ -444,16 +991,54 @@ pub fn receive_arp_responses(rx: &mut Box<dyn DataLinkReceiver>, options: Arc<Sc
 
         target_detail
 
-    }).collect();
+    }).collect()
+}
 
-    // The response summary can be used to display analytics related to the
-    // performed ARP scans (packet counts, timings, ...)
-    let response_summary = ResponseSummary {
-        packet_count,
-        arp_count,
-        duration_ms: start_recording.elapsed().as_millis()
-    };
-    (response_summary, target_details)
+/**
+ * Find the ARP payload in an Ethernet frame, skipping all VLAN tags (802.1Q
+ * or 802.1ad) before the payload. The innermost VLAN ID is returned with the
+ * ARP payload offset, or nothing if the frame does not contain ARP.
+ */
+fn find_arp_payload(ethernet_packet: &EthernetPacket) -> Option<(Option<u16>, usize)> {
+
+    let mut ethertype = ethernet_packet.get_ethertype();
+    let mut payload_offset = ETHERNET_HEADER_SIZE;
+    let mut vlan_id: Option<u16> = None;
+
+    while matches!(ethertype, EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ) {
+
+        let vlan_packet = VlanPacket::new(&ethernet_packet.packet()[payload_offset..])?;
+        vlan_id = Some(vlan_packet.get_vlan_identifier());
+        ethertype = vlan_packet.get_ethertype();
+        payload_offset += VLAN_TAG_SIZE;
+    }
+
+    match ethertype {
+        EtherTypes::Arp => Some((vlan_id, payload_offset)),
+        _ => None
+    }
+}
+
+/**
+ * Find the VLAN of an untagged ARP response, based on the networks targeted
+ * in each VLAN lane. The first lane containing the IPv4 will be selected.
+ */
+fn find_lane_vlan(lanes: &[ScanLane], ipv4: Ipv4Addr) -> Option<u16> {
+
+    lanes.iter()
+        .find(|lane| lane.ip_networks.iter().any(|network| network.contains(IpAddr::V4(ipv4))))
+        .and_then(|lane| lane.vlan_id)
+}
+
+/**
+ * Check that an ARP packet is a reply to the scanner, sent to the source MAC
+ * and IPv4 addresses of a scan lane.
+ */
+fn is_reply_to_scanner(arp_packet: &ArpPacket, lanes: &[ScanLane]) -> bool {
+
+    arp_packet.get_operation() == ArpOperations::Reply && lanes.iter().any(|lane| {
+        lane.source_ip == arp_packet.get_target_proto_addr() && lane.source_mac == arp_packet.get_target_hw_addr()
+    })
 }
 
 /**
@@ -485,6 +1070,32 @@ mod tests {
 
     use ipnetwork::Ipv4Network;
     use std::env;
+    use std::io;
+
+    use crate::args::build_args;
+
+    /**
+     * Replays a fixed list of frames, then times out the receiving loop.
+     */
+    struct ReplayReceiver {
+        frames: Vec<Vec<u8>>,
+        current_frame: Vec<u8>,
+        timed_out: Arc<AtomicBool>
+    }
+
+    impl DataLinkReceiver for ReplayReceiver {
+
+        fn next(&mut self) -> io::Result<&[u8]> {
+
+            if self.frames.is_empty() {
+                self.timed_out.store(true, Ordering::Relaxed);
+                return Err(io::Error::new(TimedOut, "no more frames"));
+            }
+
+            self.current_frame = self.frames.remove(0);
+            Ok(&self.current_frame)
+        }
+    }
 
     #[test]
     fn should_resolve_public_ip() {
@@ -492,12 +1103,11 @@ mod tests {
         // Sometimes, we do not have access to public networks in the test
         // environment and can pass the OFFLINE environment variable.
         if env::var("OFFLINE").is_ok() {
-            assert_eq!(true, true);
-        }
-        else {
-            let ipv4 = Ipv4Addr::new(1,1,1,1);
-            assert_eq!(find_hostname(ipv4), Some("one.one.one.one".to_string()));
+            return;
         }
+
+        let ipv4 = Ipv4Addr::new(1,1,1,1);
+        assert_eq!(find_hostname(ipv4), Some("one.one.one.one".to_string()));
     }
 
     #[test]
@@ -519,7 +1129,7 @@ mod tests {
     #[test]
     fn should_iterate_over_empty_networks() {
 
-        let mut iterator = NetworkIterator::new(&vec![], false);
+        let mut iterator = NetworkIterator::new(&[], false);
 
         assert_eq!(iterator.next(), None);
     }
@@ -594,9 +1204,288 @@ mod tests {
 
         let mut iterator = NetworkIterator::new(&target_network, true);
 
-        assert_eq!(iterator.next().is_some(), true);
-        assert_eq!(iterator.next().is_some(), true);
+        assert!(iterator.next().is_some());
+        assert!(iterator.next().is_some());
         assert_eq!(iterator.next(), None);
     }
 
+    #[test]
+    fn should_iterate_randomly_over_all_addresses() {
+
+        let network_a = IpNetwork::V4(
+            Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 0), 24).unwrap()
+        );
+        let network_b = IpNetwork::V4(
+            Ipv4Network::new(Ipv4Addr::new(10, 10, 20, 0), 30).unwrap()
+        );
+        let target_network: Vec<&IpNetwork> = vec![
+            &network_a,
+            &network_b
+        ];
+
+        let sequential_addresses: Vec<IpAddr> = NetworkIterator::with_seed(&target_network, None).collect();
+        let mut random_addresses: Vec<IpAddr> = NetworkIterator::with_seed(&target_network, Some(42)).collect();
+
+        assert_eq!(random_addresses.len(), 260);
+        assert_ne!(random_addresses, sequential_addresses);
+
+        random_addresses.sort();
+        let mut sorted_addresses = sequential_addresses.clone();
+        sorted_addresses.sort();
+        assert_eq!(random_addresses, sorted_addresses);
+    }
+
+    #[test]
+    fn should_reproduce_random_order_with_seed() {
+
+        let network_a = IpNetwork::V4(
+            Ipv4Network::new(Ipv4Addr::new(10, 1, 0, 0), 16).unwrap()
+        );
+        let target_network: Vec<&IpNetwork> = vec![
+            &network_a
+        ];
+
+        let first_order: Vec<IpAddr> = NetworkIterator::with_seed(&target_network, Some(99)).take(50).collect();
+        let second_order: Vec<IpAddr> = NetworkIterator::with_seed(&target_network, Some(99)).take(50).collect();
+
+        assert_eq!(first_order, second_order);
+    }
+
+    #[test]
+    fn should_skip_network_bounds() {
+
+        let networks = vec![
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 0), 24).unwrap()),
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 31).unwrap()),
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 1, 1), 32).unwrap())
+        ];
+
+        let target_ranges = compute_target_ranges(&networks, &[], true, &[]);
+
+        assert_eq!(target_ranges, vec![
+            TargetRange { first: u32::from(Ipv4Addr::new(192, 168, 1, 1)), last: u32::from(Ipv4Addr::new(192, 168, 1, 254)) },
+            TargetRange { first: u32::from(Ipv4Addr::new(10, 0, 0, 0)), last: u32::from(Ipv4Addr::new(10, 0, 0, 1)) },
+            TargetRange { first: u32::from(Ipv4Addr::new(10, 0, 1, 1)), last: u32::from(Ipv4Addr::new(10, 0, 1, 1)) }
+        ]);
+        assert_eq!(target_ranges.iter().map(|range| range.size()).sum::<u64>(), 257);
+    }
+
+    #[test]
+    fn should_keep_bounds_of_target_ranges() {
+
+        let options = ScanOptions::new(&build_args().get_matches_from(vec!["arp-scan", "-n", "10.0.0.10-10.0.0.50,192.168.1.0/24"]));
+        let networks = options.network_range.clone().unwrap();
+
+        let target_ranges = compute_target_ranges(&networks, &[], options.skip_network_bounds, &options.list_range_networks());
+        let targets: Vec<IpAddr> = NetworkIterator::from_ranges(target_ranges, None).collect();
+
+        let range_targets: Vec<IpAddr> = (10..=50).map(|last_byte| IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_byte))).collect();
+        assert_eq!(targets[..41], range_targets[..]);
+        assert_eq!(targets[41], IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(targets.len(), 41 + 254);
+    }
+
+    #[test]
+    fn should_remove_excluded_networks() {
+
+        let networks = vec![
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 0), 24).unwrap())
+        ];
+        let excluded_networks = vec![
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 128), 25).unwrap()),
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 10), 32).unwrap()),
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap())
+        ];
+
+        let target_ranges = compute_target_ranges(&networks, &excluded_networks, false, &[]);
+        let targets: Vec<IpAddr> = NetworkIterator::from_ranges(target_ranges, None).collect();
+
+        assert_eq!(targets.len(), 127);
+        assert_eq!(targets[9], IpAddr::V4(Ipv4Addr::new(192, 168, 1, 9)));
+        assert_eq!(targets[10], IpAddr::V4(Ipv4Addr::new(192, 168, 1, 11)));
+        assert_eq!(targets.last(), Some(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 127))));
+    }
+
+    #[test]
+    fn should_exclude_whole_network() {
+
+        let networks = vec![
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(255, 255, 255, 0), 24).unwrap())
+        ];
+        let excluded_networks = vec![
+            IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(255, 255, 0, 0), 16).unwrap())
+        ];
+
+        assert_eq!(compute_target_ranges(&networks, &excluded_networks, true, &[]), vec![]);
+    }
+
+    #[test]
+    fn should_build_untagged_frame() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan"]);
+        let options = ScanOptions::new(&matches);
+
+        let source_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+        let frame = build_arp_frame(source_mac, MacAddr::broadcast(), Ipv4Addr::new(192, 168, 1, 2), Ipv4Addr::new(192, 168, 1, 1), &[], &options);
+
+        assert_eq!(frame.len(), 42);
+        assert_eq!(&frame[12..14], &[0x08, 0x06]);
+        assert_eq!(&frame[38..42], &[192, 168, 1, 1]);
+    }
+
+    #[test]
+    fn should_build_double_tagged_frame() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-Q", "45:5", "--outer-vlan", "300:3:1"]);
+        let options = ScanOptions::new(&matches);
+
+        let mut vlan_tags = options.outer_vlan_tags.clone();
+        vlan_tags.push(options.vlan_tags[0]);
+
+        let source_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+        let frame = build_arp_frame(source_mac, MacAddr::broadcast(), Ipv4Addr::new(192, 168, 1, 2), Ipv4Addr::new(192, 168, 1, 1), &vlan_tags, &options);
+
+        assert_eq!(frame.len(), 50);
+        assert_eq!(&frame[12..14], &[0x88, 0xa8]);
+        assert_eq!(&frame[14..18], &[0x71, 0x2c, 0x81, 0x00]);
+        assert_eq!(&frame[18..22], &[0xa0, 0x2d, 0x08, 0x06]);
+        assert_eq!(&frame[46..50], &[192, 168, 1, 1]);
+    }
+
+    #[test]
+    fn should_find_tagged_arp_payload() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-Q", "45", "--outer-vlan", "300"]);
+        let options = ScanOptions::new(&matches);
+
+        let mut vlan_tags = options.outer_vlan_tags.clone();
+        vlan_tags.push(options.vlan_tags[0]);
+
+        let source_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+        let frame = build_arp_frame(source_mac, MacAddr::broadcast(), Ipv4Addr::new(192, 168, 1, 2), Ipv4Addr::new(192, 168, 1, 1), &vlan_tags, &options);
+        let ethernet_packet = EthernetPacket::new(&frame).unwrap();
+
+        assert_eq!(find_arp_payload(&ethernet_packet), Some((Some(45), 22)));
+    }
+
+    #[test]
+    fn should_find_lane_vlan() {
+
+        let lane_a = ScanLane {
+            vlan_id: Some(10),
+            vlan_tags: vec![],
+            ip_networks: vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 10, 0), 24).unwrap())],
+            target_ranges: vec![],
+            source_ip: Ipv4Addr::new(10, 0, 10, 250),
+            source_mac: MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01)
+        };
+        let lane_b = ScanLane {
+            vlan_id: Some(20),
+            vlan_tags: vec![],
+            ip_networks: vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 20, 0), 24).unwrap())],
+            target_ranges: vec![],
+            source_ip: Ipv4Addr::new(10, 0, 20, 250),
+            source_mac: MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01)
+        };
+        let lanes = vec![lane_a, lane_b];
+
+        assert_eq!(find_lane_vlan(&lanes, Ipv4Addr::new(10, 0, 20, 4)), Some(20));
+        assert_eq!(find_lane_vlan(&lanes, Ipv4Addr::new(10, 0, 30, 4)), None);
+    }
+
+    #[test]
+    fn should_patch_frame_template() {
+
+        let matches = build_args().get_matches_from(vec!["arp-scan", "-Q", "45"]);
+        let options = ScanOptions::new(&matches);
+
+        let interface = NetworkInterface {
+            name: "eth0".to_string(),
+            description: "".to_string(),
+            index: 1,
+            mac: Some(MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01)),
+            ips: vec![],
+            flags: 0
+        };
+        let lane = ScanLane {
+            vlan_id: Some(45),
+            vlan_tags: options.vlan_tags.clone(),
+            ip_networks: vec![],
+            target_ranges: vec![],
+            source_ip: Ipv4Addr::new(192, 168, 1, 2),
+            source_mac: interface.mac.unwrap()
+        };
+
+        let template = FrameTemplate::new(&interface, &lane, MacAddr::broadcast(), &options);
+        let mut buffer = vec![0u8; template.len()];
+        template.write_frame(Ipv4Addr::new(192, 168, 1, 77), &mut buffer);
+
+        let expected_frame = build_arp_frame(interface.mac.unwrap(), MacAddr::broadcast(), lane.source_ip, Ipv4Addr::new(192, 168, 1, 77), &lane.vlan_tags, &options);
+        assert_eq!(buffer, expected_frame);
+    }
+
+    #[test]
+    fn should_merge_response_summaries() {
+
+        let first_summary = ResponseSummary {
+            packet_count: 10,
+            arp_count: 4,
+            duration_ms: 2000,
+            rtt_p99_us: Some(900),
+            kernel_statistics: Some(KernelStatistics { packet_count: 4, drop_count: 1 }),
+            ..ResponseSummary::default()
+        };
+        let second_summary = ResponseSummary {
+            packet_count: 5,
+            arp_count: 5,
+            duration_ms: 2500,
+            rtt_p99_us: Some(300),
+            kernel_statistics: None,
+            ..ResponseSummary::default()
+        };
+
+        let merged_summary = ResponseSummary::merge([first_summary, second_summary].iter());
+
+        assert_eq!(merged_summary.packet_count, 15);
+        assert_eq!(merged_summary.arp_count, 9);
+        assert_eq!(merged_summary.duration_ms, 2500);
+        assert_eq!(merged_summary.rtt_p99_us, Some(900));
+        assert_eq!(merged_summary.kernel_statistics, Some(KernelStatistics { packet_count: 4, drop_count: 1 }));
+    }
+
+    #[test]
+    fn should_only_flag_promiscuous_replies_to_scanner() {
+
+        let options = ScanOptions::new(&build_args().get_matches_from(vec!["arp-scan", "--numeric"]));
+        let lane = ScanLane {
+            vlan_id: None,
+            vlan_tags: vec![],
+            ip_networks: vec![],
+            target_ranges: vec![],
+            source_ip: Ipv4Addr::new(192, 168, 1, 2),
+            source_mac: MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01)
+        };
+
+        // A host resolving its gateway during the probe phase, this request is
+        // not an answer to the probe and must not be flagged.
+        let host_mac = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x0a);
+        let host_request = build_arp_frame(host_mac, MacAddr::zero(), Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(192, 168, 1, 254), &[], &options);
+
+        let mut probe_reply = build_arp_frame(MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x0b), lane.source_mac, Ipv4Addr::new(192, 168, 1, 11), lane.source_ip, &[], &options);
+        MutableArpPacket::new(&mut probe_reply[14..]).unwrap().set_operation(ArpOperations::Reply);
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let mut rx: Box<dyn DataLinkReceiver> = Box::new(ReplayReceiver {
+            frames: vec![host_request, probe_reply],
+            current_frame: vec![],
+            timed_out: Arc::clone(&timed_out)
+        });
+
+        let (_, target_details) = receive_arp_responses(&mut rx, options, &[lane], timed_out, Arc::new(AtomicUsize::new(1)), Arc::new(ResponseMonitor::new(None)), &mut Vendor::new());
+
+        let find_flags = |ipv4: Ipv4Addr| target_details.iter().find(|detail| detail.ipv4 == ipv4).map(|detail| detail.flags.clone());
+        assert_eq!(find_flags(Ipv4Addr::new(192, 168, 1, 10)), Some(vec![]));
+        assert_eq!(find_flags(Ipv4Addr::new(192, 168, 1, 11)), Some(vec![TargetFlag::PromiscuousProbe(0)]));
+    }
+
 }
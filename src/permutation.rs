@@ -0,0 +1,145 @@
+// The number of Feistel rounds, 4 rounds are enough to hide the sequential
+// nature of the target indexes (this is not meant to be cryptographically
+// secure, only to spread requests over the whole target space).
+const FEISTEL_ROUNDS: usize = 4;
+
+/**
+ * A pseudorandom permutation over all target indexes (from 0 to N-1), built
+ * with a balanced Feistel network and cycle walking. Each index is permuted
+ * on-the-fly, which allows randomizing huge target spaces without memory.
+ * The same seed always gives the same permutation.
+ */
+pub struct TargetPermutation {
+    target_count: u64,
+    half_bits: u32,
+    round_keys: [u64; FEISTEL_ROUNDS]
+}
+
+impl TargetPermutation {
+
+    pub fn new(target_count: u64, seed: u64) -> TargetPermutation {
+
+        // The Feistel network works on an even number of bits, covering at
+        // least all target indexes (the domain is less than 4 times bigger).
+        let required_bits = u64::BITS - target_count.saturating_sub(1).leading_zeros();
+        let half_bits = ((required_bits + 1) / 2).max(1);
+
+        let mut round_keys = [0u64; FEISTEL_ROUNDS];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            *round_key = mix_bits(seed.wrapping_add(round as u64));
+        }
+
+        TargetPermutation {
+            target_count,
+            half_bits,
+            round_keys
+        }
+    }
+
+    /**
+     * Permute a target index. Results outside of the target space are encrypted
+     * again (cycle walking) until a valid index is found, which is guaranteed
+     * since the Feistel network is a permutation of its whole domain.
+     */
+    pub fn permute(&self, index: u64) -> u64 {
+
+        let mut permuted_index = self.encrypt(index);
+        while permuted_index >= self.target_count {
+            permuted_index = self.encrypt(permuted_index);
+        }
+        permuted_index
+    }
+
+    fn encrypt(&self, value: u64) -> u64 {
+
+        let half_mask = (1u64 << self.half_bits) - 1;
+        let mut left = (value >> self.half_bits) & half_mask;
+        let mut right = value & half_mask;
+
+        for round_key in self.round_keys.iter() {
+
+            let next_right = left ^ (mix_bits(right ^ round_key) & half_mask);
+            left = right;
+            right = next_right;
+        }
+
+        (left << self.half_bits) | right
+    }
+}
+
+/**
+ * Derive a new seed from a base seed, giving each retry round its own target
+ * order while keeping the whole scan reproducible.
+ */
+pub fn derive_seed(seed: u64, round: u64) -> u64 {
+
+    mix_bits(seed ^ mix_bits(round))
+}
+
+/**
+ * Mix all bits of a 64 bits value (SplitMix64 finalizer), each input bit
+ * having an influence on all output bits.
+ */
+fn mix_bits(value: u64) -> u64 {
+
+    let mut mixed = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    mixed ^ (mixed >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_permute_all_indexes() {
+
+        for target_count in [1, 2, 3, 7, 256, 1000, 4099] {
+
+            let permutation = TargetPermutation::new(target_count, 42);
+            let mut permuted_indexes: Vec<u64> = (0..target_count).map(|index| permutation.permute(index)).collect();
+            permuted_indexes.sort_unstable();
+
+            let expected_indexes: Vec<u64> = (0..target_count).collect();
+            assert_eq!(permuted_indexes, expected_indexes);
+        }
+    }
+
+    #[test]
+    fn should_reproduce_permutation_with_seed() {
+
+        let first_permutation = TargetPermutation::new(65536, 1234);
+        let second_permutation = TargetPermutation::new(65536, 1234);
+        let other_permutation = TargetPermutation::new(65536, 4321);
+
+        let first_order: Vec<u64> = (0..100).map(|index| first_permutation.permute(index)).collect();
+        let second_order: Vec<u64> = (0..100).map(|index| second_permutation.permute(index)).collect();
+        let other_order: Vec<u64> = (0..100).map(|index| other_permutation.permute(index)).collect();
+
+        assert_eq!(first_order, second_order);
+        assert_ne!(first_order, other_order);
+    }
+
+    #[test]
+    fn should_spread_over_whole_range() {
+
+        // The first 256 targets of a /16 should not stay in a small window,
+        // each quarter of the range should be visited early.
+        let permutation = TargetPermutation::new(65536, 7);
+        let mut visited_quarters = [false; 4];
+        for index in 0..256 {
+            visited_quarters[(permutation.permute(index) / 16384) as usize] = true;
+        }
+
+        assert!(visited_quarters.iter().all(|is_visited| *is_visited));
+    }
+
+    #[test]
+    fn should_derive_different_seeds() {
+
+        assert_eq!(derive_seed(10, 1), derive_seed(10, 1));
+        assert_ne!(derive_seed(10, 1), derive_seed(10, 2));
+    }
+}
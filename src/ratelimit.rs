@@ -0,0 +1,217 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Below this duration, the rate limiter will busy-wait instead of sleeping
+// since the OS scheduler does not guarantee a microsecond sleep precision.
+const SPIN_THRESHOLD_US: u64 = 200;
+
+/**
+ * A token bucket rate limiter with microsecond precision. Tokens are added at
+ * the requested packet rate and the bucket holds at most 'burst' tokens, each
+ * sent packet consumes a single token. A limiter without rate never blocks.
+ */
+pub struct RateLimiter {
+    token_interval: Option<Duration>,
+    burst_size: u32,
+    available_tokens: u32,
+    last_refill: Instant,
+    first_packet: Option<Instant>,
+    last_packet: Option<Instant>,
+    packet_count: u64
+}
+
+impl RateLimiter {
+
+    pub fn new(packet_rate: Option<f64>, burst_size: u32) -> RateLimiter {
+
+        let token_interval = packet_rate
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(1.0 / rate))
+            .filter(|interval| !interval.is_zero());
+        let burst_size = burst_size.max(1);
+
+        RateLimiter {
+            token_interval,
+            burst_size,
+            available_tokens: burst_size,
+            last_refill: Instant::now(),
+            first_packet: None,
+            last_packet: None,
+            packet_count: 0
+        }
+    }
+
+    /**
+     * Block the current thread until a token is available and consume it.
+     * This should be called right before sending each packet.
+     */
+    pub fn acquire(&mut self) {
+
+        if let Some(token_interval) = self.token_interval {
+
+            self.refill(token_interval);
+            if self.available_tokens == 0 {
+
+                let next_token = self.last_refill + token_interval;
+                wait_until(next_token);
+                self.refill(token_interval);
+            }
+            self.available_tokens = self.available_tokens.saturating_sub(1);
+        }
+
+        let now = Instant::now();
+        self.first_packet.get_or_insert(now);
+        self.last_packet = Some(now);
+        self.packet_count += 1;
+    }
+
+    /**
+     * Check if a packet can be sent without waiting, no token is consumed.
+     * A limiter without rate always has a token available.
+     */
+    pub fn has_token(&mut self) -> bool {
+
+        match self.token_interval {
+            Some(token_interval) => {
+                self.refill(token_interval);
+                self.available_tokens > 0
+            },
+            None => true
+        }
+    }
+
+    /**
+     * Add all tokens generated since the last refill. The refill instant only
+     * moves by whole token intervals to avoid accumulating rounding drifts.
+     */
+    fn refill(&mut self, token_interval: Duration) {
+
+        let elapsed = self.last_refill.elapsed();
+        let new_tokens = elapsed.as_nanos() / token_interval.as_nanos();
+        if new_tokens == 0 {
+            return;
+        }
+
+        // A full bucket does not store more tokens, the next refill will
+        // then start from now to avoid an unexpected burst.
+        let missing_tokens = self.burst_size - self.available_tokens;
+        if new_tokens >= missing_tokens.into() {
+            self.available_tokens = self.burst_size;
+            self.last_refill = Instant::now();
+        }
+        else {
+            let new_tokens = new_tokens as u32;
+            self.available_tokens += new_tokens;
+            self.last_refill += token_interval * new_tokens;
+        }
+    }
+
+    pub fn packet_count(&self) -> u64 {
+        self.packet_count
+    }
+
+    /**
+     * Computes the achieved packet rate (packets per second) between the first
+     * and the last sent packet, a single packet has no measurable rate.
+     */
+    pub fn achieved_rate(&self) -> Option<f64> {
+
+        match (self.first_packet, self.last_packet) {
+            (Some(first_packet), Some(last_packet)) if self.packet_count > 1 => {
+                let elapsed = last_packet.duration_since(first_packet).as_secs_f64();
+                match elapsed > 0.0 {
+                    true => Some((self.packet_count - 1) as f64 / elapsed),
+                    false => None
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+/**
+ * Wait until the given instant, sleeping for most of the duration and then
+ * spinning for the last microseconds.
+ */
+fn wait_until(deadline: Instant) {
+
+    let now = Instant::now();
+    if deadline <= now {
+        return;
+    }
+
+    let remaining = deadline - now;
+    let spin_threshold = Duration::from_micros(SPIN_THRESHOLD_US);
+    if remaining > spin_threshold {
+        thread::sleep(remaining - spin_threshold);
+    }
+
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_not_block_without_rate() {
+
+        let mut rate_limiter = RateLimiter::new(None, 1);
+        let start = Instant::now();
+
+        for _ in 0..1000 {
+            rate_limiter.acquire();
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(rate_limiter.packet_count(), 1000);
+    }
+
+    #[test]
+    fn should_pace_packets() {
+
+        let mut rate_limiter = RateLimiter::new(Some(2000.0), 1);
+        let start = Instant::now();
+
+        for _ in 0..101 {
+            rate_limiter.acquire();
+        }
+
+        // 100 intervals of 500us are expected after the first packet
+        assert!(start.elapsed() >= Duration::from_millis(49));
+
+        let achieved_rate = rate_limiter.achieved_rate().unwrap();
+        assert!(achieved_rate <= 2100.0);
+    }
+
+    #[test]
+    fn should_allow_bursts() {
+
+        let mut rate_limiter = RateLimiter::new(Some(10.0), 5);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            rate_limiter.acquire();
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn should_check_tokens_without_consuming() {
+
+        let mut rate_limiter = RateLimiter::new(Some(10.0), 2);
+
+        assert!(rate_limiter.has_token());
+        assert!(rate_limiter.has_token());
+        rate_limiter.acquire();
+        rate_limiter.acquire();
+        assert!(!rate_limiter.has_token());
+
+        assert!(RateLimiter::new(None, 1).has_token());
+    }
+
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::metadata::ScanMetadata;
+use crate::network::{ResponseSummary, TargetDetails};
+use crate::time;
+use crate::utils;
+
+// The report is a single file without external assets (no CDN, no fonts),
+// so that it can be sent by mail or opened on an offline machine.
+const REPORT_STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1 { font-size: 1.6em; } h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; width: 100%; margin-top: 0.5em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #eee; }
+th { background: #f4f4f4; }
+#hosts th { cursor: pointer; user-select: none; }
+#hosts th.asc::after { content: ' \\25B2'; } #hosts th.desc::after { content: ' \\25BC'; }
+.facts th { width: 14em; }
+.bar { background: #4a90d9; height: 0.8em; }
+.mono { font-family: monospace; }
+#filter { padding: 0.4em; width: 20em; }
+";
+
+const REPORT_SCRIPT: &str = "
+var table = document.getElementById('hosts');
+var rows = Array.prototype.slice.call(table.tBodies[0].rows);
+document.getElementById('filter').addEventListener('input', function (event) {
+  var query = event.target.value.toLowerCase();
+  var shown = 0;
+  rows.forEach(function (row) {
+    var isShown = row.textContent.toLowerCase().indexOf(query) !== -1;
+    row.style.display = isShown ? '' : 'none';
+    if (isShown) { shown++; }
+  });
+  document.getElementById('shown').textContent = shown;
+});
+Array.prototype.forEach.call(table.tHead.rows[0].cells, function (header, index) {
+  header.addEventListener('click', function () {
+    var isAscending = !header.classList.contains('asc');
+    Array.prototype.forEach.call(table.tHead.rows[0].cells, function (cell) { cell.className = ''; });
+    header.className = isAscending ? 'asc' : 'desc';
+    rows.sort(function (first, second) {
+      var firstKey = first.cells[index].getAttribute('data-sort');
+      var secondKey = second.cells[index].getAttribute('data-sort');
+      var order = firstKey < secondKey ? -1 : (firstKey > secondKey ? 1 : 0);
+      return isAscending ? order : -order;
+    });
+    rows.forEach(function (row) { table.tBodies[0].appendChild(row); });
+  });
+});
+";
+
+/**
+ * Export the scan results as a self-contained HTML report, meant for readers
+ * without a terminal: scan metadata, statistics, vendor distribution and a
+ * host table that can be sorted (by clicking a column) and filtered.
+ */
+pub fn export_to_html(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>) -> String {
+
+    utils::sort_target_details(&mut target_details);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>ARP scan report</title>\n");
+    html.push_str(&format!("<style>{}</style>\n", REPORT_STYLE));
+    html.push_str("</head>\n<body>\n<h1>ARP scan report</h1>\n");
+
+    if let Some(scan_metadata) = &response_summary.scan_metadata {
+        html.push_str(&format_metadata_section(scan_metadata));
+    }
+    html.push_str(&format_statistics_section(&response_summary, target_details.len()));
+    html.push_str(&format_vendor_section(&target_details));
+    html.push_str(&format_host_section(&target_details));
+
+    html.push_str(&format!("<script>{}</script>\n", REPORT_SCRIPT));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn format_metadata_section(scan_metadata: &ScanMetadata) -> String {
+
+    let networks: Vec<String> = scan_metadata.interfaces.iter().flat_map(|interface| {
+        interface.lanes.iter().map(move |lane| {
+            let vlan_text = lane.vlan.map(|vlan| format!(", VLAN {}", vlan)).unwrap_or_default();
+            format!("{} ({}{}): {}", interface.name, interface.mac, vlan_text, lane.networks.join(", "))
+        })
+    }).collect();
+
+    let mut facts: Vec<(&str, String)> = vec![
+        ("Started at", scan_metadata.started_at.clone()),
+        ("Finished at", scan_metadata.finished_at.clone()),
+        ("Scanning host", scan_metadata.hostname.clone().unwrap_or_default()),
+        ("Scan profile", scan_metadata.profile.clone()),
+        ("Networks", networks.join("\n")),
+        ("Timeout", format!("{}ms", scan_metadata.timing.timeout_ms)),
+        ("Retry count", scan_metadata.timing.retry_count.to_string())
+    ];
+    if let Some(random_seed) = scan_metadata.timing.random_seed {
+        facts.push(("Random seed", random_seed.to_string()));
+    }
+    facts.push(("Tool version", format!("arp-scan {}", scan_metadata.version)));
+
+    format!("<h2>Scan</h2>\n{}", format_fact_table(&facts))
+}
+
+fn format_statistics_section(response_summary: &ResponseSummary, host_count: usize) -> String {
+
+    let mut facts: Vec<(&str, String)> = vec![
+        ("Hosts found", host_count.to_string()),
+        ("Scan duration", time::format_milliseconds(response_summary.duration_ms)),
+        ("ARP requests sent", response_summary.request_count.to_string()),
+        ("Packets received", response_summary.packet_count.to_string()),
+        ("ARP packets received", response_summary.arp_count.to_string())
+    ];
+    if let Some(achieved_rate) = response_summary.achieved_rate {
+        let requested_text = response_summary.requested_rate.map(|rate| format!(" ({:.0} packets/s requested)", rate)).unwrap_or_default();
+        facts.push(("Request rate", format!("{:.1} packets/s{}", achieved_rate, requested_text)));
+    }
+    if let Some(rtt_p99_us) = response_summary.rtt_p99_us {
+        facts.push(("Response time (p99)", format!("{:.3}ms", rtt_p99_us as f64 / 1000.0)));
+    }
+    if let Some(kernel_statistics) = &response_summary.kernel_statistics {
+        facts.push(("Kernel packets", format!("{} received, {} dropped", kernel_statistics.packet_count, kernel_statistics.drop_count)));
+    }
+    if let Some(subnet_discovery) = &response_summary.subnet_discovery {
+        facts.push(("Occupied subnets", format!("{} (of {} sampled)", subnet_discovery.subnets.len(), subnet_discovery.sampled_count)));
+    }
+
+    format!("<h2>Statistics</h2>\n{}", format_fact_table(&facts))
+}
+
+/**
+ * List the vendors by number of hosts (most frequent first), each with a bar
+ * relative to the total number of hosts.
+ */
+fn format_vendor_section(target_details: &[TargetDetails]) -> String {
+
+    let vendor_counts = count_vendors(target_details);
+
+    let mut section = String::from("<h2>Vendors</h2>\n<table>\n<thead><tr><th>Vendor</th><th>Hosts</th><th>Share</th></tr></thead>\n<tbody>\n");
+    for (vendor, count) in vendor_counts.iter() {
+        let share = (*count as f64) * 100.0 / (target_details.len() as f64);
+        section.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>\n",
+            escape_html(vendor), count, share
+        ));
+    }
+    section.push_str("</tbody>\n</table>\n");
+    section
+}
+
+fn count_vendors(target_details: &[TargetDetails]) -> Vec<(String, usize)> {
+
+    let mut vendor_counts: HashMap<String, usize> = HashMap::new();
+    for detail in target_details.iter() {
+        let vendor = detail.vendor.clone().unwrap_or_else(|| "Unknown".to_string());
+        *vendor_counts.entry(vendor).or_insert(0) += 1;
+    }
+
+    let mut vendor_counts: Vec<(String, usize)> = vendor_counts.into_iter().collect();
+    vendor_counts.sort_by(|(first_vendor, first_count), (second_vendor, second_count)| {
+        second_count.cmp(first_count).then_with(|| first_vendor.cmp(second_vendor))
+    });
+    vendor_counts
+}
+
+fn format_host_section(target_details: &[TargetDetails]) -> String {
+
+    let mut section = String::from("<h2>Hosts</h2>\n");
+    section.push_str(&format!(
+        "<p><input id=\"filter\" type=\"search\" placeholder=\"Filter hosts\"> <span id=\"shown\">{}</span> of {} hosts shown</p>\n",
+        target_details.len(), target_details.len()
+    ));
+    section.push_str("<table id=\"hosts\">\n<thead><tr><th>IPv4</th><th>MAC</th><th>Vendor</th><th>Hostname</th></tr></thead>\n<tbody>\n");
+
+    for detail in target_details.iter() {
+
+        // IPv4 addresses are sorted by their numeric value, zero-padded so
+        // that the report script can compare them as texts.
+        let ip_key = format!("{:010}", u32::from(detail.ipv4));
+        let mac_text = detail.mac.to_string();
+        let vendor_text = detail.vendor.as_deref().unwrap_or("");
+        let hostname_text = detail.hostname.as_deref().unwrap_or("");
+
+        section.push_str(&format!(
+            "<tr><td class=\"mono\" data-sort=\"{}\">{}</td><td class=\"mono\" data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td></tr>\n",
+            ip_key, detail.ipv4,
+            mac_text, mac_text,
+            escape_html(&vendor_text.to_lowercase()), escape_html(vendor_text),
+            escape_html(&hostname_text.to_lowercase()), escape_html(hostname_text)
+        ));
+    }
+    section.push_str("</tbody>\n</table>\n");
+    section
+}
+
+fn format_fact_table(facts: &[(&str, String)]) -> String {
+
+    let mut table = String::from("<table class=\"facts\">\n");
+    for (label, value) in facts.iter() {
+        table.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", label, escape_html(value).replace('\n', "<br>")));
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+/**
+ * Escape a text for HTML attributes and contents, vendor names and hostnames
+ * are never trusted since they come from the network or external files.
+ */
+fn escape_html(text: &str) -> String {
+
+    text.chars().map(|character| match character {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&#39;".to_string(),
+        _ => character.to_string()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::Ipv4Addr;
+    use pnet_datalink::MacAddr;
+
+    fn build_target(last_byte: u8, vendor: Option<&str>, hostname: Option<&str>) -> TargetDetails {
+
+        TargetDetails {
+            hostname: hostname.map(|hostname| hostname.to_string()),
+            vendor: vendor.map(|vendor| vendor.to_string()),
+            ..TargetDetails::for_test(Ipv4Addr::new(192, 168, 1, last_byte), MacAddr::new(0x00, 0x16, 0xf6, 0x00, 0x00, last_byte))
+        }
+    }
+
+    #[test]
+    fn should_count_vendors() {
+
+        let target_details = vec![
+            build_target(1, Some("Cisco"), None),
+            build_target(2, None, None),
+            build_target(3, Some("Apple"), None),
+            build_target(4, Some("Cisco"), None)
+        ];
+
+        assert_eq!(count_vendors(&target_details), vec![
+            ("Cisco".to_string(), 2),
+            ("Apple".to_string(), 1),
+            ("Unknown".to_string(), 1)
+        ]);
+    }
+
+    #[test]
+    fn should_export_html_report() {
+
+        let response_summary = ResponseSummary {
+            packet_count: 12,
+            duration_ms: 1500,
+            ..ResponseSummary::default()
+        };
+        let target_details = vec![
+            build_target(20, Some("Vendor & Co"), Some("<script>")),
+            build_target(3, None, None)
+        ];
+
+        let html = export_to_html(response_summary, target_details);
+
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains("<tr><th>Packets received</th><td>12</td></tr>"));
+        assert!(html.contains("<td data-sort=\"vendor &amp; co\">Vendor &amp; Co</td><td data-sort=\"&lt;script&gt;\">&lt;script&gt;</td>"));
+        assert!(html.find("data-sort=\"3232235779\"") < html.find("data-sort=\"3232235796\""));
+        assert!(!html.contains("http://") && !html.contains("https://"));
+        assert!(html.ends_with("</html>\n"));
+    }
+}
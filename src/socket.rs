@@ -0,0 +1,433 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pnet_datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+
+use crate::network::KernelStatistics;
+
+// Not all socket options of 'linux/if_packet.h' are exposed by the libc crate
+const SOL_PACKET: libc::c_int = 263;
+const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
+const PACKET_STATISTICS: libc::c_int = 6;
+const PACKET_MR_PROMISC: libc::c_ushort = 1;
+const SIOCGSTAMPNS: libc::c_ulong = 0x8907;
+
+const ETHERTYPE_OFFSET: u32 = 12;
+const ETHERTYPE_ARP: u32 = 0x0806;
+const VLAN_TAG_SIZE: u32 = 4;
+const VLAN_TAG_PROTOCOLS: [u32; 3] = [0x8100, 0x88a8, 0x9100];
+
+// The kernel filter accepts ARP frames carried in up to 2 VLAN tags (QinQ),
+// most drivers strip the outermost tag before the filter is applied anyway.
+const FILTER_VLAN_DEPTH: u32 = 2;
+const FILTER_ACCEPT_LENGTH: u32 = 0x40000;
+const FILTER_REJECT_LENGTH: u32 = 0;
+
+const RECEIVE_BUFFER_SIZE: usize = 65536;
+
+#[repr(C)]
+struct PacketMembership {
+    mr_ifindex: libc::c_int,
+    mr_type: libc::c_ushort,
+    mr_alen: libc::c_ushort,
+    mr_address: [libc::c_uchar; 8]
+}
+
+#[repr(C)]
+struct PacketStatistics {
+    tp_packets: libc::c_uint,
+    tp_drops: libc::c_uint
+}
+
+/**
+ * An AF_PACKET socket file descriptor shared by the sender, the receiver and
+ * the statistics reader. The socket is closed once all of them are dropped.
+ */
+struct SocketFd(RawFd);
+
+impl Drop for SocketFd {
+
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+pub struct FilteredSender {
+    socket: Arc<SocketFd>,
+    write_buffer: Vec<u8>
+}
+
+pub struct FilteredReceiver {
+    socket: Arc<SocketFd>,
+    read_buffer: Vec<u8>
+}
+
+pub struct StatisticsReader {
+    socket: Arc<SocketFd>
+}
+
+pub struct TimestampReader {
+    socket: Arc<SocketFd>
+}
+
+pub type FilteredChannel = (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>, StatisticsReader);
+
+/**
+ * Open a datalink channel on an AF_PACKET socket with a classic BPF program
+ * attached, only ARP frames (optionally VLAN-tagged) will then be copied by
+ * the kernel to the receiver. The socket is created without any protocol and
+ * only bound once the filter is attached, so no other frame can be queued.
+ * Like the default datalink channel, the interface is switched to promiscuous
+ * mode so that replies sent to a forced source MAC are received.
+ */
+pub fn open_filtered_channel(interface: &NetworkInterface, accept_vlan: bool, read_timeout: Duration, write_buffer_size: usize) -> io::Result<FilteredChannel> {
+
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = Arc::new(SocketFd(fd));
+
+    let mut filter = compute_arp_filter(accept_vlan);
+    let filter_program = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr()
+    };
+    set_socket_option(fd, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &filter_program)?;
+
+    let receive_timeout = libc::timeval {
+        tv_sec: read_timeout.as_secs() as libc::time_t,
+        tv_usec: read_timeout.subsec_micros() as libc::suseconds_t
+    };
+    set_socket_option(fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &receive_timeout)?;
+
+    let mut socket_address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    socket_address.sll_family = libc::AF_PACKET as libc::c_ushort;
+    socket_address.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    socket_address.sll_ifindex = interface.index as libc::c_int;
+
+    let bind_result = unsafe {
+        libc::bind(fd, &socket_address as *const libc::sockaddr_ll as *const libc::sockaddr, mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t)
+    };
+    if bind_result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let membership = PacketMembership {
+        mr_ifindex: interface.index as libc::c_int,
+        mr_type: PACKET_MR_PROMISC,
+        mr_alen: 0,
+        mr_address: [0; 8]
+    };
+    set_socket_option(fd, SOL_PACKET, PACKET_ADD_MEMBERSHIP, &membership)?;
+
+    let sender = FilteredSender {
+        socket: Arc::clone(&socket),
+        write_buffer: vec![0u8; write_buffer_size]
+    };
+    let receiver = FilteredReceiver {
+        socket: Arc::clone(&socket),
+        read_buffer: vec![0u8; RECEIVE_BUFFER_SIZE]
+    };
+    Ok((Box::new(sender), Box::new(receiver), StatisticsReader { socket }))
+}
+
+fn set_socket_option<T>(fd: RawFd, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+
+    let option_result = unsafe {
+        libc::setsockopt(fd, level, name, value as *const T as *const libc::c_void, mem::size_of::<T>() as libc::socklen_t)
+    };
+    match option_result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error())
+    }
+}
+
+/**
+ * Build a classic BPF program accepting ARP frames. With VLAN support, each
+ * VLAN tag protocol found at the EtherType offset moves the check 4 bytes
+ * further, up to the maximum VLAN depth. All other frames are rejected.
+ */
+pub fn compute_arp_filter(accept_vlan: bool) -> Vec<libc::sock_filter> {
+
+    let vlan_depth = match accept_vlan {
+        true => FILTER_VLAN_DEPTH,
+        false => 0
+    };
+
+    // Each level loads the EtherType and checks for ARP, intermediate levels
+    // also check all VLAN tag protocols. The reject and accept instructions
+    // are placed at the end of the program.
+    let level_size = 2 + VLAN_TAG_PROTOCOLS.len();
+    let program_size = level_size * vlan_depth as usize + 2 + 2;
+    let reject_index = program_size - 2;
+    let accept_index = program_size - 1;
+
+    let mut filter: Vec<libc::sock_filter> = Vec::with_capacity(program_size);
+    for level in 0..=vlan_depth {
+
+        filter.push(bpf_statement(libc::BPF_LD | libc::BPF_H | libc::BPF_ABS, ETHERTYPE_OFFSET + VLAN_TAG_SIZE * level));
+
+        let is_last_level = level == vlan_depth;
+        let arp_fail_index = match is_last_level {
+            true => reject_index,
+            false => filter.len() + 1
+        };
+        let arp_index = filter.len();
+        filter.push(bpf_jump(ETHERTYPE_ARP, arp_index, accept_index, arp_fail_index));
+
+        if !is_last_level {
+
+            let next_level_index = filter.len() + VLAN_TAG_PROTOCOLS.len();
+            for (protocol_position, tag_protocol) in VLAN_TAG_PROTOCOLS.iter().enumerate() {
+
+                let protocol_index = filter.len();
+                let fail_index = match protocol_position == VLAN_TAG_PROTOCOLS.len() - 1 {
+                    true => reject_index,
+                    false => protocol_index + 1
+                };
+                filter.push(bpf_jump(*tag_protocol, protocol_index, next_level_index, fail_index));
+            }
+        }
+    }
+
+    filter.push(bpf_statement(libc::BPF_RET | libc::BPF_K, FILTER_REJECT_LENGTH));
+    filter.push(bpf_statement(libc::BPF_RET | libc::BPF_K, FILTER_ACCEPT_LENGTH));
+    filter
+}
+
+fn bpf_statement(code: u32, k: u32) -> libc::sock_filter {
+
+    libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+/**
+ * Build a BPF equality jump, the true and false targets are absolute indexes
+ * converted to offsets relative to the next instruction.
+ */
+fn bpf_jump(k: u32, index: usize, true_index: usize, false_index: usize) -> libc::sock_filter {
+
+    libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt: (true_index - index - 1) as u8,
+        jf: (false_index - index - 1) as u8,
+        k
+    }
+}
+
+impl DataLinkSender for FilteredSender {
+
+    fn build_and_send(&mut self, num_packets: usize, packet_size: usize, func: &mut dyn FnMut(&mut [u8])) -> Option<io::Result<()>> {
+
+        if packet_size == 0 || num_packets * packet_size > self.write_buffer.len() {
+            return None;
+        }
+
+        for _ in 0..num_packets {
+
+            let packet = &mut self.write_buffer[..packet_size];
+            func(packet);
+            if let Err(error) = send_packet(self.socket.0, packet) {
+                return Some(Err(error));
+            }
+        }
+        Some(Ok(()))
+    }
+
+    fn send_to(&mut self, packet: &[u8], _dst: Option<NetworkInterface>) -> Option<io::Result<()>> {
+
+        Some(send_packet(self.socket.0, packet))
+    }
+}
+
+fn send_packet(fd: RawFd, packet: &[u8]) -> io::Result<()> {
+
+    let send_result = unsafe { libc::send(fd, packet.as_ptr() as *const libc::c_void, packet.len(), 0) };
+    match send_result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+impl DataLinkReceiver for FilteredReceiver {
+
+    /**
+     * Receive the next frame accepted by the kernel filter. A receive timeout
+     * (or an interrupted call) is reported as a 'TimedOut' error, just like
+     * the default pnet receiver.
+     */
+    fn next(&mut self) -> io::Result<&[u8]> {
+
+        let receive_result = unsafe {
+            libc::recv(self.socket.0, self.read_buffer.as_mut_ptr() as *mut libc::c_void, self.read_buffer.len(), 0)
+        };
+
+        if receive_result < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Err(io::Error::new(io::ErrorKind::TimedOut, error)),
+                _ => Err(error)
+            };
+        }
+
+        let frame_size = (receive_result as usize).min(self.read_buffer.len());
+        Ok(&self.read_buffer[..frame_size])
+    }
+}
+
+impl StatisticsReader {
+
+    /**
+     * Read the kernel statistics of the socket: the frames accepted by the
+     * filter and the frames dropped because the receive queue was full. The
+     * kernel resets these counters on each read.
+     */
+    pub fn read(&self) -> io::Result<KernelStatistics> {
+
+        let mut statistics = PacketStatistics { tp_packets: 0, tp_drops: 0 };
+        let mut statistics_size = mem::size_of::<PacketStatistics>() as libc::socklen_t;
+
+        let option_result = unsafe {
+            libc::getsockopt(self.socket.0, SOL_PACKET, PACKET_STATISTICS, &mut statistics as *mut PacketStatistics as *mut libc::c_void, &mut statistics_size)
+        };
+        if option_result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(compute_kernel_statistics(&statistics))
+    }
+
+    /**
+     * Build a timestamp reader on the same socket, used by the response
+     * thread to read the kernel timestamp of each received frame.
+     */
+    pub fn timestamp_reader(&self) -> TimestampReader {
+
+        TimestampReader {
+            socket: Arc::clone(&self.socket)
+        }
+    }
+}
+
+impl TimestampReader {
+
+    /**
+     * Read the kernel timestamp of the last frame received on the socket. The
+     * kernel only starts timestamping frames after the first call, which will
+     * then give the current time instead.
+     */
+    pub fn read(&self) -> io::Result<SystemTime> {
+
+        let mut timestamp = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        let ioctl_result = unsafe { libc::ioctl(self.socket.0, SIOCGSTAMPNS as _, &mut timestamp as *mut libc::timespec) };
+        if ioctl_result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(UNIX_EPOCH + Duration::new(timestamp.tv_sec as u64, timestamp.tv_nsec as u32))
+    }
+}
+
+/**
+ * Convert the raw socket statistics, the kernel packet counter includes the
+ * dropped frames that were never queued to the socket.
+ */
+fn compute_kernel_statistics(statistics: &PacketStatistics) -> KernelStatistics {
+
+    KernelStatistics {
+        packet_count: statistics.tp_packets.saturating_sub(statistics.tp_drops).into(),
+        drop_count: statistics.tp_drops.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Minimal classic BPF interpreter, supporting only the instructions used
+     * by the ARP filter (absolute half-word loads, equality jumps & returns).
+     */
+    fn run_filter(filter: &[libc::sock_filter], frame: &[u8]) -> u32 {
+
+        let mut accumulator: u32 = 0;
+        let mut index = 0;
+        loop {
+            let instruction = &filter[index];
+            match instruction.code as u32 {
+                code if code == libc::BPF_LD | libc::BPF_H | libc::BPF_ABS => {
+                    let offset = instruction.k as usize;
+                    if offset + 2 > frame.len() {
+                        return 0;
+                    }
+                    accumulator = u16::from_be_bytes([frame[offset], frame[offset + 1]]).into();
+                    index += 1;
+                },
+                code if code == libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K => {
+                    let jump = match accumulator == instruction.k {
+                        true => instruction.jt,
+                        false => instruction.jf
+                    };
+                    index += 1 + jump as usize;
+                },
+                code if code == libc::BPF_RET | libc::BPF_K => return instruction.k,
+                _ => panic!("Unexpected BPF instruction")
+            }
+        }
+    }
+
+    fn build_frame(ethertypes: &[u16]) -> Vec<u8> {
+
+        let mut frame = vec![0u8; 12];
+        for (position, ethertype) in ethertypes.iter().enumerate() {
+            frame.extend_from_slice(&ethertype.to_be_bytes());
+            if position < ethertypes.len() - 1 {
+                frame.extend_from_slice(&[0, 45]);
+            }
+        }
+        frame.extend_from_slice(&[0u8; 28]);
+        frame
+    }
+
+    #[test]
+    fn should_filter_untagged_arp() {
+
+        let filter = compute_arp_filter(false);
+
+        assert_eq!(run_filter(&filter, &build_frame(&[0x0806])), FILTER_ACCEPT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x0800])), FILTER_REJECT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x8100, 0x0806])), FILTER_REJECT_LENGTH);
+    }
+
+    #[test]
+    fn should_filter_tagged_arp() {
+
+        let filter = compute_arp_filter(true);
+
+        assert_eq!(run_filter(&filter, &build_frame(&[0x0806])), FILTER_ACCEPT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x8100, 0x0806])), FILTER_ACCEPT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x88a8, 0x8100, 0x0806])), FILTER_ACCEPT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x8100, 0x0800])), FILTER_REJECT_LENGTH);
+        assert_eq!(run_filter(&filter, &build_frame(&[0x86dd])), FILTER_REJECT_LENGTH);
+    }
+
+    #[test]
+    fn should_reject_deep_vlan_stack() {
+
+        let filter = compute_arp_filter(true);
+
+        assert_eq!(run_filter(&filter, &build_frame(&[0x88a8, 0x8100, 0x8100, 0x0806])), FILTER_REJECT_LENGTH);
+    }
+
+    #[test]
+    fn should_exclude_drops_from_packet_count() {
+
+        let statistics = PacketStatistics { tp_packets: 120, tp_drops: 20 };
+
+        assert_eq!(compute_kernel_statistics(&statistics), KernelStatistics { packet_count: 100, drop_count: 20 });
+    }
+}
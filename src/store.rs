@@ -0,0 +1,337 @@
+use rusqlite::{params, Connection};
+
+use crate::metadata::ScanMetadata;
+use crate::network::{ResponseSummary, TargetDetails};
+
+// The store version is saved in the SQLite 'user_version' pragma, and is
+// increased on each incompatible change of the tables below.
+const STORE_VERSION: i64 = 1;
+
+const STORE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY,
+        tool_version TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        hostname TEXT,
+        profile TEXT NOT NULL,
+        timeout_ms INTEGER NOT NULL,
+        retry_count INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        packet_count INTEGER NOT NULL,
+        arp_count INTEGER NOT NULL,
+        request_count INTEGER NOT NULL,
+        host_count INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS scan_lanes (
+        id INTEGER PRIMARY KEY,
+        scan_id INTEGER NOT NULL REFERENCES scans(id) ON DELETE CASCADE,
+        interface TEXT NOT NULL,
+        interface_mac TEXT NOT NULL,
+        vlan INTEGER,
+        source_ipv4 TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS scan_networks (
+        lane_id INTEGER NOT NULL REFERENCES scan_lanes(id) ON DELETE CASCADE,
+        network TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS hosts (
+        id INTEGER PRIMARY KEY,
+        scan_id INTEGER NOT NULL REFERENCES scans(id) ON DELETE CASCADE,
+        interface TEXT,
+        vlan INTEGER,
+        ipv4 TEXT NOT NULL,
+        mac TEXT NOT NULL,
+        ethernet_mac TEXT NOT NULL,
+        hostname TEXT,
+        vendor TEXT,
+        flags TEXT NOT NULL,
+        rtt_us INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS hosts_mac ON hosts (mac);
+    CREATE INDEX IF NOT EXISTS hosts_ipv4 ON hosts (ipv4);
+";
+
+/**
+ * A scan saved in the results store, as listed by the history command.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredScan {
+    pub id: i64,
+    pub started_at: String,
+    pub interfaces: String,
+    pub duration_ms: i64,
+    pub host_count: i64
+}
+
+/**
+ * The sightings of a MAC address (or an IPv4 address) across all stored
+ * scans, grouped by linked address (the IPv4 of a MAC, or the MAC of an IPv4).
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostSighting {
+    pub ipv4: String,
+    pub mac: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub scan_count: i64
+}
+
+/**
+ * Stores the results of each scan (metadata, summary and hosts) in an SQLite
+ * database, so that hosts can be followed across many scans.
+ */
+pub struct ResultStore {
+    connection: Connection
+}
+
+impl ResultStore {
+
+    /**
+     * Open (or create) the results store, the schema is created on the first
+     * use. A store written by a newer version is rejected.
+     */
+    pub fn open(file_path: &str) -> Result<ResultStore, String> {
+
+        let connection = Connection::open(file_path).map_err(|err| {
+            format!("Could not open results store {} - {}", file_path, err)
+        })?;
+        ResultStore::init(connection).map_err(|err| format!("{} ({})", err, file_path))
+    }
+
+    fn init(connection: Connection) -> Result<ResultStore, String> {
+
+        let store_version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|err| {
+            format!("Could not read results store version - {}", err)
+        })?;
+        if store_version > STORE_VERSION {
+            return Err(format!("Expected results store version {} at most, found version {}", STORE_VERSION, store_version));
+        }
+
+        connection.execute_batch(STORE_SCHEMA).map_err(|err| {
+            format!("Could not create results store tables - {}", err)
+        })?;
+        connection.execute_batch(&format!("PRAGMA user_version = {}; PRAGMA foreign_keys = ON;", STORE_VERSION)).map_err(|err| {
+            format!("Could not update results store version - {}", err)
+        })?;
+
+        Ok(ResultStore { connection })
+    }
+
+    /**
+     * Save a finished scan in a single transaction, a failure will never
+     * leave a partially saved scan. Returns the identifier of the new scan.
+     */
+    pub fn save_scan(&mut self, response_summary: &ResponseSummary, target_details: &[TargetDetails]) -> Result<i64, String> {
+
+        let scan_metadata = response_summary.scan_metadata.as_ref().ok_or_else(|| {
+            "Could not store a scan without metadata".to_string()
+        })?;
+
+        let transaction = self.connection.transaction().map_err(|err| format!("Could not start results store transaction - {}", err))?;
+        let scan_id = insert_scan(&transaction, scan_metadata, response_summary, target_details)
+            .map_err(|err| format!("Could not store scan results - {}", err))?;
+        transaction.commit().map_err(|err| format!("Could not commit scan results - {}", err))?;
+
+        Ok(scan_id)
+    }
+
+    /**
+     * List the most recent scans, the latest scan first.
+     */
+    pub fn list_scans(&self, limit: usize) -> Result<Vec<StoredScan>, String> {
+
+        let mut statement = self.connection.prepare("
+            SELECT scans.id, scans.started_at, COALESCE(GROUP_CONCAT(DISTINCT scan_lanes.interface), ''), scans.duration_ms, scans.host_count
+            FROM scans LEFT JOIN scan_lanes ON scan_lanes.scan_id = scans.id
+            GROUP BY scans.id
+            ORDER BY scans.started_at DESC, scans.id DESC
+            LIMIT ?1
+        ").map_err(|err| format!("Could not list stored scans - {}", err))?;
+
+        let scans = statement.query_map(params![limit as i64], |row| Ok(StoredScan {
+            id: row.get(0)?,
+            started_at: row.get(1)?,
+            interfaces: row.get(2)?,
+            duration_ms: row.get(3)?,
+            host_count: row.get(4)?
+        })).and_then(|rows| rows.collect::<Result<Vec<StoredScan>, rusqlite::Error>>());
+
+        scans.map_err(|err| format!("Could not list stored scans - {}", err))
+    }
+
+    /**
+     * Find when a MAC address was first and last seen, for each IPv4 address
+     * it answered for.
+     */
+    pub fn find_mac_history(&self, mac: &str) -> Result<Vec<HostSighting>, String> {
+
+        self.find_sightings("hosts.mac = ?1", &mac.to_lowercase())
+    }
+
+    /**
+     * Find when an IPv4 address was first and last seen, for each MAC address
+     * that answered for it.
+     */
+    pub fn find_ip_history(&self, ipv4: &str) -> Result<Vec<HostSighting>, String> {
+
+        self.find_sightings("hosts.ipv4 = ?1", ipv4)
+    }
+
+    fn find_sightings(&self, host_filter: &str, filter_value: &str) -> Result<Vec<HostSighting>, String> {
+
+        let mut statement = self.connection.prepare(&format!("
+            SELECT hosts.ipv4, hosts.mac, MIN(scans.started_at), MAX(scans.finished_at), COUNT(DISTINCT scans.id)
+            FROM hosts JOIN scans ON scans.id = hosts.scan_id
+            WHERE {}
+            GROUP BY hosts.ipv4, hosts.mac
+            ORDER BY MIN(scans.started_at)
+        ", host_filter)).map_err(|err| format!("Could not query host history - {}", err))?;
+
+        let sightings = statement.query_map(params![filter_value], |row| Ok(HostSighting {
+            ipv4: row.get(0)?,
+            mac: row.get(1)?,
+            first_seen: row.get(2)?,
+            last_seen: row.get(3)?,
+            scan_count: row.get(4)?
+        })).and_then(|rows| rows.collect::<Result<Vec<HostSighting>, rusqlite::Error>>());
+
+        sightings.map_err(|err| format!("Could not query host history - {}", err))
+    }
+}
+
+fn insert_scan(connection: &Connection, scan_metadata: &ScanMetadata, response_summary: &ResponseSummary, target_details: &[TargetDetails]) -> rusqlite::Result<i64> {
+
+    connection.execute("
+        INSERT INTO scans (tool_version, started_at, finished_at, hostname, profile, timeout_ms, retry_count, duration_ms, packet_count, arp_count, request_count, host_count)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+    ", params![
+        scan_metadata.version,
+        scan_metadata.started_at,
+        scan_metadata.finished_at,
+        scan_metadata.hostname,
+        scan_metadata.profile,
+        scan_metadata.timing.timeout_ms as i64,
+        scan_metadata.timing.retry_count as i64,
+        response_summary.duration_ms as i64,
+        response_summary.packet_count as i64,
+        response_summary.arp_count as i64,
+        response_summary.request_count as i64,
+        target_details.len() as i64
+    ])?;
+    let scan_id = connection.last_insert_rowid();
+
+    for interface in scan_metadata.interfaces.iter() {
+        for lane in interface.lanes.iter() {
+
+            connection.execute(
+                "INSERT INTO scan_lanes (scan_id, interface, interface_mac, vlan, source_ipv4) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![scan_id, interface.name, interface.mac, lane.vlan, lane.source_ipv4]
+            )?;
+            let lane_id = connection.last_insert_rowid();
+
+            for network in lane.networks.iter() {
+                connection.execute("INSERT INTO scan_networks (lane_id, network) VALUES (?1, ?2)", params![lane_id, network])?;
+            }
+        }
+    }
+
+    for detail in target_details.iter() {
+
+        let flags = detail.flags.iter().map(|flag| flag.label()).collect::<Vec<String>>().join(",");
+        connection.execute("
+            INSERT INTO hosts (scan_id, interface, vlan, ipv4, mac, ethernet_mac, hostname, vendor, flags, rtt_us)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+        ", params![
+            scan_id,
+            detail.interface_name,
+            detail.vlan_id,
+            detail.ipv4.to_string(),
+            detail.mac.to_string(),
+            detail.ethernet_mac.to_string(),
+            detail.hostname,
+            detail.vendor,
+            flags,
+            detail.rtt.map(|rtt| rtt.as_micros() as i64)
+        ])?;
+    }
+
+    Ok(scan_id)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, UNIX_EPOCH};
+    use pnet_datalink::MacAddr;
+    use crate::args::{build_args, ScanOptions};
+
+    fn build_target(last_byte: u8, mac_byte: u8) -> TargetDetails {
+
+        TargetDetails {
+            interface_name: Some("eth0".to_string()),
+            rtt: Some(Duration::from_micros(420)),
+            ..TargetDetails::for_test(Ipv4Addr::new(10, 0, 0, last_byte), MacAddr::new(0x00, 0x16, 0xf6, 0x00, 0x00, mac_byte))
+        }
+    }
+
+    fn build_summary(started_seconds: u64) -> ResponseSummary {
+
+        let options = ScanOptions::new(&build_args().get_matches_from(vec!["arp-scan"]));
+        ResponseSummary {
+            scan_metadata: Some(ScanMetadata::new(UNIX_EPOCH + Duration::from_secs(started_seconds), &[], &options)),
+            ..ResponseSummary::default()
+        }
+    }
+
+    fn open_memory_store() -> ResultStore {
+
+        ResultStore::init(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_store_scans() {
+
+        let mut store = open_memory_store();
+
+        let first_id = store.save_scan(&build_summary(1_700_000_000), &[build_target(1, 1), build_target(2, 2)]).unwrap();
+        let second_id = store.save_scan(&build_summary(1_700_003_600), &[build_target(1, 1)]).unwrap();
+
+        let scans = store.list_scans(10).unwrap();
+        assert_eq!(scans.len(), 2);
+        assert_eq!(scans[0].id, second_id);
+        assert_eq!(scans[1].id, first_id);
+        assert_eq!(scans[1].host_count, 2);
+    }
+
+    #[test]
+    fn should_find_host_history() {
+
+        let mut store = open_memory_store();
+        store.save_scan(&build_summary(1_700_000_000), &[build_target(1, 1), build_target(2, 2)]).unwrap();
+        store.save_scan(&build_summary(1_700_003_600), &[build_target(1, 1), build_target(2, 1)]).unwrap();
+
+        let mac_history = store.find_mac_history("00:16:F6:00:00:01").unwrap();
+        assert_eq!(mac_history.len(), 2);
+        assert_eq!(mac_history[0].ipv4, "10.0.0.1");
+        assert_eq!(mac_history[0].first_seen, "2023-11-14T22:13:20Z");
+        assert_eq!(mac_history[0].scan_count, 2);
+        assert_eq!(mac_history[1].ipv4, "10.0.0.2");
+        assert_eq!(mac_history[1].scan_count, 1);
+
+        let ip_history = store.find_ip_history("10.0.0.2").unwrap();
+        assert_eq!(ip_history.len(), 2);
+        assert_eq!(ip_history[0].mac, "00:16:f6:00:00:02");
+    }
+
+    #[test]
+    fn should_reject_scan_without_metadata() {
+
+        let mut store = open_memory_store();
+
+        assert!(store.save_scan(&ResponseSummary::default(), &[]).is_err());
+        assert_eq!(store.list_scans(10), Ok(vec![]));
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::process;
+
+use pnet_datalink::MacAddr;
+use serde::Serialize;
+
+use crate::args::ScanOptions;
+use crate::network::{self, ResponseSummary, TargetDetails};
+use crate::utils;
+use crate::vendor::Vendor;
+
+const CLI_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/**
+ * A single NDJSON record, tagged with its type. A streamed scan always starts
+ * with a 'start' record, then gives a 'host' record for each discovered host
+ * and ends with a 'summary' record.
+ */
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum StreamRecord {
+    Start {
+        version: String,
+        interfaces: Vec<String>,
+        target_count: u128,
+        timeout_ms: u64,
+        retry_count: usize,
+        random_seed: Option<u64>
+    },
+    Host {
+        ipv4: String,
+        mac: String,
+        vlan: Option<u16>,
+        interface: Option<String>,
+        hostname: String,
+        vendor: String
+    },
+    Summary {
+        host_count: usize,
+        packet_count: usize,
+        arp_count: usize,
+        duration_ms: u128,
+        request_count: u64,
+        requested_rate: Option<f64>,
+        achieved_rate: Option<f64>,
+        rtt_p99_us: Option<u128>,
+        kernel_packet_count: Option<u64>,
+        kernel_drop_count: Option<u64>
+    }
+}
+
+/**
+ * Streams discovered hosts as NDJSON records while the scan is running. Each
+ * host is only streamed once (per interface and VLAN), hosts are enriched
+ * with a hostname and vendor outside of the response threads.
+ */
+pub struct HostStream {
+    streamed_hosts: HashSet<(Option<String>, Option<u16>, Ipv4Addr)>,
+    vendor_list: Vendor
+}
+
+impl HostStream {
+
+    pub fn new() -> HostStream {
+
+        HostStream {
+            streamed_hosts: HashSet::new(),
+            vendor_list: Vendor::new()
+        }
+    }
+
+    /**
+     * Stream a host that answered on an interface, if not already streamed.
+     */
+    pub fn stream_response(&mut self, interface_name: &str, vlan_id: Option<u16>, ipv4: Ipv4Addr, mac: MacAddr, options: &ScanOptions) {
+
+        let host_key = (Some(interface_name.to_string()), vlan_id, ipv4);
+        if self.streamed_hosts.contains(&host_key) {
+            return;
+        }
+
+        let target_detail = TargetDetails {
+            ipv4,
+            mac,
+            ethernet_mac: mac,
+            vlan_id,
+            interface_name: Some(interface_name.to_string()),
+            hostname: None,
+            vendor: None,
+            flags: vec![],
+            rtt: None,
+            discovered_at: None,
+            response_macs: vec![mac]
+        };
+        let target_details = network::enrich_target_details(vec![target_detail], options, &mut self.vendor_list);
+
+        self.streamed_hosts.insert(host_key);
+        target_details.iter().for_each(print_host_record);
+    }
+
+    /**
+     * Stream all final results that were not streamed during the scan (late
+     * responses, hosts of a resumed scan, ...).
+     */
+    pub fn stream_remaining(&mut self, target_details: &[TargetDetails]) {
+
+        for detail in target_details.iter() {
+
+            let host_key = (detail.interface_name.clone(), detail.vlan_id, detail.ipv4);
+            if self.streamed_hosts.insert(host_key) {
+                print_host_record(detail);
+            }
+        }
+    }
+}
+
+impl Default for HostStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn print_host_record(detail: &TargetDetails) {
+
+    print_record(&build_host_record(detail));
+}
+
+fn build_host_record(detail: &TargetDetails) -> StreamRecord {
+
+    StreamRecord::Host {
+        ipv4: detail.ipv4.to_string(),
+        mac: detail.mac.to_string(),
+        vlan: detail.vlan_id,
+        interface: detail.interface_name.clone(),
+        hostname: detail.hostname.clone().unwrap_or_default(),
+        vendor: detail.vendor.clone().unwrap_or_default()
+    }
+}
+
+/**
+ * Print the start record of a streamed scan, with the scan settings.
+ */
+pub fn print_start_record(interface_names: Vec<String>, target_count: u128, options: &ScanOptions) {
+
+    print_record(&build_start_record(interface_names, target_count, options));
+}
+
+fn build_start_record(interface_names: Vec<String>, target_count: u128, options: &ScanOptions) -> StreamRecord {
+
+    StreamRecord::Start {
+        version: CLI_VERSION.to_string(),
+        interfaces: interface_names,
+        target_count,
+        timeout_ms: options.timeout_ms,
+        retry_count: options.retry_count,
+        random_seed: options.random_seed
+    }
+}
+
+/**
+ * Print the summary record of a streamed scan, once all hosts are streamed.
+ */
+pub fn print_summary_record(response_summary: &ResponseSummary, host_count: usize) {
+
+    print_record(&build_summary_record(response_summary, host_count));
+}
+
+fn build_summary_record(response_summary: &ResponseSummary, host_count: usize) -> StreamRecord {
+
+    StreamRecord::Summary {
+        host_count,
+        packet_count: response_summary.packet_count,
+        arp_count: response_summary.arp_count,
+        duration_ms: response_summary.duration_ms,
+        request_count: response_summary.request_count,
+        requested_rate: response_summary.requested_rate,
+        achieved_rate: response_summary.achieved_rate,
+        rtt_p99_us: response_summary.rtt_p99_us,
+        kernel_packet_count: response_summary.kernel_statistics.map(|statistics| statistics.packet_count),
+        kernel_drop_count: response_summary.kernel_statistics.map(|statistics| statistics.drop_count)
+    }
+}
+
+/**
+ * Export a finished scan as NDJSON records (start, hosts and summary), used
+ * when the records are written to a file instead of being streamed.
+ */
+pub fn export_to_ndjson(interface_names: Vec<String>, target_count: u128, response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>, options: &ScanOptions) -> String {
+
+    utils::sort_target_details(&mut target_details);
+
+    let mut output = format_record(&build_start_record(interface_names, target_count, options));
+    for detail in target_details.iter() {
+        output.push_str(&format_record(&build_host_record(detail)));
+    }
+    output.push_str(&format_record(&build_summary_record(&response_summary, target_details.len())));
+    output
+}
+
+/**
+ * Print a record on a single line, the standard output being line-buffered
+ * each record is immediately available for the reading process.
+ */
+fn print_record(record: &StreamRecord) {
+
+    print!("{}", format_record(record));
+}
+
+fn format_record(record: &StreamRecord) -> String {
+
+    let record_line = serde_json::to_string(record).unwrap_or_else(|err| {
+        eprintln!("Could not export NDJSON record ({})", err);
+        process::exit(1);
+    });
+    format!("{}\n", record_line)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_tag_stream_records() {
+
+        let record = StreamRecord::Host {
+            ipv4: "192.168.1.1".to_string(),
+            mac: "00:16:f6:00:00:01".to_string(),
+            vlan: None,
+            interface: Some("eth0".to_string()),
+            hostname: "".to_string(),
+            vendor: "".to_string()
+        };
+
+        let record_line = serde_json::to_string(&record).unwrap();
+
+        assert!(record_line.starts_with("{\"type\":\"host\",\"ipv4\":\"192.168.1.1\""));
+        assert!(!record_line.contains('\n'));
+    }
+}
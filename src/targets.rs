@@ -0,0 +1,240 @@
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use ipnetwork::{IpNetwork, Ipv4Network};
+
+// Octet patterns may expand to a huge number of disjoint ranges (for instance
+// '*.*.*.1'), such patterns are rejected instead of exhausting the memory.
+const MAX_PATTERN_RANGES: u64 = 65536;
+
+/**
+ * Parse a single target specification into IPv4 networks. A specification can
+ * be a CIDR network or address ('10.0.0.0/24', '10.0.0.1'), a dash range
+ * ('10.0.0.10-10.0.0.50' or '10.0.0.10-50') or an octet pattern with
+ * wildcards and octet ranges ('10.1-3.*.1'). Ranges are turned into the
+ * smallest list of CIDR networks covering exactly the same addresses.
+ */
+pub fn parse_target(target_text: &str) -> Result<Vec<IpNetwork>, String> {
+
+    let target_text = target_text.trim();
+
+    if let Some((first_text, last_text)) = target_text.split_once('-') {
+        if is_full_address(first_text) && is_full_address(last_text) {
+            return parse_dash_range(first_text, last_text);
+        }
+    }
+
+    if is_range_target(target_text) && is_full_address(target_text) {
+        return parse_octet_pattern(target_text);
+    }
+
+    IpNetwork::from_str(target_text)
+        .map(|network| vec![network])
+        .map_err(|err| format!("Expected valid IPv4 network range ({})", err))
+}
+
+/**
+ * Check if a target specification is a dash range or an octet pattern. Such
+ * targets are expanded in CIDR blocks that the user did not give, the network
+ * and broadcast addresses of these blocks are regular targets.
+ */
+pub fn is_range_target(target_text: &str) -> bool {
+    target_text.contains('*') || target_text.contains('-')
+}
+
+fn is_full_address(address_text: &str) -> bool {
+    address_text.split('.').count() == 4
+}
+
+/**
+ * Parse a dash range between two full IPv4 addresses.
+ */
+fn parse_dash_range(first_text: &str, last_text: &str) -> Result<Vec<IpNetwork>, String> {
+
+    let first_ip = Ipv4Addr::from_str(first_text.trim()).map_err(|err| {
+        format!("Expected valid IPv4 range start {} ({})", first_text, err)
+    })?;
+    let last_ip = Ipv4Addr::from_str(last_text.trim()).map_err(|err| {
+        format!("Expected valid IPv4 range end {} ({})", last_text, err)
+    })?;
+
+    if first_ip > last_ip {
+        return Err(format!("Expected ascending IPv4 range ({} is after {})", first_ip, last_ip));
+    }
+    Ok(compute_range_networks(u32::from(first_ip), u32::from(last_ip)))
+}
+
+/**
+ * Parse an IPv4 pattern where each octet is a value, an octet range ('1-3')
+ * or a wildcard ('*'). Trailing wildcards and the last octet range are
+ * merged in contiguous address ranges, leading octets are enumerated.
+ */
+fn parse_octet_pattern(pattern_text: &str) -> Result<Vec<IpNetwork>, String> {
+
+    let octet_texts: Vec<&str> = pattern_text.split('.').collect();
+    if octet_texts.len() != 4 {
+        return Err(format!("Expected IPv4 pattern with 4 octets ({})", pattern_text));
+    }
+
+    let octet_ranges: Vec<(u32, u32)> = octet_texts.iter()
+        .map(|octet_text| parse_octet_range(octet_text, pattern_text))
+        .collect::<Result<Vec<(u32, u32)>, String>>()?;
+
+    // The last octet that is not a full wildcard gives the size of each
+    // contiguous range, all octets before are enumerated.
+    let range_octet = octet_ranges.iter().rposition(|range| *range != (0, 255)).unwrap_or(0);
+    let block_bits = 8 * (3 - range_octet) as u32;
+
+    let range_count: u64 = octet_ranges[..range_octet].iter()
+        .map(|(first, last)| u64::from(last - first) + 1)
+        .product();
+    if range_count > MAX_PATTERN_RANGES {
+        return Err(format!("IPv4 pattern {} expands to too many ranges ({} max)", pattern_text, MAX_PATTERN_RANGES));
+    }
+
+    let mut prefixes: Vec<u32> = vec![0];
+    for (first, last) in octet_ranges[..range_octet].iter() {
+        prefixes = prefixes.iter()
+            .flat_map(|prefix| (*first..=*last).map(move |octet| (prefix << 8) | octet))
+            .collect();
+    }
+
+    let (range_first, range_last) = octet_ranges[range_octet];
+    let block_mask = ((1u64 << block_bits) - 1) as u32;
+
+    let networks = prefixes.into_iter().flat_map(|prefix| {
+
+        let base = match range_octet {
+            0 => 0,
+            _ => prefix << (32 - 8 * range_octet as u32)
+        };
+        let first = base | (range_first << block_bits);
+        let last = base | (range_last << block_bits) | block_mask;
+        compute_range_networks(first, last)
+
+    }).collect();
+
+    Ok(networks)
+}
+
+fn parse_octet_range(octet_text: &str, pattern_text: &str) -> Result<(u32, u32), String> {
+
+    let parse_octet = |text: &str| text.trim().parse::<u8>().map(u32::from).map_err(|_| {
+        format!("Expected valid IPv4 octet in pattern {} ({})", pattern_text, octet_text)
+    });
+
+    let (first, last) = match octet_text {
+        "*" => (0, 255),
+        _ => match octet_text.split_once('-') {
+            Some((first_text, last_text)) => (parse_octet(first_text)?, parse_octet(last_text)?),
+            None => {
+                let octet = parse_octet(octet_text)?;
+                (octet, octet)
+            }
+        }
+    };
+
+    if first > last {
+        return Err(format!("Expected ascending octet range in pattern {} ({})", pattern_text, octet_text));
+    }
+    Ok((first, last))
+}
+
+/**
+ * Compute the smallest list of CIDR networks covering an inclusive range of
+ * IPv4 addresses. Each network is the largest aligned block starting at the
+ * first remaining address and fitting in the range.
+ */
+pub fn compute_range_networks(first: u32, last: u32) -> Vec<IpNetwork> {
+
+    let mut networks: Vec<IpNetwork> = vec![];
+    let mut block_start = u64::from(first);
+    let range_end = u64::from(last);
+
+    while block_start <= range_end {
+
+        let mut block_bits = match block_start {
+            0 => 32,
+            _ => block_start.trailing_zeros().min(32)
+        };
+        while block_start + (1u64 << block_bits) - 1 > range_end {
+            block_bits -= 1;
+        }
+
+        let block_ip = Ipv4Addr::from(block_start as u32);
+        let network = Ipv4Network::new(block_ip, (32 - block_bits) as u8).expect("Internal prefix computation failed");
+        networks.push(IpNetwork::V4(network));
+
+        block_start += 1u64 << block_bits;
+    }
+    networks
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn build_network(ip: Ipv4Addr, prefix: u8) -> IpNetwork {
+        IpNetwork::V4(Ipv4Network::new(ip, prefix).unwrap())
+    }
+
+    #[test]
+    fn should_parse_cidr_target() {
+
+        assert_eq!(parse_target("10.0.0.0/24"), Ok(vec![build_network(Ipv4Addr::new(10, 0, 0, 0), 24)]));
+        assert_eq!(parse_target(" 10.0.0.1 "), Ok(vec![build_network(Ipv4Addr::new(10, 0, 0, 1), 32)]));
+    }
+
+    #[test]
+    fn should_parse_dash_range() {
+
+        let expected_networks = vec![
+            build_network(Ipv4Addr::new(10, 0, 0, 10), 31),
+            build_network(Ipv4Addr::new(10, 0, 0, 12), 30),
+            build_network(Ipv4Addr::new(10, 0, 0, 16), 28),
+            build_network(Ipv4Addr::new(10, 0, 0, 32), 28),
+            build_network(Ipv4Addr::new(10, 0, 0, 48), 31),
+            build_network(Ipv4Addr::new(10, 0, 0, 50), 32)
+        ];
+
+        assert_eq!(parse_target("10.0.0.10-10.0.0.50"), Ok(expected_networks.clone()));
+        assert_eq!(parse_target("10.0.0.10-50"), Ok(expected_networks));
+    }
+
+    #[test]
+    fn should_parse_dash_range_across_networks() {
+
+        assert_eq!(parse_target("10.0.0.0-10.0.3.255"), Ok(vec![build_network(Ipv4Addr::new(10, 0, 0, 0), 22)]));
+        assert_eq!(parse_target("0.0.0.0-255.255.255.255"), Ok(vec![build_network(Ipv4Addr::new(0, 0, 0, 0), 0)]));
+    }
+
+    #[test]
+    fn should_parse_octet_pattern() {
+
+        let networks = parse_target("10.1-3.*.1").unwrap();
+
+        assert_eq!(networks.len(), 768);
+        assert_eq!(networks[0], build_network(Ipv4Addr::new(10, 1, 0, 1), 32));
+        assert_eq!(networks[767], build_network(Ipv4Addr::new(10, 3, 255, 1), 32));
+    }
+
+    #[test]
+    fn should_merge_trailing_wildcards() {
+
+        assert_eq!(parse_target("10.2.*.*"), Ok(vec![build_network(Ipv4Addr::new(10, 2, 0, 0), 16)]));
+        assert_eq!(parse_target("10.4-7.*.*"), Ok(vec![build_network(Ipv4Addr::new(10, 4, 0, 0), 14)]));
+        assert_eq!(parse_target("*.*.*.*"), Ok(vec![build_network(Ipv4Addr::new(0, 0, 0, 0), 0)]));
+    }
+
+    #[test]
+    fn should_fail_incorrect_targets() {
+
+        assert!(parse_target("10.0.0.50-10.0.0.10").is_err());
+        assert!(parse_target("10.0.0.50-10").is_err());
+        assert!(parse_target("10.0.*").is_err());
+        assert!(parse_target("no-network").is_err());
+        assert!(parse_target("10.0.300.*").is_err());
+        assert!(parse_target("*.*.*.1").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use crate::network::{ResponseSummary, TargetDetails};
+use crate::utils;
+
+/**
+ * A field that can be referenced in an output template. Host fields are only
+ * available in the host template, while summary fields can be used in all
+ * templates (header, host and footer).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateField {
+    Index,
+    Ip,
+    Mac,
+    EthernetMac,
+    Vlan,
+    Interface,
+    Hostname,
+    Vendor,
+    Flags,
+    RttMs,
+    RttUs,
+    HostCount,
+    PacketCount,
+    ArpCount,
+    DurationMs,
+    RequestCount,
+    RequestedRate,
+    AchievedRate,
+    RttP99Us,
+    KernelPacketCount,
+    KernelDropCount,
+    StartedAt,
+    FinishedAt,
+    Scanner,
+    Profile,
+    Version
+}
+
+impl TemplateField {
+
+    pub fn parse(field_text: &str) -> Result<TemplateField, String> {
+
+        match field_text {
+            "index" => Ok(TemplateField::Index),
+            "ip" => Ok(TemplateField::Ip),
+            "mac" => Ok(TemplateField::Mac),
+            "ethernet_mac" => Ok(TemplateField::EthernetMac),
+            "vlan" => Ok(TemplateField::Vlan),
+            "interface" => Ok(TemplateField::Interface),
+            "hostname" => Ok(TemplateField::Hostname),
+            "vendor" => Ok(TemplateField::Vendor),
+            "flags" => Ok(TemplateField::Flags),
+            "rtt_ms" => Ok(TemplateField::RttMs),
+            "rtt_us" => Ok(TemplateField::RttUs),
+            "host_count" => Ok(TemplateField::HostCount),
+            "packet_count" => Ok(TemplateField::PacketCount),
+            "arp_count" => Ok(TemplateField::ArpCount),
+            "duration_ms" => Ok(TemplateField::DurationMs),
+            "request_count" => Ok(TemplateField::RequestCount),
+            "requested_rate" => Ok(TemplateField::RequestedRate),
+            "achieved_rate" => Ok(TemplateField::AchievedRate),
+            "rtt_p99_us" => Ok(TemplateField::RttP99Us),
+            "kernel_packet_count" => Ok(TemplateField::KernelPacketCount),
+            "kernel_drop_count" => Ok(TemplateField::KernelDropCount),
+            "started_at" => Ok(TemplateField::StartedAt),
+            "finished_at" => Ok(TemplateField::FinishedAt),
+            "scanner" => Ok(TemplateField::Scanner),
+            "profile" => Ok(TemplateField::Profile),
+            "version" => Ok(TemplateField::Version),
+            _ => Err(format!("Expected known template field, got {{{}}}", field_text))
+        }
+    }
+
+    fn is_host_field(&self) -> bool {
+
+        matches!(self,
+            TemplateField::Index | TemplateField::Ip | TemplateField::Mac | TemplateField::EthernetMac |
+            TemplateField::Vlan | TemplateField::Interface | TemplateField::Hostname | TemplateField::Vendor |
+            TemplateField::Flags | TemplateField::RttMs | TemplateField::RttUs
+        )
+    }
+}
+
+/**
+ * The escaping applied to each field value (never to the template text), so
+ * that hostnames or vendors cannot break the line format of an integration.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateEscape {
+    Raw,
+    Csv,
+    Json,
+    Shell
+}
+
+impl TemplateEscape {
+
+    pub fn parse(escape_text: &str) -> Result<TemplateEscape, String> {
+
+        match escape_text {
+            "none" => Ok(TemplateEscape::Raw),
+            "csv" => Ok(TemplateEscape::Csv),
+            "json" => Ok(TemplateEscape::Json),
+            "shell" => Ok(TemplateEscape::Shell),
+            _ => Err(format!("Expected correct template escaping (none/csv/json/shell), got {}", escape_text))
+        }
+    }
+
+    fn apply(&self, value: &str) -> String {
+
+        match self {
+            TemplateEscape::Raw => value.to_string(),
+            TemplateEscape::Csv => match value.contains([',', '"', '\n', '\r']) {
+                true => format!("\"{}\"", value.replace('"', "\"\"")),
+                false => value.to_string()
+            },
+            TemplateEscape::Json => {
+                let json_text = serde_json::to_string(value).unwrap_or_default();
+                json_text[1..json_text.len() - 1].to_string()
+            },
+            TemplateEscape::Shell => format!("'{}'", value.replace('\'', "'\\''"))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    Field(TemplateField, Option<String>)
+}
+
+/**
+ * A parsed line template, with '{field}' placeholders. A missing value (no
+ * hostname, no VLAN, ...) is replaced by the default given after a pipe, as
+ * in '{hostname|unknown}', or by an empty text. Literal braces are written
+ * '{{' and '}}', while '\t', '\n' and '\\' give a tab, a newline and a
+ * backslash.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<TemplatePart>
+}
+
+impl Template {
+
+    pub fn parse(template_text: &str, allows_host_fields: bool) -> Result<Template, String> {
+
+        let mut parts: Vec<TemplatePart> = vec![];
+        let mut text = String::new();
+        let mut characters = template_text.chars().peekable();
+
+        while let Some(character) = characters.next() {
+
+            match character {
+                '\\' => match characters.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') => text.push('\\'),
+                    _ => return Err(format!("Expected \\t, \\n or \\\\ escape sequence in template ({})", template_text))
+                },
+                '}' => match characters.next() {
+                    Some('}') => text.push('}'),
+                    _ => return Err(format!("Expected '}}}}' for a literal brace in template ({})", template_text))
+                },
+                '{' if characters.peek() == Some(&'{') => {
+                    characters.next();
+                    text.push('{');
+                },
+                '{' => {
+                    let mut placeholder = String::new();
+                    let mut is_closed = false;
+                    for character in characters.by_ref() {
+                        if character == '}' {
+                            is_closed = true;
+                            break;
+                        }
+                        placeholder.push(character);
+                    }
+                    if !is_closed || placeholder.contains('{') {
+                        return Err(format!("Expected closing brace after template field in template ({})", template_text));
+                    }
+
+                    let (field_text, default_text) = match placeholder.split_once('|') {
+                        Some((field_text, default_text)) => (field_text, Some(default_text.to_string())),
+                        None => (placeholder.as_str(), None)
+                    };
+                    let field = TemplateField::parse(field_text)?;
+                    if field.is_host_field() && !allows_host_fields {
+                        return Err(format!("Expected summary fields in header and footer templates, got {{{}}}", field_text));
+                    }
+
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(text.clone()));
+                        text.clear();
+                    }
+                    parts.push(TemplatePart::Field(field, default_text));
+                },
+                _ => text.push(character)
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    fn render(&self, host: Option<(usize, &TargetDetails)>, response_summary: &ResponseSummary, host_count: usize, escape: TemplateEscape) -> String {
+
+        self.parts.iter().map(|part| match part {
+            TemplatePart::Text(text) => text.clone(),
+            TemplatePart::Field(field, default_text) => {
+                match find_field_value(*field, host, response_summary, host_count) {
+                    Some(value) => escape.apply(&value),
+                    None => default_text.clone().unwrap_or_default()
+                }
+            }
+        }).collect()
+    }
+}
+
+/**
+ * All templates of a user-defined output: an optional header and footer,
+ * written once, and a line template written for each host.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputTemplate {
+    pub header: Option<Template>,
+    pub host: Template,
+    pub footer: Option<Template>,
+    pub escape: TemplateEscape
+}
+
+fn find_field_value(field: TemplateField, host: Option<(usize, &TargetDetails)>, response_summary: &ResponseSummary, host_count: usize) -> Option<String> {
+
+    let scan_metadata = response_summary.scan_metadata.as_ref();
+    let kernel_statistics = response_summary.kernel_statistics.as_ref();
+
+    match (field, host) {
+        (TemplateField::Index, Some((index, _))) => Some((index + 1).to_string()),
+        (TemplateField::Ip, Some((_, detail))) => Some(detail.ipv4.to_string()),
+        (TemplateField::Mac, Some((_, detail))) => Some(detail.mac.to_string()),
+        (TemplateField::EthernetMac, Some((_, detail))) => Some(detail.ethernet_mac.to_string()),
+        (TemplateField::Vlan, Some((_, detail))) => detail.vlan_id.map(|vlan_id| vlan_id.to_string()),
+        (TemplateField::Interface, Some((_, detail))) => detail.interface_name.clone(),
+        (TemplateField::Hostname, Some((_, detail))) => detail.hostname.clone(),
+        (TemplateField::Vendor, Some((_, detail))) => detail.vendor.clone(),
+        (TemplateField::Flags, Some((_, detail))) => match detail.flags.is_empty() {
+            true => None,
+            false => Some(detail.flags.iter().map(|flag| flag.label()).collect::<Vec<String>>().join(","))
+        },
+        (TemplateField::RttMs, Some((_, detail))) => detail.rtt.map(|rtt| format!("{:.3}", rtt.as_secs_f64() * 1000.0)),
+        (TemplateField::RttUs, Some((_, detail))) => detail.rtt.map(|rtt| rtt.as_micros().to_string()),
+        (TemplateField::HostCount, _) => Some(host_count.to_string()),
+        (TemplateField::PacketCount, _) => Some(response_summary.packet_count.to_string()),
+        (TemplateField::ArpCount, _) => Some(response_summary.arp_count.to_string()),
+        (TemplateField::DurationMs, _) => Some(response_summary.duration_ms.to_string()),
+        (TemplateField::RequestCount, _) => Some(response_summary.request_count.to_string()),
+        (TemplateField::RequestedRate, _) => response_summary.requested_rate.map(|rate| format!("{:.1}", rate)),
+        (TemplateField::AchievedRate, _) => response_summary.achieved_rate.map(|rate| format!("{:.1}", rate)),
+        (TemplateField::RttP99Us, _) => response_summary.rtt_p99_us.map(|rtt_us| rtt_us.to_string()),
+        (TemplateField::KernelPacketCount, _) => kernel_statistics.map(|statistics| statistics.packet_count.to_string()),
+        (TemplateField::KernelDropCount, _) => kernel_statistics.map(|statistics| statistics.drop_count.to_string()),
+        (TemplateField::StartedAt, _) => scan_metadata.map(|metadata| metadata.started_at.clone()),
+        (TemplateField::FinishedAt, _) => scan_metadata.map(|metadata| metadata.finished_at.clone()),
+        (TemplateField::Scanner, _) => scan_metadata.and_then(|metadata| metadata.hostname.clone()),
+        (TemplateField::Profile, _) => scan_metadata.map(|metadata| metadata.profile.clone()),
+        (TemplateField::Version, _) => Some(env!("CARGO_PKG_VERSION").to_string()),
+        (_, None) => None
+    }
+}
+
+/**
+ * Export the scan results with the user-defined templates, each rendered
+ * template being written on its own line.
+ */
+pub fn export_to_template(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>, output_template: &OutputTemplate) -> String {
+
+    utils::sort_target_details(&mut target_details);
+
+    let host_count = target_details.len();
+    let escape = output_template.escape;
+
+    let mut output = String::new();
+    if let Some(header) = &output_template.header {
+        output.push_str(&header.render(None, &response_summary, host_count, escape));
+        output.push('\n');
+    }
+    for (index, detail) in target_details.iter().enumerate() {
+        output.push_str(&output_template.host.render(Some((index, detail)), &response_summary, host_count, escape));
+        output.push('\n');
+    }
+    if let Some(footer) = &output_template.footer {
+        output.push_str(&footer.render(None, &response_summary, host_count, escape));
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use pnet_datalink::MacAddr;
+
+    fn build_target(last_byte: u8, hostname: Option<&str>) -> TargetDetails {
+
+        TargetDetails {
+            interface_name: Some("eth0".to_string()),
+            hostname: hostname.map(|hostname| hostname.to_string()),
+            rtt: Some(Duration::from_micros(1250)),
+            ..TargetDetails::for_test(Ipv4Addr::new(10, 0, 0, last_byte), MacAddr::new(0x00, 0x16, 0xf6, 0x00, 0x00, last_byte))
+        }
+    }
+
+    #[test]
+    fn should_parse_template_escapes() {
+
+        let template = Template::parse("{{{ip}}}\\t{vendor|none}\\\\", true).unwrap();
+
+        assert_eq!(template.parts, vec![
+            TemplatePart::Text("{".to_string()),
+            TemplatePart::Field(TemplateField::Ip, None),
+            TemplatePart::Text("}\t".to_string()),
+            TemplatePart::Field(TemplateField::Vendor, Some("none".to_string())),
+            TemplatePart::Text("\\".to_string())
+        ]);
+    }
+
+    #[test]
+    fn should_fail_incorrect_templates() {
+
+        assert_eq!(Template::parse("{address}", true), Err("Expected known template field, got {address}".to_string()));
+        assert_eq!(Template::parse("hosts: {ip}", false), Err("Expected summary fields in header and footer templates, got {ip}".to_string()));
+        assert!(Template::parse("{ip", true).is_err());
+        assert!(Template::parse("{hostname|{index}}", true).is_err());
+        assert!(Template::parse("ip}", true).is_err());
+        assert!(Template::parse("\\r", true).is_err());
+    }
+
+    #[test]
+    fn should_export_dhcp_hosts() {
+
+        let output_template = OutputTemplate {
+            header: Some(Template::parse("# {host_count} hosts", false).unwrap()),
+            host: Template::parse("dhcp-host={mac},{ip},{hostname|*}", true).unwrap(),
+            footer: None,
+            escape: TemplateEscape::Raw
+        };
+        let target_details = vec![build_target(7, None), build_target(3, Some("printer"))];
+
+        let output = export_to_template(ResponseSummary::default(), target_details, &output_template);
+
+        assert_eq!(output, "# 2 hosts\ndhcp-host=00:16:f6:00:00:03,10.0.0.3,printer\ndhcp-host=00:16:f6:00:00:07,10.0.0.7,*\n");
+    }
+
+    #[test]
+    fn should_escape_field_values() {
+
+        assert_eq!(TemplateEscape::Csv.apply("Vendor, Inc. \"A\""), "\"Vendor, Inc. \"\"A\"\"\"");
+        assert_eq!(TemplateEscape::Csv.apply("Vendor"), "Vendor");
+        assert_eq!(TemplateEscape::Json.apply("a\"b\n"), "a\\\"b\\n");
+        assert_eq!(TemplateEscape::Shell.apply("it's"), "'it'\\''s'");
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 /**
  * Parse a given time string into milliseconds. This can be used to convert a
  * string such as '20ms', '10s' or '1h' into adequate milliseconds. Without
@@ -45,6 +47,23 @@ pub fn parse_to_milliseconds(time_arg: &str) -> Result<u64, &str> {
     }
 }
 
+/**
+ * Parse a given time string into microseconds. This accepts the same formats
+ * as the millisecond parser, with an additional 'us' suffix for microseconds
+ * (for example '250us'). Without suffix, the value is parsed as milliseconds.
+ */
+pub fn parse_to_microseconds(time_arg: &str) -> Result<u64, &str> {
+
+    if let Some(microseconds_text) = time_arg.strip_suffix("us") {
+        return match microseconds_text.parse::<u64>() {
+            Ok(us_value) => Ok(us_value),
+            Err(_) => Err("invalid microseconds")
+        };
+    }
+
+    parse_to_milliseconds(time_arg).map(|ms_value| ms_value * 1000)
+}
+
 /**
  * Format milliseconds to a human-readable string. This will of course give an
  * approximation, but will be readable.
@@ -69,6 +88,76 @@ pub fn format_milliseconds(milliseconds: u128) -> String {
     format!("{}h", hours)
 }
 
+/**
+ * Format a system time as an RFC 3339 UTC timestamp with a second precision
+ * (for example '2023-11-14T22:13:20Z'). Dates are computed from the days since
+ * the UNIX epoch, with the proleptic Gregorian calendar.
+ */
+pub fn format_rfc3339(system_time: SystemTime) -> String {
+
+    let seconds = system_time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (days, day_seconds) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days (eras of 400 years, years starting in March)
+    let shifted_days = days + 719468;
+    let era = shifted_days / 146097;
+    let era_day = shifted_days - era * 146097;
+    let era_year = (era_day - era_day / 1460 + era_day / 36524 - era_day / 146096) / 365;
+    let year_day = era_day - (365 * era_year + era_year / 4 - era_year / 100);
+    let shifted_month = (5 * year_day + 2) / 153;
+    let day = year_day - (153 * shifted_month + 2) / 5 + 1;
+    let month = match shifted_month {
+        0..=9 => shifted_month + 3,
+        _ => shifted_month - 9
+    };
+    let year = era_year + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, day_seconds / 3600, (day_seconds % 3600) / 60, day_seconds % 60
+    )
+}
+
+/**
+ * Parse an RFC 3339 UTC timestamp with a second precision, as produced by the
+ * format function, into seconds since the UNIX epoch. Other offsets and
+ * fractional seconds are not supported.
+ */
+pub fn parse_rfc3339(timestamp: &str) -> Result<u64, &str> {
+
+    let is_valid_layout = timestamp.len() == 20 && timestamp.bytes().zip("0000-00-00T00:00:00Z".bytes()).all(|(byte, expected)| {
+        match expected {
+            b'0' => byte.is_ascii_digit(),
+            _ => byte == expected
+        }
+    });
+    if !is_valid_layout {
+        return Err("invalid timestamp");
+    }
+
+    let field = |start: usize, end: usize| timestamp[start..end].parse::<u64>().unwrap_or(0);
+    let (year, month, day) = (field(0, 4), field(5, 7), field(8, 10));
+    let (hours, minutes, seconds) = (field(11, 13), field(14, 16), field(17, 19));
+
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 59 {
+        return Err("invalid timestamp");
+    }
+
+    // Days from civil date (eras of 400 years, years starting in March)
+    let shifted_year = year - u64::from(month <= 2);
+    let era = shifted_year / 400;
+    let era_year = shifted_year - era * 400;
+    let shifted_month = match month {
+        3..=12 => month - 3,
+        _ => month + 9
+    };
+    let year_day = (153 * shifted_month + 2) / 5 + day - 1;
+    let era_day = era_year * 365 + era_year / 4 - era_year / 100 + year_day;
+    let days = (era * 146097 + era_day).checked_sub(719468).ok_or("invalid timestamp")?;
+
+    Ok(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -116,6 +205,20 @@ mod tests {
         assert_eq!(parse_to_milliseconds("3z"), Err("invalid milliseconds"));
     }
 
+    #[test]
+    fn should_parse_microseconds() {
+        
+        assert_eq!(parse_to_microseconds("250us"), Ok(250));
+        assert_eq!(parse_to_microseconds("3ms"), Ok(3000));
+        assert_eq!(parse_to_microseconds("2"), Ok(2000));
+    }
+
+    #[test]
+    fn should_deny_invalid_microseconds() {
+        
+        assert_eq!(parse_to_microseconds("2.5us"), Err("invalid microseconds"));
+    }
+
     // ---
 
     #[test]
@@ -142,4 +245,27 @@ mod tests {
         assert_eq!(format_milliseconds(4_200_000), "1h".to_string());
     }
 
+    #[test]
+    fn should_format_rfc3339_timestamps() {
+
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(UNIX_EPOCH + std::time::Duration::from_secs(951_868_799)), "2000-02-29T23:59:59Z");
+        assert_eq!(format_rfc3339(UNIX_EPOCH + std::time::Duration::from_secs(1_704_067_200)), "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn should_parse_rfc3339_timestamps() {
+
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse_rfc3339("2000-02-29T23:59:59Z"), Ok(951_868_799));
+        assert_eq!(parse_rfc3339("2024-01-01T00:00:00Z"), Ok(1_704_067_200));
+    }
+
+    #[test]
+    fn should_deny_invalid_rfc3339_timestamps() {
+
+        assert_eq!(parse_rfc3339("2024-01-01T00:00:00+02:00"), Err("invalid timestamp"));
+        assert_eq!(parse_rfc3339("2024-13-01T00:00:00Z"), Err("invalid timestamp"));
+        assert_eq!(parse_rfc3339("2024-01-01 00:00:00Z"), Err("invalid timestamp"));
+    }
 }
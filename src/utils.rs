@@ -1,13 +1,22 @@
+use std::env;
+use std::fs;
 use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::sync::Arc;
 
-use pnet_datalink::NetworkInterface;
-use ipnetwork::{IpNetwork, NetworkSize};
+use pnet_datalink::{MacAddr, NetworkInterface};
+use ipnetwork::IpNetwork;
 use serde::Serialize;
-use ansi_term::Color::{Green, Red};
+use ansi_term::Color;
+use ansi_term::Color::{Green, Red, Yellow};
 
-use crate::network::{ResponseSummary, TargetDetails};
-use crate::args::ScanOptions;
+use crate::analysis;
+use crate::network;
+use crate::metadata::ScanMetadata;
+use crate::network::{ResponseSummary, TargetDetails, TargetRange};
+use crate::args::{self, ScanOptions, SortKey, TableColumn};
+use crate::store::{HostSighting, StoredScan};
+use crate::time;
 
 /**
  * Prints on stdout a list of all available network interfaces with some
@@ -19,18 +28,20 @@ pub fn show_interfaces(interfaces: &[NetworkInterface]) {
     let mut interface_count = 0;
     let mut ready_count = 0;
 
+    let is_colored = args::is_color_enabled(false);
+
     println!();
     for interface in interfaces.iter() {
 
         let up_text = match interface.is_up() {
-            true => format!("{} UP", Green.paint("✔")),
-            false => format!("{} DOWN", Red.paint("✖"))
+            true => format!("{} UP", paint(Green, "✔", is_colored)),
+            false => format!("{} DOWN", paint(Red, "✖", is_colored))
         };
         let mac_text = match interface.mac {
             Some(mac_address) => format!("{}", mac_address),
             None => "No MAC address".to_string()
         };
-        let first_ip = match interface.ips.get(0) {
+        let first_ip = match interface.ips.first() {
             Some(ip_address) => format!("{}", ip_address),
             None => "".to_string()
         };
@@ -51,6 +62,42 @@ pub fn show_interfaces(interfaces: &[NetworkInterface]) {
     println!();
 }
 
+/**
+ * Prints on stdout the past scans saved in a results store, the latest scan
+ * being displayed first.
+ */
+pub fn show_stored_scans(stored_scans: &[StoredScan]) {
+
+    println!();
+    println!("{: <6} {: <22} {: <20} {: >10} {: >6}", "Scan", "Started at", "Interfaces", "Duration", "Hosts");
+    for scan in stored_scans.iter() {
+        println!("{: <6} {: <22} {: <20} {: >8}ms {: >6}", scan.id, scan.started_at, scan.interfaces, scan.duration_ms, scan.host_count);
+    }
+    println!();
+    println!("Found {} stored scans", stored_scans.len());
+    println!();
+}
+
+/**
+ * Prints on stdout when each IPv4/MAC pair of a host was first and last seen
+ * across the stored scans.
+ */
+pub fn show_host_sightings(host_sightings: &[HostSighting]) {
+
+    println!();
+    if host_sightings.is_empty() {
+        println!("This host has not been seen in any stored scan");
+        println!();
+        return;
+    }
+
+    println!("{: <15} {: <17} {: <22} {: <22} {: >6}", "IPv4", "MAC", "First seen", "Last seen", "Scans");
+    for sighting in host_sightings.iter() {
+        println!("{: <15} {: <17} {: <22} {: <22} {: >6}", sighting.ipv4, sighting.mac, sighting.first_seen, sighting.last_seen, sighting.scan_count);
+    }
+    println!();
+}
+
 pub fn print_ascii_packet() {
 
     println!();
@@ -82,150 +129,391 @@ pub fn print_ascii_packet() {
  */
 pub fn select_default_interface(interfaces: &[NetworkInterface]) -> Option<NetworkInterface> {
 
-    let default_interface = interfaces.iter().find(|interface| {
-
-        if interface.mac.is_none() {
-            return false;
-        }
+    interfaces.iter().find(|interface| is_eligible_interface(interface)).cloned()
+}
 
-        if interface.ips.is_empty() || !interface.is_up() || interface.is_loopback() {
-            return false;
-        }
+/**
+ * An interface is eligible for ARP scans if it is up, has a MAC address and
+ * at least one IPv4 address. Loopback interfaces are never eligible.
+ */
+pub fn is_eligible_interface(interface: &NetworkInterface) -> bool {
 
-        let potential_ipv4 = interface.ips.iter().find(|ip| ip.is_ipv4());
-        if potential_ipv4.is_none() {
-            return false;
-        }
+    if interface.mac.is_none() {
+        return false;
+    }
 
-        true
-    });
+    if interface.ips.is_empty() || !interface.is_up() || interface.is_loopback() {
+        return false;
+    }
 
-    default_interface.cloned()
+    interface.ips.iter().any(|ip| ip.is_ipv4())
 }
 
 /**
  * Display scan settings before launching an ARP scan. This includes network
  * details (IP range, interface, ...) and timing informations.
  */
-pub fn display_prescan_details(ip_networks: &[&IpNetwork], selected_interface: &NetworkInterface, scan_options: Arc<ScanOptions>) {
-    
-    let mut network_list = ip_networks.iter().take(5).map(|network| network.to_string()).collect::<Vec<String>>().join(", ");
-    if ip_networks.len() > 5 {
-        let more_text = format!(" ({} more)", ip_networks.len()-5);
-        network_list.push_str(&more_text);
-    }
+pub fn display_prescan_details(interface_configurations: &[(&NetworkInterface, Vec<&IpNetwork>)], scan_options: Arc<ScanOptions>) {
 
     println!();
-    println!("Selected interface {} with IP {}", selected_interface.name, network_list);
+    for (selected_interface, ip_networks) in interface_configurations.iter() {
+
+        let mut network_list = ip_networks.iter().take(5).map(|network| network.to_string()).collect::<Vec<String>>().join(", ");
+        if ip_networks.len() > 5 {
+            let more_text = format!(" ({} more)", ip_networks.len()-5);
+            network_list.push_str(&more_text);
+        }
+        println!("Selected interface {} with IP {}", selected_interface.name, network_list);
+    }
+    if interface_configurations.len() > 1 {
+        println!("The {} interfaces will be scanned in parallel with a shared rate limit", interface_configurations.len());
+    }
     if let Some(forced_source_ipv4) = scan_options.source_ipv4 {
         println!("The ARP source IPv4 will be forced to {}", forced_source_ipv4);
     }
     if let Some(forced_destination_mac) = scan_options.destination_mac {
         println!("The ARP destination MAC will be forced to {}", forced_destination_mac);
     }
+    if !scan_options.excluded_networks.is_empty() {
+        println!("The scan will skip {} excluded network ranges", scan_options.excluded_networks.len());
+    }
+    if let Some(random_seed) = scan_options.random_seed {
+        println!("The targets will be randomized with seed {} (use --seed to reproduce)", random_seed);
+    }
+    if scan_options.promisc_check {
+        println!("The ARP requests will also be sent to {} bogus MAC addresses", network::PROMISC_PROBES.len());
+    }
+    if scan_options.vlan_tags.len() > 1 {
+        println!("The ARP requests will be sent on {} VLANs", scan_options.vlan_tags.len());
+    }
 }
 
 /**
- * Computes multiple IPv4 networks total size, IPv6 network are not being
- * supported by this function. 
+ * Computes the total number of IPv4 targets in all target ranges. Excluded
+ * addresses are already removed from these ranges.
  */
-pub fn compute_network_size(ip_networks: &[&IpNetwork]) -> u128 {
+pub fn compute_network_size(target_ranges: &[TargetRange]) -> u128 {
 
-    ip_networks.iter().fold(0u128, |total_size, ip_network| {
+    target_ranges.iter().map(|range| u128::from(range.size())).sum()
+}
 
-        let network_size: u128 = match ip_network.size() {
-            NetworkSize::V4(ipv4_network_size) => ipv4_network_size.into(),
-            NetworkSize::V6(_) => {
-                eprintln!("IPv6 networks are not supported by the ARP protocol");
-                process::exit(1);
-            }
-        };
-        total_size + network_size
-    })
+/**
+ * Sort scan results by interface, VLAN and IPv4 address. Results of a single
+ * interface without VLAN are then simply sorted by IPv4 address.
+ */
+pub fn sort_target_details(target_details: &mut [TargetDetails]) {
+
+    target_details.sort_by(|first, second| {
+        (&first.interface_name, first.vlan_id, first.ipv4).cmp(&(&second.interface_name, second.vlan_id, second.ipv4))
+    });
+}
+
+/**
+ * Format all analysis flags of a target as a single text, this text will be
+ * used in the plain output and all exports.
+ */
+fn format_target_flags(detail: &TargetDetails) -> String {
+
+    detail.flags.iter().map(|flag| flag.label()).collect::<Vec<String>>().join(",")
+}
+
+/**
+ * Sort the results of the plain output with the requested key, results with
+ * the same key (or without value) keep the default interface, VLAN and IPv4
+ * order. Results without value are always placed last.
+ */
+fn sort_plain_results(target_details: &mut [TargetDetails], options: &ScanOptions) {
+
+    sort_target_details(target_details);
+
+    match options.sort_key {
+        SortKey::Ip => {},
+        SortKey::Mac => target_details.sort_by_key(|detail| detail.mac.to_string()),
+        SortKey::Vendor => target_details.sort_by_key(|detail| (detail.vendor.is_none(), detail.vendor.clone())),
+        SortKey::Hostname => target_details.sort_by_key(|detail| (detail.hostname.is_none(), detail.hostname.clone())),
+        SortKey::Rtt => target_details.sort_by_key(|detail| (detail.rtt.is_none(), detail.rtt)),
+        SortKey::Discovery => target_details.sort_by_key(|detail| (detail.discovered_at.is_none(), detail.discovered_at))
+    };
+
+    if options.reverse_sort {
+        target_details.reverse();
+    }
+}
+
+/**
+ * List the columns of the plain output table. Without requested columns, the
+ * interface and VLAN columns are only given for multi-interface and VLAN
+ * scans, and the notes column only if at least one host has been flagged
+ * during the result analysis (shared MAC, ...).
+ */
+fn list_table_columns(target_details: &[TargetDetails], options: &ScanOptions) -> Vec<TableColumn> {
+
+    if let Some(table_columns) = &options.table_columns {
+        return table_columns.clone();
+    }
+
+    let mut columns: Vec<TableColumn> = vec![];
+    if options.is_multi_interface() {
+        columns.push(TableColumn::Interface);
+    }
+    if options.has_vlan() {
+        columns.push(TableColumn::Vlan);
+    }
+    columns.extend([TableColumn::Ip, TableColumn::Mac, TableColumn::Hostname, TableColumn::Vendor]);
+    if target_details.iter().any(|detail| !detail.flags.is_empty()) {
+        columns.push(TableColumn::Notes);
+    }
+    columns
+}
+
+fn format_column_header(column: TableColumn) -> &'static str {
+
+    match column {
+        TableColumn::Interface => "Interface",
+        TableColumn::Vlan => "VLAN",
+        TableColumn::Ip => "IPv4",
+        TableColumn::Mac => "MAC",
+        TableColumn::EthernetMac => "Ethernet MAC",
+        TableColumn::Hostname => "Hostname",
+        TableColumn::Vendor => "Vendor",
+        TableColumn::Rtt => "RTT",
+        TableColumn::Notes => "Notes"
+    }
+}
+
+fn compute_column_width(column: TableColumn) -> usize {
+
+    match column {
+        TableColumn::Interface => 9,
+        TableColumn::Vlan => 4,
+        TableColumn::Ip => 15,
+        TableColumn::Mac | TableColumn::EthernetMac => 17,
+        TableColumn::Hostname | TableColumn::Vendor => 15,
+        TableColumn::Rtt => 9,
+        TableColumn::Notes => 5
+    }
+}
+
+fn format_table_cell(column: TableColumn, detail: &TargetDetails, options: &ScanOptions) -> String {
+
+    match column {
+        TableColumn::Interface => detail.interface_name.clone().unwrap_or_else(|| "?".to_string()),
+        TableColumn::Vlan => detail.vlan_id.map(|vlan_id| vlan_id.to_string()).unwrap_or_else(|| "?".to_string()),
+        TableColumn::Ip => detail.ipv4.to_string(),
+        TableColumn::Mac => detail.mac.to_string(),
+        TableColumn::EthernetMac => detail.ethernet_mac.to_string(),
+        TableColumn::Hostname => match &detail.hostname {
+            Some(hostname) => hostname.clone(),
+            None if !options.resolve_hostname => "(disabled)".to_string(),
+            None => "".to_string()
+        },
+        TableColumn::Vendor => detail.vendor.clone().unwrap_or_default(),
+        TableColumn::Rtt => detail.rtt.map(|rtt| format!("{:.3}ms", rtt.as_secs_f64() * 1000.0)).unwrap_or_default(),
+        TableColumn::Notes => format_target_flags(detail)
+    }
+}
+
+fn format_table_row(cells: &[String], widths: &[usize]) -> String {
+
+    let mut row = String::new();
+    for (cell, width) in cells.iter().zip(widths.iter()) {
+        row.push_str(&format!("| {: <width$} ", cell, width=width));
+    }
+    row.push_str("|\n");
+    row
 }
 
 /**
  * Display the scan results on stdout with a table. The 'final_result' vector
  * contains all items that will be displayed.
  */
-pub fn display_scan_results(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>, options: &ScanOptions) {
+pub fn display_scan_results(response_summary: ResponseSummary, target_details: Vec<TargetDetails>, options: &ScanOptions) {
 
-    target_details.sort_by_key(|item| item.ipv4);
+    print!("{}", format_scan_results(response_summary, target_details, options, options.is_colored));
+}
 
-    let mut hostname_len = 15;
-    let mut vendor_len = 15;
-    for detail in target_details.iter() {
+/**
+ * Format the scan results as a text table, followed by the analysis warnings
+ * and scan statistics. Colors are only used for terminal outputs.
+ */
+pub fn format_scan_results(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>, options: &ScanOptions, is_colored: bool) -> String {
 
-        if let Some(hostname) = &detail.hostname {
-            if hostname.len() > hostname_len {
-                hostname_len = hostname.len();
-            }
-        }
+    sort_plain_results(&mut target_details, options);
 
-        if let Some(vendor) = &detail.vendor {
-            if vendor.len() > vendor_len {
-                vendor_len = vendor.len();
-            }
+    let mut output = String::new();
+
+    let columns = list_table_columns(&target_details, options);
+    let headers: Vec<String> = columns.iter().map(|column| format_column_header(*column).to_string()).collect();
+    let rows: Vec<Vec<String>> = target_details.iter()
+        .map(|detail| columns.iter().map(|column| format_table_cell(*column, detail, options)).collect())
+        .collect();
+
+    // Each column is as large as its largest cell, with a minimal width to
+    // keep tables of successive scans aligned.
+    let widths: Vec<usize> = columns.iter().enumerate().map(|(column_index, column)| {
+        rows.iter()
+            .map(|row| row[column_index].chars().count())
+            .fold(compute_column_width(*column).max(headers[column_index].len()), usize::max)
+    }).collect();
+
+    if !target_details.is_empty() {
+        output.push('\n');
+        if options.show_header {
+            output.push_str(&format_table_row(&headers, &widths));
+            let separators: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+            output.push_str(&format!("|{}|\n", separators.join("|")));
         }
     }
 
-    if !target_details.is_empty() {
-        println!();
-        println!("| IPv4            | MAC               | {: <h_max$} | {: <v_max$} |", "Hostname", "Vendor", h_max=hostname_len, v_max=vendor_len);
-        println!("|-----------------|-------------------|-{:-<h_max$}-|-{:-<v_max$}-|", "", "", h_max=hostname_len, v_max=vendor_len);
+    for row in rows.iter() {
+        output.push_str(&format_table_row(row, &widths));
     }
 
-    for detail in target_details.iter() {
+    let shared_macs = analysis::list_shared_macs(&target_details);
+    if !shared_macs.is_empty() {
+        output.push('\n');
+    }
+    for (interface_name, _, shared_mac, ip_count) in shared_macs.iter() {
+        let interface_text = interface_name.as_ref().map(|interface_name| format!(" on {}", interface_name)).unwrap_or_default();
+        output.push_str(&format!("{} MAC {}{} answers for {} IPv4 addresses (probable proxy-ARP, router or load-balancer)\n", paint(Yellow, "[warn]", is_colored), shared_mac, interface_text, ip_count));
+    }
 
-        let hostname: &str = match &detail.hostname {
-            Some(hostname) => hostname,
-            None if !options.resolve_hostname => "(disabled)",
-            None => ""
-        };
-        let vendor: &str = match &detail.vendor {
-            Some(vendor) => vendor,
-            None => ""
+    let mac_mismatches = analysis::list_mac_mismatches(&target_details);
+    if !mac_mismatches.is_empty() {
+        output.push('\n');
+    }
+    for (ipv4, sender_mac, ethernet_mac) in mac_mismatches.iter() {
+        output.push_str(&format!("{} Host {} answered with ARP sender {} from Ethernet source {} (probable spoofing or bridging device)\n", paint(Yellow, "[warn]", is_colored), ipv4, sender_mac, ethernet_mac));
+    }
+
+    let promiscuous_hosts = analysis::list_promiscuous_hosts(&target_details);
+    if options.promisc_check {
+        output.push('\n');
+        match promiscuous_hosts.len() {
+            0 => output.push_str("No host answered the promiscuous probes\n"),
+            1 => output.push_str("1 host answered the promiscuous probes\n"),
+            _ => output.push_str(&format!("{} hosts answered the promiscuous probes\n", promiscuous_hosts.len()))
         };
-        println!("| {: <15} | {: <18} | {: <h_max$} | {: <v_max$} |", detail.ipv4, detail.mac, hostname, vendor, h_max=hostname_len, v_max=vendor_len);
+    }
+    for (ipv4, mac, probe_names) in promiscuous_hosts.iter() {
+        output.push_str(&format!("{} Host {} ({}) answered bogus probes {} (network card probably in promiscuous mode)\n", paint(Yellow, "[warn]", is_colored), ipv4, mac, probe_names.join(", ")));
     }
 
-    println!();
-    print!("ARP scan finished, ");
+    if let Some(subnet_discovery) = &response_summary.subnet_discovery {
+        output.push('\n');
+        output.push_str(&format!("{} occupied subnets found in {} sampled subnets\n", subnet_discovery.subnets.len(), subnet_discovery.sampled_count));
+        let interface_len = subnet_discovery.subnets.iter()
+            .filter_map(|occupancy| occupancy.interface_name.as_ref().map(|interface_name| interface_name.len()))
+            .fold(compute_column_width(TableColumn::Interface), usize::max);
+        for occupancy in subnet_discovery.subnets.iter() {
+
+            let interface_text = match options.is_multi_interface() {
+                true => format!("{: <i_max$} ", occupancy.interface_name.as_deref().unwrap_or("?"), i_max=interface_len),
+                false => "".to_string()
+            };
+            let vlan_text = match occupancy.vlan_id {
+                Some(vlan_id) => format!("VLAN {: <4} ", vlan_id),
+                None => "".to_string()
+            };
+            let host_text = match occupancy.host_count {
+                1 => "1 host".to_string(),
+                host_count => format!("{} hosts", host_count)
+            };
+            output.push_str(&format!("  {}{}{: <18} {}\n", interface_text, vlan_text, occupancy.subnet.to_string(), host_text));
+        }
+    }
+
+    output.push('\n');
+    output.push_str("ARP scan finished, ");
     let target_count = target_details.len();
     match target_count {
-        0 => print!("{}", Red.paint("no hosts found")),
-        1 => print!("1 host found"),
-        _ => print!("{} hosts found", target_count)
+        0 => output.push_str(&paint(Red, "no hosts found", is_colored)),
+        1 => output.push_str("1 host found"),
+        _ => output.push_str(&format!("{} hosts found", target_count))
     }
     let seconds_duration = (response_summary.duration_ms as f32) / (1000_f32);
-    println!(" in {:.3} seconds", seconds_duration);
+    output.push_str(&format!(" in {:.3} seconds\n", seconds_duration));
 
     match response_summary.packet_count {
-        0 => print!("No packets received, "),
-        1 => print!("1 packet received, "),
-        _ => print!("{} packets received, ", response_summary.packet_count)
+        0 => output.push_str("No packets received, "),
+        1 => output.push_str("1 packet received, "),
+        _ => output.push_str(&format!("{} packets received, ", response_summary.packet_count))
     };
     match response_summary.arp_count {
-        0 => println!("no ARP packets filtered"),
-        1 => println!("1 ARP packet filtered"),
-        _ => println!("{} ARP packets filtered", response_summary.arp_count)
+        0 => output.push_str("no ARP packets filtered\n"),
+        1 => output.push_str("1 ARP packet filtered\n"),
+        _ => output.push_str(&format!("{} ARP packets filtered\n", response_summary.arp_count))
     };
-    println!();
+    if let Some(kernel_statistics) = response_summary.kernel_statistics {
+        let drop_text = match kernel_statistics.drop_count {
+            0 => paint(Green, "no frames dropped", is_colored),
+            drop_count => paint(Red, &format!("{} frames dropped", drop_count), is_colored)
+        };
+        output.push_str(&format!("Kernel filter accepted {} frames, {}\n", kernel_statistics.packet_count, drop_text));
+    }
+
+    let requested_rate_text = match response_summary.requested_rate {
+        Some(requested_rate) => format!("{:.0} packets/s requested", requested_rate),
+        None => "no rate limit".to_string()
+    };
+    match response_summary.achieved_rate {
+        Some(achieved_rate) => output.push_str(&format!("{} ARP requests sent at {:.1} packets/s ({})\n", response_summary.request_count, achieved_rate, requested_rate_text)),
+        None => output.push_str(&format!("{} ARP requests sent ({})\n", response_summary.request_count, requested_rate_text))
+    };
+    if let Some(rtt_p99_us) = response_summary.rtt_p99_us {
+        output.push_str(&format!("Responses received within {:.3}ms (99th percentile)\n", rtt_p99_us as f64 / 1000.0));
+    }
+    output.push('\n');
+
+    output
+}
+
+/**
+ * Paint a text with a terminal color, colors are disabled for file outputs.
+ */
+fn paint(color: Color, text: &str, is_colored: bool) -> String {
+
+    match is_colored {
+        true => color.paint(text).to_string(),
+        false => text.to_string()
+    }
 }
 
 #[derive(Serialize)]
 struct SerializableResultItem {
     ipv4: String,
     mac: String,
+    ethernet_mac: String,
+    vlan: Option<u16>,
+    interface: Option<String>,
     hostname: String,
-    vendor: String
+    vendor: String,
+    flags: String
+}
+
+#[derive(Serialize)]
+struct SerializableSubnetItem {
+    subnet: String,
+    vlan: Option<u16>,
+    interface: Option<String>,
+    host_count: usize
 }
 
 #[derive(Serialize)]
 struct SerializableGlobalResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<ScanMetadata>,
     packet_count: usize,
     arp_count: usize,
     duration_ms: u128,
+    request_count: u64,
+    requested_rate: Option<f64>,
+    achieved_rate: Option<f64>,
+    rtt_p99_us: Option<u128>,
+    kernel_packet_count: Option<u64>,
+    kernel_drop_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subnets: Option<Vec<SerializableSubnetItem>>,
     results: Vec<SerializableResultItem>
 }
 
@@ -251,16 +539,38 @@ fn get_serializable_result(response_summary: ResponseSummary, target_details: Ve
             SerializableResultItem {
                 ipv4: format!("{}", detail.ipv4),
                 mac: format!("{}", detail.mac),
+                ethernet_mac: format!("{}", detail.ethernet_mac),
+                vlan: detail.vlan_id,
+                interface: detail.interface_name.clone(),
                 hostname,
-                vendor
+                vendor,
+                flags: format_target_flags(&detail)
             }
         })
         .collect();
 
+    let exportable_subnets = response_summary.subnet_discovery.map(|subnet_discovery| {
+
+        subnet_discovery.subnets.into_iter().map(|occupancy| SerializableSubnetItem {
+            subnet: occupancy.subnet.to_string(),
+            vlan: occupancy.vlan_id,
+            interface: occupancy.interface_name,
+            host_count: occupancy.host_count
+        }).collect()
+    });
+
     SerializableGlobalResult {
+        metadata: response_summary.scan_metadata,
         packet_count: response_summary.packet_count,
         arp_count: response_summary.arp_count,
         duration_ms: response_summary.duration_ms,
+        request_count: response_summary.request_count,
+        requested_rate: response_summary.requested_rate,
+        achieved_rate: response_summary.achieved_rate,
+        rtt_p99_us: response_summary.rtt_p99_us,
+        kernel_packet_count: response_summary.kernel_statistics.map(|statistics| statistics.packet_count),
+        kernel_drop_count: response_summary.kernel_statistics.map(|statistics| statistics.drop_count),
+        subnets: exportable_subnets,
         results: exportable_results
     }
 }
@@ -271,7 +581,7 @@ fn get_serializable_result(response_summary: ResponseSummary, target_details: Ve
  */
 pub fn export_to_json(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>) -> String {
 
-    target_details.sort_by_key(|item| item.ipv4);
+    sort_target_details(&mut target_details);
 
     let global_result = get_serializable_result(response_summary, target_details);
 
@@ -287,7 +597,7 @@ pub fn export_to_json(response_summary: ResponseSummary, mut target_details: Vec
  */
 pub fn export_to_yaml(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>) -> String {
 
-    target_details.sort_by_key(|item| item.ipv4);
+    sort_target_details(&mut target_details);
 
     let global_result = get_serializable_result(response_summary, target_details);
 
@@ -299,11 +609,17 @@ pub fn export_to_yaml(response_summary: ResponseSummary, mut target_details: Vec
 
 /**
  * Export the scan results as a CSV string with response details (timings, ...)
- * and ARP results from the local network.
+ * and ARP results from the local network. The scan metadata is given in
+ * comment lines before the CSV header.
  */
 pub fn export_to_csv(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>) -> String {
 
-    target_details.sort_by_key(|item| item.ipv4);
+    sort_target_details(&mut target_details);
+
+    let metadata_comments = match &response_summary.scan_metadata {
+        Some(scan_metadata) => scan_metadata.format_csv_comments(),
+        None => String::new()
+    };
 
     let global_result = get_serializable_result(response_summary, target_details);
 
@@ -324,8 +640,323 @@ pub fn export_to_csv(response_summary: ResponseSummary, mut target_details: Vec<
         eprintln!("Could not convert final CSV result ({})", err);
         process::exit(1);
     });
-    String::from_utf8(convert_writer).unwrap_or_else(|err| {
+    let csv_text = String::from_utf8(convert_writer).unwrap_or_else(|err| {
         eprintln!("Could not convert final CSV result to text ({})", err);
         process::exit(1);
+    });
+    metadata_comments + &csv_text
+}
+
+/**
+ * Export the scan results as a nmap-compatible XML document, which can be
+ * imported in tools reading nmap reports. Each host is reported as 'up' with
+ * an 'arp-response' reason, with its IPv4 and MAC addresses.
+ */
+pub fn export_to_xml(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>) -> String {
+
+    sort_target_details(&mut target_details);
+
+    // Scan times come from the scan metadata, results without metadata are
+    // approximated as a scan ending now.
+    let metadata_times = response_summary.scan_metadata.as_ref().and_then(|scan_metadata| {
+        let start_time = time::parse_rfc3339(&scan_metadata.started_at).ok()?;
+        let end_time = time::parse_rfc3339(&scan_metadata.finished_at).ok()?;
+        Some((start_time, end_time))
+    });
+    let (start_time, end_time) = metadata_times.unwrap_or_else(|| {
+        let end_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+        (end_time.saturating_sub((response_summary.duration_ms / 1000) as u64), end_time)
+    });
+    let command_line = env::args().collect::<Vec<String>>().join(" ");
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<!DOCTYPE nmaprun>\n");
+    xml.push_str(&format!(
+        "<nmaprun scanner=\"arp-scan\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"1.05\">\n",
+        escape_xml(&command_line), start_time, env!("CARGO_PKG_VERSION")
+    ));
+    xml.push_str("<verbose level=\"0\"/>\n<debugging level=\"0\"/>\n");
+
+    for detail in target_details.iter() {
+
+        xml.push_str(&format!("<host starttime=\"{}\" endtime=\"{}\">", start_time, end_time));
+        xml.push_str("<status state=\"up\" reason=\"arp-response\" reason_ttl=\"0\"/>\n");
+        xml.push_str(&format!("<address addr=\"{}\" addrtype=\"ipv4\"/>\n", detail.ipv4));
+
+        let mac_text = detail.mac.to_string().to_uppercase();
+        match &detail.vendor {
+            Some(vendor) => xml.push_str(&format!("<address addr=\"{}\" addrtype=\"mac\" vendor=\"{}\"/>\n", mac_text, escape_xml(vendor))),
+            None => xml.push_str(&format!("<address addr=\"{}\" addrtype=\"mac\"/>\n", mac_text))
+        };
+
+        match &detail.hostname {
+            Some(hostname) => xml.push_str(&format!("<hostnames>\n<hostname name=\"{}\" type=\"PTR\"/>\n</hostnames>\n", escape_xml(hostname))),
+            None => xml.push_str("<hostnames>\n</hostnames>\n")
+        };
+        xml.push_str("</host>\n");
+    }
+
+    let elapsed_seconds = (response_summary.duration_ms as f64) / 1000.0;
+    let host_count = target_details.len();
+    xml.push_str("<runstats>");
+    xml.push_str(&format!(
+        "<finished time=\"{}\" elapsed=\"{:.2}\" summary=\"ARP scan done; {} hosts up, {} ARP requests sent, {} packets received\" exit=\"success\"/>",
+        end_time, elapsed_seconds, host_count, response_summary.request_count, response_summary.packet_count
+    ));
+    xml.push_str(&format!("<hosts up=\"{}\" down=\"0\" total=\"{}\"/>\n", host_count, host_count));
+    xml.push_str("</runstats>\n");
+    xml.push_str("</nmaprun>\n");
+
+    xml
+}
+
+/**
+ * Export the scan results with the output of the C arp-scan tool, so that
+ * existing parsers keep working: tab-separated host lines in response order,
+ * a '(DUP: n)' marker on each other MAC answering for the same IPv4, and the
+ * usual header and footer lines.
+ */
+pub fn export_to_classic(response_summary: ResponseSummary, mut target_details: Vec<TargetDetails>, target_count: u128, options: &ScanOptions) -> String {
+
+    sort_target_details(&mut target_details);
+    target_details.sort_by_key(|detail| (detail.discovered_at.is_none(), detail.discovered_at));
+
+    let mut classic = String::new();
+    if !options.classic_plain {
+
+        let interfaces = response_summary.scan_metadata.as_ref().map(|metadata| metadata.interfaces.as_slice()).unwrap_or_default();
+        for interface in interfaces.iter() {
+            let source_ipv4 = interface.lanes.first().map(|lane| lane.source_ipv4.as_str()).unwrap_or("");
+            classic.push_str(&format!("Interface: {}, type: EN10MB, MAC: {}, IPv4: {}\n", interface.name, interface.mac, source_ipv4));
+        }
+        classic.push_str(&format!("Starting arp-scan {} with {} hosts (https://github.com/saluki/arp-scan-rs)\n", env!("CARGO_PKG_VERSION"), target_count));
+    }
+
+    for detail in target_details.iter() {
+
+        let response_macs = match detail.response_macs.is_empty() {
+            true => vec![detail.mac],
+            false => detail.response_macs.clone()
+        };
+
+        for (index, mac) in response_macs.iter().enumerate() {
+
+            if index > 0 && options.ignore_duplicates {
+                break;
+            }
+
+            let mut line = format!("{}\t{}", detail.ipv4, mac);
+            if !options.classic_quiet {
+                line.push_str(&format!("\t{}", format_classic_vendor(detail, mac)));
+            }
+            if index > 0 {
+                line.push_str(&format!(" (DUP: {})", index + 1));
+            }
+            classic.push_str(&line);
+            classic.push('\n');
+        }
+    }
+
+    if !options.classic_plain {
+
+        let (received_count, dropped_count) = match &response_summary.kernel_statistics {
+            Some(kernel_statistics) => (kernel_statistics.packet_count, kernel_statistics.drop_count),
+            None => (response_summary.packet_count as u64, 0)
+        };
+        let elapsed_seconds = (response_summary.duration_ms as f64) / 1000.0;
+        let host_rate = match elapsed_seconds > 0.0 {
+            true => target_count as f64 / elapsed_seconds,
+            false => 0.0
+        };
+
+        classic.push('\n');
+        classic.push_str(&format!("{} packets received by filter, {} packets dropped by kernel\n", received_count, dropped_count));
+        classic.push_str(&format!(
+            "Ending arp-scan {}: {} hosts scanned in {:.3} seconds ({:.2} hosts/sec). {} responded\n",
+            env!("CARGO_PKG_VERSION"), target_count, elapsed_seconds, host_rate, target_details.len()
+        ));
+    }
+
+    classic
+}
+
+/**
+ * The vendor of a classic output line, unknown vendors are written like the
+ * C arp-scan does (with a note for locally administered addresses). Only the
+ * vendor of the last MAC is known, it is reused for MACs with the same OUI.
+ */
+fn format_classic_vendor(detail: &TargetDetails, mac: &MacAddr) -> String {
+
+    let has_same_oui = (mac.0, mac.1, mac.2) == (detail.mac.0, detail.mac.1, detail.mac.2);
+    match (&detail.vendor, has_same_oui) {
+        (Some(vendor), true) => vendor.clone(),
+        _ if mac.0 & 0x02 != 0 => "(Unknown: locally administered)".to_string(),
+        _ => "(Unknown)".to_string()
+    }
+}
+
+/**
+ * Escape a text for XML attributes and contents (vendor names or hostnames
+ * may contain special characters).
+ */
+fn escape_xml(text: &str) -> String {
+
+    text.chars().map(|character| match character {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&apos;".to_string(),
+        _ => character.to_string()
+    }).collect()
+}
+
+/**
+ * Write an output file atomically: the content is first written in a
+ * temporary file and then renamed, readers will never see a partial file.
+ */
+pub fn write_file_atomically(file_path: &str, content: &str) -> Result<(), String> {
+
+    let temporary_path = format!("{}.tmp", file_path);
+    fs::write(&temporary_path, content).map_err(|err| {
+        format!("Could not write output file {} - {}", temporary_path, err)
+    })?;
+    fs::rename(&temporary_path, file_path).map_err(|err| {
+        let _ = fs::remove_file(&temporary_path);
+        format!("Could not replace output file {} - {}", file_path, err)
     })
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use crate::args::OutputFormat;
+
+    #[test]
+    fn should_escape_xml_text() {
+
+        assert_eq!(escape_xml("Vendor <R&D> \"Inc.\""), "Vendor &lt;R&amp;D&gt; &quot;Inc.&quot;");
+    }
+
+    #[test]
+    fn should_export_nmap_xml() {
+
+        let target_details = vec![TargetDetails {
+            interface_name: Some("eth0".to_string()),
+            hostname: Some("router.home".to_string()),
+            vendor: Some("Vendor & Co".to_string()),
+            ..TargetDetails::for_test(Ipv4Addr::new(192, 168, 1, 1), MacAddr::new(0x00, 0x16, 0xf6, 0xaa, 0xbb, 0xcc))
+        }];
+        let response_summary = ResponseSummary {
+            packet_count: 3,
+            request_count: 256,
+            duration_ms: 2500,
+            ..ResponseSummary::default()
+        };
+
+        let xml = export_to_xml(response_summary, target_details);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n<nmaprun scanner=\"arp-scan\""));
+        assert!(xml.contains("<status state=\"up\" reason=\"arp-response\" reason_ttl=\"0\"/>"));
+        assert!(xml.contains("<address addr=\"192.168.1.1\" addrtype=\"ipv4\"/>"));
+        assert!(xml.contains("<address addr=\"00:16:F6:AA:BB:CC\" addrtype=\"mac\" vendor=\"Vendor &amp; Co\"/>"));
+        assert!(xml.contains("<hostname name=\"router.home\" type=\"PTR\"/>"));
+        assert!(xml.contains("elapsed=\"2.50\""));
+        assert!(xml.contains("<hosts up=\"1\" down=\"0\" total=\"1\"/>"));
+        assert!(xml.ends_with("</nmaprun>\n"));
+    }
+
+    #[test]
+    fn should_export_nmap_xml_scan_times() {
+
+        let options = ScanOptions::new(&crate::args::build_args().get_matches_from(vec!["arp-scan"]));
+        let scan_metadata = ScanMetadata {
+            finished_at: "2023-11-14T22:13:23Z".to_string(),
+            ..ScanMetadata::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000), &[], &options)
+        };
+        let response_summary = ResponseSummary {
+            duration_ms: 2500,
+            scan_metadata: Some(scan_metadata),
+            ..ResponseSummary::default()
+        };
+
+        let xml = export_to_xml(response_summary, vec![build_target(1, None, None)]);
+
+        assert!(xml.contains(" start=\"1700000000\" "));
+        assert!(xml.contains("<host starttime=\"1700000000\" endtime=\"1700000003\">"));
+        assert!(xml.contains("<finished time=\"1700000003\" elapsed=\"2.50\""));
+    }
+
+    fn build_target(last_byte: u8, vendor: Option<&str>, rtt_us: Option<u64>) -> TargetDetails {
+
+        TargetDetails {
+            interface_name: Some("eth0".to_string()),
+            vendor: vendor.map(|vendor| vendor.to_string()),
+            rtt: rtt_us.map(Duration::from_micros),
+            ..TargetDetails::for_test(Ipv4Addr::new(10, 0, 0, last_byte), MacAddr::new(0x00, 0x16, 0xf6, 0x00, 0x00, last_byte))
+        }
+    }
+
+    #[test]
+    fn should_format_selected_columns() {
+
+        let options = ScanOptions::new(&crate::args::build_args().get_matches_from(vec!["arp-scan", "--columns", "rtt,ip", "--sort", "rtt", "--no-header"]));
+        let target_details = vec![
+            build_target(1, None, Some(900)),
+            build_target(2, None, None),
+            build_target(3, None, Some(250))
+        ];
+
+        let output = format_scan_results(ResponseSummary::default(), target_details, &options, false);
+
+        assert!(output.starts_with("\n| 0.250ms   | 10.0.0.3        |\n| 0.900ms   | 10.0.0.1        |\n|           | 10.0.0.2        |\n"));
+    }
+
+    #[test]
+    fn should_sort_by_reversed_vendor() {
+
+        let options = ScanOptions::new(&crate::args::build_args().get_matches_from(vec!["arp-scan", "--sort", "vendor", "--reverse"]));
+        let mut target_details = vec![
+            build_target(1, Some("Cisco"), None),
+            build_target(2, Some("Apple"), None),
+            build_target(3, None, None)
+        ];
+
+        sort_plain_results(&mut target_details, &options);
+
+        let last_bytes: Vec<u8> = target_details.iter().map(|detail| detail.ipv4.octets()[3]).collect();
+        assert_eq!(last_bytes, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn should_export_classic_duplicates() {
+
+        let mut conflict_target = build_target(2, Some("Cisco"), None);
+        conflict_target.response_macs.push(MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x09));
+        let target_details = vec![build_target(1, Some("Apple"), None), conflict_target];
+        let response_summary = ResponseSummary {
+            packet_count: 3,
+            duration_ms: 2000,
+            ..ResponseSummary::default()
+        };
+
+        let options = ScanOptions::new(&crate::args::build_args().get_matches_from(vec!["arp-scan", "-o", "classic"]));
+        let classic = export_to_classic(response_summary.clone(), target_details.clone(), 256, &options);
+
+        assert!(classic.starts_with("Starting arp-scan "));
+        assert!(classic.contains("\n10.0.0.1\t00:16:f6:00:00:01\tApple\n10.0.0.2\t00:16:f6:00:00:02\tCisco\n"));
+        assert!(classic.contains("\n10.0.0.2\t02:00:00:00:00:09\t(Unknown: locally administered) (DUP: 2)\n"));
+        assert!(classic.contains("\n3 packets received by filter, 0 packets dropped by kernel\n"));
+        assert!(classic.ends_with(": 256 hosts scanned in 2.000 seconds (128.00 hosts/sec). 2 responded\n"));
+
+        let options = ScanOptions::new(&crate::args::build_args().get_matches_from(vec!["arp-scan", "--quiet", "--plain", "--ignoredups"]));
+        let classic = export_to_classic(response_summary, target_details, 256, &options);
+
+        assert_eq!(options.output, OutputFormat::Classic);
+        assert_eq!(classic, "10.0.0.1\t00:16:f6:00:00:01\n10.0.0.2\t00:16:f6:00:00:02\n");
+    }
+}